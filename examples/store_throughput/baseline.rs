@@ -0,0 +1,118 @@
+//! The message store as it was before sharding, one lock for the whole buffer
+//!
+//! Only the parts the benchmark exercises are kept: storing with host
+//! tracking, pattern mining, indexing and eviction, and reading the newest
+//! messages. Deduplication is off in the benchmark and left out.
+
+use light_gelf_collector_rs::fields::FieldCatalogue;
+use light_gelf_collector_rs::gelf::ReceiveMetadata;
+use light_gelf_collector_rs::hosts::{HostInfo, HostTracker, HostsConfig};
+use light_gelf_collector_rs::index::InvertedIndex;
+use light_gelf_collector_rs::patterns::PatternMiner;
+use light_gelf_collector_rs::storage::{DefaultBroadcaster, MessageBroadcaster};
+use light_gelf_collector_rs::{GelfMessage, MessageFilter, MessageResponse, StoreEvent, StoredMessage};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::RwLock;
+
+struct StoreState {
+    messages: VecDeque<StoredMessage>,
+    index: InvertedIndex,
+    fields: FieldCatalogue,
+    patterns: PatternMiner,
+    hosts: HostTracker,
+    next_id: u64,
+}
+
+impl StoreState {
+    fn add(&mut self, mut gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) -> StoreEvent {
+        let received_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        let corrected_timestamp = self.hosts.observe(&mut gelf_message, &metadata, received_at);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut stored_message = StoredMessage::new(id, gelf_message, raw_message, metadata);
+        stored_message.corrected_timestamp = corrected_timestamp;
+        stored_message.pattern_id = self.patterns.insert(&stored_message.gelf_message, stored_message.received_at);
+        self.index.insert(id, &stored_message.gelf_message);
+        self.fields.insert(&stored_message.gelf_message, stored_message.received_at);
+        let response = stored_message.to_response();
+        self.messages.push_back(stored_message);
+        StoreEvent::Added(response)
+    }
+
+    fn trim_to(&mut self, max_size: usize) {
+        while self.messages.len() > max_size {
+            if let Some(stored) = self.messages.pop_front() {
+                self.index.remove(stored.id, &stored.gelf_message);
+                self.fields.remove(&stored.gelf_message);
+                if let Some(pattern_id) = stored.pattern_id {
+                    self.patterns.remove(pattern_id);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BaselineStore {
+    state: Arc<RwLock<StoreState>>,
+    max_size: usize,
+    broadcaster: Arc<DefaultBroadcaster>,
+}
+
+impl BaselineStore {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            state: Arc::new(RwLock::new(StoreState {
+                messages: VecDeque::new(),
+                index: InvertedIndex::new(),
+                fields: FieldCatalogue::new(),
+                patterns: PatternMiner::new(),
+                hosts: HostTracker::new(&HostsConfig::default()),
+                next_id: 1,
+            })),
+            max_size,
+            broadcaster: Arc::new(DefaultBroadcaster::new(100)),
+        }
+    }
+
+    pub async fn add_message(&self, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) {
+        let event = {
+            let mut state_guard = self.state.write().await;
+            let event = state_guard.add(gelf_message, raw_message, metadata);
+            state_guard.trim_to(self.max_size);
+            event
+        };
+        let _ = self.broadcaster.broadcast(event);
+    }
+
+    pub async fn get_messages(&self, filter: MessageFilter, limit: Option<usize>) -> Vec<MessageResponse> {
+        let state_guard = self.state.read().await;
+        let limit = limit.unwrap_or(state_guard.messages.len());
+        state_guard
+            .messages
+            .iter()
+            .rev()
+            .filter(|stored| filter.matches(*stored))
+            .take(limit)
+            .map(|stored| stored.to_response())
+            .collect()
+    }
+
+    pub async fn ids(&self) -> Vec<u64> {
+        self.state.read().await.messages.iter().map(|stored| stored.id).collect()
+    }
+
+    pub async fn get_hosts(&self) -> Vec<HostInfo> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        self.state.read().await.hosts.list(now)
+    }
+}
@@ -0,0 +1,216 @@
+//! Ingest throughput of the message store with 8 concurrent writers
+//!
+//! The baseline is the store as it was before sharding, with one lock for
+//! the whole buffer; it is compared with the current store at several shard
+//! counts. Messages come from 64 hosts, or all from one busy host. A reader
+//! polls the newest messages the way the web interface does, so each run
+//! also shows how much reading slows ingestion down and how fast reads are.
+//!
+//! Every run checks that the store kept exactly the newest messages, that
+//! reads come back newest first and that every message was counted for its
+//! host, so a faster store cannot get there by losing messages.
+//!
+//! Run with `cargo run --release --example store_throughput`.
+
+mod baseline;
+
+use baseline::BaselineStore;
+use light_gelf_collector_rs::gelf::ReceiveMetadata;
+use light_gelf_collector_rs::hosts::HostInfo;
+use light_gelf_collector_rs::{GelfMessage, InMemoryMessageStore, MessageFilter, MessageStore};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const WRITERS: usize = 8;
+const MESSAGES_PER_WRITER: usize = 25_000;
+const CAPACITY: usize = 50_000;
+const READ_LIMIT: usize = 100;
+const SHARD_COUNTS: [usize; 3] = [1, 4, 8];
+const HOST_COUNTS: [usize; 2] = [64, 1];
+
+/// The store under test, as the benchmark uses it
+#[derive(Clone)]
+enum Store {
+    Baseline(BaselineStore),
+    Sharded(InMemoryMessageStore),
+}
+
+impl Store {
+    async fn add_message(&self, message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) {
+        match self {
+            Store::Baseline(store) => store.add_message(message, raw_message, metadata).await,
+            Store::Sharded(store) => store.add_message(message, raw_message, metadata).await,
+        }
+    }
+
+    async fn newest_ids(&self) -> Vec<u64> {
+        let messages = match self {
+            Store::Baseline(store) => store.get_messages(MessageFilter::default(), Some(READ_LIMIT)).await,
+            Store::Sharded(store) => store.get_messages(MessageFilter::default(), Some(READ_LIMIT)).await,
+        };
+        messages.iter().map(|message| message.id).collect()
+    }
+
+    /// Ids of all buffered messages, oldest first
+    async fn ids(&self) -> Vec<u64> {
+        match self {
+            Store::Baseline(store) => store.ids().await,
+            Store::Sharded(store) => store
+                .get_page(MessageFilter::default(), 0, usize::MAX)
+                .await
+                .iter()
+                .map(|stored| stored.id)
+                .collect(),
+        }
+    }
+
+    async fn hosts(&self) -> Vec<HostInfo> {
+        match self {
+            Store::Baseline(store) => store.get_hosts().await,
+            Store::Sharded(store) => store.get_hosts().await,
+        }
+    }
+}
+
+fn message(writer: usize, sequence: usize, hosts: usize) -> GelfMessage {
+    let mut additional_fields = serde_json::Map::new();
+    additional_fields.insert("_request_id".to_string(), format!("{}-{}", writer, sequence).into());
+    additional_fields.insert("_duration_ms".to_string(), (sequence % 500).into());
+    let failed = sequence.is_multiple_of(50);
+
+    GelfMessage {
+        version: Some("1.1".to_string()),
+        host: Some(format!("host-{}", (sequence * WRITERS + writer) % hosts)),
+        short_message: Some(format!(
+            "GET /api/items/{} completed with status {} in {} ms",
+            sequence,
+            if failed { 500 } else { 200 },
+            sequence % 500
+        )),
+        full_message: None,
+        timestamp: None,
+        level: Some(if failed { 3 } else { 6 }),
+        facility: Some("bench".to_string()),
+        line: None,
+        file: None,
+        additional_fields,
+    }
+}
+
+struct RunResult {
+    elapsed: Duration,
+    reads: u64,
+}
+
+async fn run(store: Store, hosts: usize, with_reader: bool) -> RunResult {
+    let done = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicU64::new(0));
+
+    let reader = with_reader.then(|| {
+        let store = store.clone();
+        let done = done.clone();
+        let reads = reads.clone();
+        tokio::spawn(async move {
+            while !done.load(Ordering::Relaxed) {
+                let ids = store.newest_ids().await;
+                assert!(ids.windows(2).all(|pair| pair[0] > pair[1]), "reads must be newest first");
+                reads.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    });
+
+    let start = Instant::now();
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                for sequence in 0..MESSAGES_PER_WRITER {
+                    let message = message(writer, sequence, hosts);
+                    let raw_message = serde_json::to_string(&message).unwrap();
+                    let metadata = ReceiveMetadata {
+                        size_bytes: raw_message.len(),
+                        parser: "json".to_string(),
+                        ..ReceiveMetadata::default()
+                    };
+                    store.add_message(message, raw_message, metadata).await;
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.await.unwrap();
+    }
+    let elapsed = start.elapsed();
+
+    done.store(true, Ordering::Relaxed);
+    if let Some(reader) = reader {
+        reader.await.unwrap();
+    }
+
+    let total = (WRITERS * MESSAGES_PER_WRITER) as u64;
+    let expected: Vec<u64> = (total - CAPACITY as u64 + 1..=total).collect();
+    assert!(store.ids().await == expected, "the store must keep exactly the newest {} messages", CAPACITY);
+    assert_eq!(store.newest_ids().await, expected.iter().rev().take(READ_LIMIT).copied().collect::<Vec<_>>());
+    let host_info = store.hosts().await;
+    assert_eq!(host_info.len(), hosts);
+    assert_eq!(host_info.iter().map(|info| info.messages).sum::<u64>(), total);
+
+    RunResult {
+        elapsed,
+        reads: reads.load(Ordering::Relaxed),
+    }
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(WRITERS + 1)
+        .enable_all()
+        .build()
+        .unwrap();
+    let total = (WRITERS * MESSAGES_PER_WRITER) as f64;
+
+    println!(
+        "{} writers, {} messages each, capacity {}, {} cores available",
+        WRITERS,
+        MESSAGES_PER_WRITER,
+        CAPACITY,
+        std::thread::available_parallelism().map_or(1, |cores| cores.get())
+    );
+    println!(
+        "{:>5}  {:>9}  {:>10}  {:>14}  {:>10}  {:>8}  {:>8}",
+        "hosts", "store", "msgs/s", "msgs/s+reader", "reads/s", "writes", "reads"
+    );
+
+    for hosts in HOST_COUNTS {
+        let stores = std::iter::once(("baseline".to_string(), None)).chain(
+            SHARD_COUNTS
+                .iter()
+                .map(|&shards| (format!("{} shards", shards), Some(shards))),
+        );
+        let mut baseline = None;
+        for (name, shards) in stores {
+            let store = || match shards {
+                None => Store::Baseline(BaselineStore::new(CAPACITY)),
+                Some(shards) => Store::Sharded(InMemoryMessageStore::new(CAPACITY).with_shards(shards)),
+            };
+            let alone = runtime.block_on(run(store(), hosts, false));
+            let contended = runtime.block_on(run(store(), hosts, true));
+            let rate = total / alone.elapsed.as_secs_f64();
+            let contended_rate = total / contended.elapsed.as_secs_f64();
+            let read_rate = contended.reads as f64 / contended.elapsed.as_secs_f64();
+            let (baseline_rate, baseline_reads) = *baseline.get_or_insert((rate, read_rate));
+
+            println!(
+                "{:>5}  {:>9}  {:>10.0}  {:>14.0}  {:>10.0}  {:>7.2}x  {:>7.2}x",
+                hosts,
+                name,
+                rate,
+                contended_rate,
+                read_rate,
+                rate / baseline_rate,
+                read_rate / baseline_reads
+            );
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use crate::gelf::GelfMessage;
+use crate::query::field_value;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+const DEFAULT_TERMS_SIZE: usize = 10;
+
+/// Aggregations requested from `/aggregate`
+#[derive(Debug, Clone, Default)]
+pub struct AggregationRequest {
+    /// Date histogram bucket width in seconds
+    pub interval: Option<f64>,
+    /// Fields to compute top values for
+    pub terms: Vec<String>,
+    /// Number of top values to return per terms field
+    pub size: usize,
+    /// Numeric fields to compute min/max/avg for
+    pub stats: Vec<String>,
+}
+
+impl AggregationRequest {
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, String> {
+        let list = |name: &str| -> Vec<String> {
+            params
+                .get(name)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|field| !field.is_empty())
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        let interval = match params.get("interval") {
+            Some(value) => match value.parse::<f64>() {
+                Ok(interval) if interval > 0.0 => Some(interval),
+                _ => return Err(format!("invalid interval '{}', expected a positive number of seconds", value)),
+            },
+            None => None,
+        };
+
+        let size = match params.get("size") {
+            Some(value) => value
+                .parse::<usize>()
+                .map_err(|_| format!("invalid size '{}'", value))?,
+            None => DEFAULT_TERMS_SIZE,
+        };
+
+        let request = Self {
+            interval,
+            terms: list("terms"),
+            size,
+            stats: list("stats"),
+        };
+
+        if request.interval.is_none() && request.terms.is_empty() && request.stats.is_empty() {
+            return Err("no aggregation requested, use interval, terms or stats".to_string());
+        }
+
+        Ok(request)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramBucket {
+    /// Start of the bucket as a Unix timestamp
+    pub key: f64,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TermBucket {
+    pub value: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TermsResult {
+    pub buckets: Vec<TermBucket>,
+    /// Messages that had the field but whose value is not in the top buckets
+    pub other_count: usize,
+    /// Messages without the field
+    pub missing: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsResult {
+    pub count: usize,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub avg: Option<f64>,
+    pub sum: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AggregationResult {
+    pub total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<Vec<HistogramBucket>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub terms: BTreeMap<String, TermsResult>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub stats: BTreeMap<String, StatsResult>,
+}
+
+#[derive(Default)]
+struct TermsAccumulator {
+    counts: HashMap<String, usize>,
+    missing: usize,
+}
+
+#[derive(Default)]
+struct StatsAccumulator {
+    count: usize,
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+/// Incrementally computes aggregations over borrowed messages
+pub struct Aggregator<'r> {
+    request: &'r AggregationRequest,
+    total: usize,
+    histogram: BTreeMap<i64, usize>,
+    terms: Vec<TermsAccumulator>,
+    stats: Vec<StatsAccumulator>,
+}
+
+impl<'r> Aggregator<'r> {
+    pub fn new(request: &'r AggregationRequest) -> Self {
+        Self {
+            request,
+            total: 0,
+            histogram: BTreeMap::new(),
+            terms: request.terms.iter().map(|_| TermsAccumulator::default()).collect(),
+            stats: request.stats.iter().map(|_| StatsAccumulator::default()).collect(),
+        }
+    }
+
+    pub fn add(&mut self, message: &GelfMessage, received_at: f64) {
+        self.total += 1;
+
+        if let Some(interval) = self.request.interval {
+            // Bucket by the sender's timestamp, falling back to arrival time
+            let timestamp = message.timestamp.unwrap_or(received_at);
+            let bucket = (timestamp / interval).floor() as i64;
+            *self.histogram.entry(bucket).or_insert(0) += 1;
+        }
+
+        for (field, accumulator) in self.request.terms.iter().zip(&mut self.terms) {
+            match field_value(message, received_at, field) {
+                Some(value) => {
+                    let text = value.as_text();
+                    match accumulator.counts.get_mut(text.as_ref()) {
+                        Some(count) => *count += 1,
+                        None => {
+                            accumulator.counts.insert(text.into_owned(), 1);
+                        }
+                    }
+                }
+                None => accumulator.missing += 1,
+            }
+        }
+
+        for (field, accumulator) in self.request.stats.iter().zip(&mut self.stats) {
+            let Some(n) = field_value(message, received_at, field).and_then(|value| value.as_number()) else {
+                continue;
+            };
+            if accumulator.count == 0 {
+                accumulator.min = n;
+                accumulator.max = n;
+            } else {
+                accumulator.min = accumulator.min.min(n);
+                accumulator.max = accumulator.max.max(n);
+            }
+            accumulator.count += 1;
+            accumulator.sum += n;
+        }
+    }
+
+    pub fn finish(self) -> AggregationResult {
+        let histogram = self.request.interval.map(|interval| {
+            self.histogram
+                .into_iter()
+                .map(|(bucket, count)| HistogramBucket {
+                    key: bucket as f64 * interval,
+                    count,
+                })
+                .collect()
+        });
+
+        let size = self.request.size;
+        let terms = self
+            .request
+            .terms
+            .iter()
+            .cloned()
+            .zip(self.terms)
+            .map(|(field, accumulator)| {
+                let mut buckets: Vec<TermBucket> = accumulator
+                    .counts
+                    .into_iter()
+                    .map(|(value, count)| TermBucket { value, count })
+                    .collect();
+                buckets.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+
+                let other_count = buckets.iter().skip(size).map(|bucket| bucket.count).sum();
+                buckets.truncate(size);
+
+                (
+                    field,
+                    TermsResult {
+                        buckets,
+                        other_count,
+                        missing: accumulator.missing,
+                    },
+                )
+            })
+            .collect();
+
+        let stats = self
+            .request
+            .stats
+            .iter()
+            .cloned()
+            .zip(self.stats)
+            .map(|(field, accumulator)| {
+                let has_values = accumulator.count > 0;
+                (
+                    field,
+                    StatsResult {
+                        count: accumulator.count,
+                        min: has_values.then_some(accumulator.min),
+                        max: has_values.then_some(accumulator.max),
+                        avg: has_values.then(|| accumulator.sum / accumulator.count as f64),
+                        sum: accumulator.sum,
+                    },
+                )
+            })
+            .collect();
+
+        AggregationResult {
+            total: self.total,
+            histogram,
+            terms,
+            stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(params: &[(&str, &str)]) -> Result<AggregationRequest, String> {
+        let params: HashMap<String, String> = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        AggregationRequest::from_params(&params)
+    }
+
+    fn request(params: &[(&str, &str)]) -> AggregationRequest {
+        parse(params).unwrap()
+    }
+
+    fn aggregate(request: &AggregationRequest, messages: &[serde_json::Value]) -> AggregationResult {
+        let mut aggregator = Aggregator::new(request);
+        for message in messages {
+            let message: GelfMessage = serde_json::from_value(message.clone()).unwrap();
+            aggregator.add(&message, 1000.0);
+        }
+        aggregator.finish()
+    }
+
+    #[test]
+    fn requests_need_an_aggregation_and_valid_numbers() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&[("terms", " , ")]).is_err());
+        assert!(parse(&[("interval", "0")]).is_err());
+        assert!(parse(&[("interval", "-5")]).is_err());
+        assert!(parse(&[("terms", "host"), ("size", "many")]).is_err());
+
+        let request = request(&[("terms", "host, _service"), ("stats", "_duration")]);
+        assert_eq!(request.terms, ["host", "_service"]);
+        assert_eq!(request.size, DEFAULT_TERMS_SIZE);
+    }
+
+    #[test]
+    fn histogram_buckets_by_timestamp_falling_back_to_arrival() {
+        let result = aggregate(
+            &request(&[("interval", "60")]),
+            &[
+                json!({ "timestamp": 119.9 }),
+                json!({ "timestamp": 60.0 }),
+                json!({ "timestamp": 180.5 }),
+                json!({}),
+            ],
+        );
+        let buckets: Vec<(f64, usize)> = result
+            .histogram
+            .unwrap()
+            .iter()
+            .map(|bucket| (bucket.key, bucket.count))
+            .collect();
+        assert_eq!(buckets, [(60.0, 2), (180.0, 1), (960.0, 1)]);
+        assert_eq!(result.total, 4);
+    }
+
+    #[test]
+    fn terms_count_values_per_field_and_keep_the_top_ones() {
+        let messages: Vec<serde_json::Value> = [("a", 3), ("b", 3), ("a", 6), ("c", 3), ("b", 6), ("a", 3)]
+            .iter()
+            .map(|(host, level)| json!({ "host": host, "level": level }))
+            .chain([json!({ "level": 3 })])
+            .collect();
+        let result = aggregate(&request(&[("terms", "host,level,_service"), ("size", "2")]), &messages);
+
+        let hosts = &result.terms["host"];
+        let top: Vec<(&str, usize)> = hosts.buckets.iter().map(|b| (b.value.as_str(), b.count)).collect();
+        assert_eq!(top, [("a", 3), ("b", 2)]);
+        assert_eq!((hosts.other_count, hosts.missing), (1, 1));
+
+        let levels = &result.terms["level"];
+        assert_eq!((levels.buckets[0].value.as_str(), levels.buckets[0].count), ("3", 5));
+        assert_eq!((levels.other_count, levels.missing), (0, 0));
+        assert!(result.terms["_service"].buckets.is_empty());
+        assert_eq!(result.terms["_service"].missing, 7);
+    }
+
+    #[test]
+    fn equal_counts_are_ordered_by_value() {
+        let messages: Vec<serde_json::Value> = ["c", "a", "b"].iter().map(|host| json!({ "host": host })).collect();
+        let result = aggregate(&request(&[("terms", "host"), ("size", "2")]), &messages);
+        let values: Vec<&str> = result.terms["host"].buckets.iter().map(|b| b.value.as_str()).collect();
+        assert_eq!(values, ["a", "b"]);
+        assert_eq!(result.terms["host"].other_count, 1);
+    }
+
+    #[test]
+    fn stats_cover_numeric_values_only() {
+        let result = aggregate(
+            &request(&[("stats", "_duration,_missing")]),
+            &[
+                json!({ "_duration": 10 }),
+                json!({ "_duration": 2.5 }),
+                json!({ "_duration": "slow" }),
+                json!({ "_duration": 30 }),
+            ],
+        );
+        let duration = &result.stats["_duration"];
+        assert_eq!(duration.count, 3);
+        assert_eq!((duration.min, duration.max, duration.sum), (Some(2.5), Some(30.0), 42.5));
+        assert!((duration.avg.unwrap() - 42.5 / 3.0).abs() < 1e-9);
+
+        let missing = &result.stats["_missing"];
+        assert_eq!((missing.count, missing.min, missing.avg), (0, None, None));
+    }
+}
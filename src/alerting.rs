@@ -0,0 +1,516 @@
+use crate::config::ConfigError;
+use crate::gelf::GelfMessage;
+use crate::query::{field_value, Query};
+use crate::storage::StatsProvider;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn default_threshold() -> usize {
+    1
+}
+
+fn default_window_secs() -> f64 {
+    60.0
+}
+
+fn default_history_size() -> usize {
+    500
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+/// Scheme, host and port of a webhook URL; the path, query and credentials
+/// often carry its secret
+fn masked_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => format!(
+            "{}://{}{}/***",
+            parsed.scheme(),
+            parsed.host_str().unwrap_or_default(),
+            parsed.port().map(|port| format!(":{}", port)).unwrap_or_default()
+        ),
+        Err(_) => "***".to_string(),
+    }
+}
+
+/// Webhook URLs are never shown in full, e.g. in `GET /admin/config`
+fn mask_url<S: Serializer>(url: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&masked_url(url))
+}
+
+/// Header values such as `Authorization` are never shown
+fn mask_header_values<S: Serializer>(headers: &HashMap<String, String>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(headers.keys().map(|name| (name, "***")))
+}
+
+/// Alerting configuration
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    pub rules: Vec<AlertRuleConfig>,
+    /// Number of firings kept for `GET /alerts`
+    pub history_size: usize,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            history_size: default_history_size(),
+        }
+    }
+}
+
+/// A rule that fires when enough matching messages arrive within a window
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRuleConfig {
+    pub name: String,
+    /// Query selecting the messages this rule counts
+    pub filter: String,
+    /// Number of matches within the window needed to fire
+    #[serde(default = "default_threshold")]
+    pub threshold: usize,
+    #[serde(default = "default_window_secs")]
+    pub window_secs: f64,
+    /// Minimum time between two firings of this rule
+    #[serde(default)]
+    pub cooldown_secs: f64,
+    pub webhook: WebhookConfig,
+}
+
+/// HTTP request sent when a rule fires
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    #[serde(serialize_with = "mask_url")]
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    #[serde(default, serialize_with = "mask_header_values")]
+    pub headers: HashMap<String, String>,
+    /// JSON body template with `{{placeholder}}`s; a default body is sent if omitted
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+/// One recorded firing of a rule
+#[derive(Clone, Debug, Serialize)]
+pub struct AlertEvent {
+    pub id: u64,
+    pub rule: String,
+    pub fired_at: f64,
+    /// Matches within the window when the rule fired
+    pub count: usize,
+    pub host: Option<String>,
+    pub level: Option<u8>,
+    pub short_message: Option<String>,
+    /// `pending`, `sent` or `failed`
+    pub webhook_status: String,
+    pub webhook_error: Option<String>,
+}
+
+struct AlertRule {
+    config: AlertRuleConfig,
+    filter: Query,
+    method: reqwest::Method,
+    /// Receive times of recent matches, oldest first
+    matches: VecDeque<f64>,
+    last_fired: Option<f64>,
+    fired_total: u64,
+}
+
+/// A firing whose webhook is still to be sent
+struct Firing {
+    event: AlertEvent,
+    webhook: WebhookConfig,
+    method: reqwest::Method,
+    body: String,
+}
+
+struct AlertState {
+    rules: Vec<AlertRule>,
+    history: VecDeque<AlertEvent>,
+    next_id: u64,
+}
+
+/// Evaluates alert rules on incoming messages and calls webhooks
+pub struct AlertEngine {
+    state: Mutex<AlertState>,
+    history_size: usize,
+    client: reqwest::Client,
+}
+
+impl AlertEngine {
+    pub fn new(config: &AlertsConfig) -> Result<Self, ConfigError> {
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                let filter = Query::parse(&rule.filter)
+                    .map_err(|e| ConfigError::Invalid(format!("alert rule '{}' filter: {}", rule.name, e)))?;
+                let method = reqwest::Method::from_bytes(rule.webhook.method.to_uppercase().as_bytes())
+                    .map_err(|_| {
+                        ConfigError::Invalid(format!("alert rule '{}' has invalid webhook method", rule.name))
+                    })?;
+                if rule.threshold == 0 || rule.window_secs <= 0.0 {
+                    return Err(ConfigError::Invalid(format!(
+                        "alert rule '{}' needs a positive threshold and window",
+                        rule.name
+                    )));
+                }
+
+                Ok(AlertRule {
+                    config: rule.clone(),
+                    filter,
+                    method,
+                    matches: VecDeque::new(),
+                    last_fired: None,
+                    fired_total: 0,
+                })
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .map_err(|e| ConfigError::Invalid(format!("failed to create webhook client: {}", e)))?;
+
+        Ok(Self {
+            state: Mutex::new(AlertState {
+                rules,
+                history: VecDeque::new(),
+                next_id: 1,
+            }),
+            history_size: config.history_size,
+            client,
+        })
+    }
+
+    /// Count the message against every rule, firing webhooks for rules that trip
+    pub fn evaluate(self: &Arc<Self>, message: &GelfMessage) {
+        for firing in self.record(message, now()) {
+            let engine = self.clone();
+            tokio::spawn(async move {
+                let result = engine.send_webhook(&firing.webhook, firing.method, firing.body).await;
+                engine.record_webhook_result(firing.event.id, result);
+            });
+        }
+    }
+
+    /// Count a message received at `received_at` and record the rules it trips in the history
+    fn record(&self, message: &GelfMessage, received_at: f64) -> Vec<Firing> {
+        let mut state = self.state.lock().unwrap();
+        let mut fired = Vec::new();
+        let mut next_id = state.next_id;
+
+        for rule in state.rules.iter_mut() {
+            if !rule.filter.matches(message, received_at) {
+                continue;
+            }
+
+            rule.matches.push_back(received_at);
+            while rule
+                .matches
+                .front()
+                .is_some_and(|&first| received_at - first > rule.config.window_secs)
+            {
+                rule.matches.pop_front();
+            }
+
+            let cooling_down = rule
+                .last_fired
+                .is_some_and(|last| received_at - last < rule.config.cooldown_secs);
+            if rule.matches.len() < rule.config.threshold || cooling_down {
+                continue;
+            }
+
+            let count = rule.matches.len();
+            rule.last_fired = Some(received_at);
+            rule.fired_total += 1;
+            rule.matches.clear();
+
+            let event = AlertEvent {
+                id: next_id,
+                rule: rule.config.name.clone(),
+                fired_at: received_at,
+                count,
+                host: message.host.clone(),
+                level: message.level,
+                short_message: message.short_message.clone(),
+                webhook_status: "pending".to_string(),
+                webhook_error: None,
+            };
+            next_id += 1;
+
+            let body = render_body(&rule.config, message, received_at, count);
+            info!("Alert rule '{}' fired ({} matches)", rule.config.name, count);
+            fired.push(Firing {
+                event,
+                webhook: rule.config.webhook.clone(),
+                method: rule.method.clone(),
+                body,
+            });
+        }
+
+        state.next_id = next_id;
+        for firing in &fired {
+            state.history.push_back(firing.event.clone());
+            while state.history.len() > self.history_size {
+                state.history.pop_front();
+            }
+        }
+        fired
+    }
+
+    async fn send_webhook(&self, webhook: &WebhookConfig, method: reqwest::Method, body: String) -> Result<(), String> {
+        let mut request = self
+            .client
+            .request(method, &webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        for (name, value) in &webhook.headers {
+            request = request.header(name, value);
+        }
+
+        // Errors name the URL, which must not reach the alert history
+        let response = request.send().await.map_err(|e| e.without_url().to_string())?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("webhook returned {}", response.status()))
+        }
+    }
+
+    fn record_webhook_result(&self, id: u64, result: Result<(), String>) {
+        match &result {
+            Ok(()) => debug!("Webhook for alert {} delivered", id),
+            Err(e) => warn!("Webhook for alert {} failed: {}", id, e),
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(event) = state.history.iter_mut().find(|event| event.id == id) {
+            match result {
+                Ok(()) => event.webhook_status = "sent".to_string(),
+                Err(e) => {
+                    event.webhook_status = "failed".to_string();
+                    event.webhook_error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Recent firings, newest first
+    pub fn history(&self, rule: Option<&str>, limit: Option<usize>) -> Vec<AlertEvent> {
+        let state = self.state.lock().unwrap();
+        state
+            .history
+            .iter()
+            .rev()
+            .filter(|event| rule.is_none_or(|rule| event.rule == rule))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    /// Current state of every rule
+    pub fn rules(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        state
+            .rules
+            .iter()
+            .map(|rule| {
+                serde_json::json!({
+                    "name": rule.config.name,
+                    "filter": rule.config.filter,
+                    "threshold": rule.config.threshold,
+                    "window_secs": rule.config.window_secs,
+                    "cooldown_secs": rule.config.cooldown_secs,
+                    "matches_in_window": rule.matches.len(),
+                    "last_fired": rule.last_fired,
+                    "fired_total": rule.fired_total
+                })
+            })
+            .collect()
+    }
+}
+
+impl StatsProvider for AlertEngine {
+    fn stats(&self) -> serde_json::Value {
+        self.rules()
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Escape a value for insertion inside a JSON string literal
+fn json_escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Fill in `{{placeholder}}`s: `rule`, `count`, `threshold`, `window`, any
+/// message field (`host`, `short_message`, `_service`, ...) and `message`
+/// for the whole message as JSON
+fn render_body(rule: &AlertRuleConfig, message: &GelfMessage, received_at: f64, count: usize) -> String {
+    let Some(template) = &rule.webhook.body else {
+        return serde_json::json!({
+            "rule": rule.name,
+            "count": count,
+            "threshold": rule.threshold,
+            "window_secs": rule.window_secs,
+            "text": format!(
+                "Alert '{}': {} matching messages in {}s, latest from {}: {}",
+                rule.name,
+                count,
+                rule.window_secs,
+                message.host.as_deref().unwrap_or("unknown"),
+                message.short_message.as_deref().unwrap_or("")
+            ),
+            "message": message.to_gelf_payload(received_at)
+        })
+        .to_string();
+    };
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = rest[start + 2..start + end].trim();
+        let value = match name {
+            "rule" => json_escape(&rule.name),
+            "count" => count.to_string(),
+            "threshold" => rule.threshold.to_string(),
+            "window" => rule.window_secs.to_string(),
+            "message" => message.to_gelf_payload(received_at).to_string(),
+            field => field_value(message, received_at, field)
+                .map(|value| json_escape(&value.as_text()))
+                .unwrap_or_default(),
+        };
+        rendered.push_str(&value);
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn engine(rule: serde_json::Value, history_size: usize) -> AlertEngine {
+        let mut rule = rule;
+        rule["name"] = json!("errors");
+        rule["filter"] = json!("level:<=3");
+        rule["webhook"] = json!({ "url": "http://127.0.0.1:9/hook" });
+        AlertEngine::new(&AlertsConfig {
+            rules: vec![serde_json::from_value(rule).unwrap()],
+            history_size,
+        })
+        .unwrap()
+    }
+
+    fn message(level: u8) -> GelfMessage {
+        serde_json::from_value(json!({ "host": "web-1", "short_message": "failed", "level": level, "_service": "api" }))
+            .unwrap()
+    }
+
+    /// Times at which the rule fired when matching messages arrive at `times`
+    fn firings(engine: &AlertEngine, times: &[f64]) -> Vec<f64> {
+        times
+            .iter()
+            .filter(|&&at| !engine.record(&message(3), at).is_empty())
+            .copied()
+            .collect()
+    }
+
+    fn rendered(body: &str) -> String {
+        let rule: AlertRuleConfig = serde_json::from_value(json!({
+            "name": "errors \"api\"",
+            "filter": "level:<=3",
+            "threshold": 5,
+            "webhook": { "url": "http://127.0.0.1:9/hook", "body": body }
+        }))
+        .unwrap();
+        render_body(&rule, &message(3), 1000.0, 7)
+    }
+
+    #[test]
+    fn rules_fire_when_the_threshold_is_reached_within_the_window() {
+        let engine = engine(json!({ "threshold": 3, "window_secs": 10.0 }), 10);
+        // Matches spread wider than the window never add up
+        assert!(firings(&engine, &[0.0, 6.0, 12.0, 18.0]).is_empty());
+        assert_eq!(firings(&engine, &[19.0, 20.0, 21.0, 22.0, 23.0]), [19.0, 22.0]);
+
+        assert!(engine.record(&message(6), 24.0).is_empty());
+        assert_eq!(engine.rules()[0]["matches_in_window"], 1);
+        assert_eq!(engine.history(None, None)[0].count, 3);
+    }
+
+    #[test]
+    fn cooldown_suppresses_refiring() {
+        let engine = engine(json!({ "threshold": 1, "cooldown_secs": 30.0 }), 10);
+        assert_eq!(firings(&engine, &[0.0, 10.0, 29.0, 30.0, 40.0, 60.0]), [0.0, 30.0, 60.0]);
+        assert_eq!(engine.rules()[0]["fired_total"], 3);
+    }
+
+    #[test]
+    fn history_keeps_the_newest_firings() {
+        let engine = engine(json!({}), 3);
+        firings(&engine, &[1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        let history = engine.history(None, None);
+        assert_eq!(history.iter().map(|event| event.id).collect::<Vec<_>>(), [5, 4, 3]);
+        assert_eq!(history[0].webhook_status, "pending");
+        assert_eq!(engine.history(Some("errors"), Some(1)).len(), 1);
+        assert!(engine.history(Some("other"), None).is_empty());
+    }
+
+    #[test]
+    fn templates_fill_in_rule_and_message_fields() {
+        let body = rendered(r#"{"text": "{{rule}}: {{count}}/{{ threshold }} in {{window}}s on {{host}} ({{_service}}, {{missing}})"}"#);
+        let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["text"], r#"errors "api": 7/5 in 60s on web-1 (api, )"#);
+
+        let whole: serde_json::Value = serde_json::from_str(&rendered(r#"{"message": {{message}}}"#)).unwrap();
+        assert_eq!(whole["message"]["short_message"], "failed");
+    }
+
+    #[test]
+    fn unterminated_placeholders_are_kept_as_text() {
+        assert_eq!(rendered("{{count}} then {{host"), "7 then {{host");
+        assert_eq!(rendered("no placeholders }}"), "no placeholders }}");
+    }
+
+    #[test]
+    fn webhook_secrets_are_masked_when_serialized() {
+        let webhook = WebhookConfig {
+            url: "https://user:pw@hooks.example.com:8443/services/T0/SECRET?token=x".to_string(),
+            method: default_method(),
+            headers: HashMap::from([("Authorization".to_string(), "Bearer abc".to_string())]),
+            body: None,
+        };
+        let shown = serde_json::to_value(&webhook).unwrap();
+        assert_eq!(shown["url"], "https://hooks.example.com:8443/***");
+        assert_eq!(shown["headers"]["Authorization"], "***");
+        assert!(!shown.to_string().contains("SECRET"));
+        assert_eq!(masked_url("not a url"), "***");
+    }
+}
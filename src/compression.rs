@@ -5,6 +5,7 @@ use tracing::debug;
 pub trait Decompressor {
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, IoError>;
     fn can_handle(&self, data: &[u8]) -> bool;
+    fn name(&self) -> &'static str;
 }
 
 /// GZIP decompressor
@@ -34,6 +35,10 @@ impl Decompressor for GzipDecompressor {
     fn can_handle(&self, data: &[u8]) -> bool {
         data.len() > 2 && data[0] == 0x1f && data[1] == 0x8b
     }
+
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
 }
 
 /// ZLIB decompressor
@@ -63,6 +68,10 @@ impl Decompressor for ZlibDecompressor {
     fn can_handle(&self, data: &[u8]) -> bool {
         data.len() > 2 && data[0] == 0x78 && (data[1] == 0x9c || data[1] == 0xda || data[1] == 0x01)
     }
+
+    fn name(&self) -> &'static str {
+        "zlib"
+    }
 }
 
 /// Compression manager that handles multiple decompression algorithms
@@ -80,14 +89,18 @@ impl CompressionManager {
         Self { decompressors }
     }
 
+    /// Name of the compression applied to the data, if any is recognised
+    pub fn detect(&self, data: &[u8]) -> Option<&'static str> {
+        self.decompressors
+            .iter()
+            .find(|decompressor| decompressor.can_handle(data))
+            .map(|decompressor| decompressor.name())
+    }
+
     pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, IoError> {
         for decompressor in &self.decompressors {
             if decompressor.can_handle(data) {
-                let compression_type = if decompressor.can_handle(data) {
-                    if data[0] == 0x1f && data[1] == 0x8b { "GZIP" } else { "ZLIB" }
-                } else { "unknown" };
-                
-                debug!("Message compression detected: {}", compression_type);
+                debug!("Message compression detected: {}", decompressor.name());
                 return decompressor.decompress(data);
             }
         }
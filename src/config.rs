@@ -1,8 +1,18 @@
+use crate::alerting::AlertsConfig;
+use crate::dedup::DedupConfig;
+use crate::hosts::HostsConfig;
+use crate::output::OutputsConfig;
+use crate::processing::ProcessorConfig;
+use crate::streams::StreamConfig;
+use crate::tenants::TenancyConfig;
 use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 /// Application configuration
-#[derive(Parser, Clone, Debug)]
+#[derive(Parser, Clone, Debug, Serialize)]
 #[command(name = "light-gelf-collector")]
 #[command(about = "A lightweight GELF log collector")]
 pub struct Config {
@@ -18,12 +28,75 @@ pub struct Config {
     #[arg(short, long, default_value = "10000")]
     pub max_messages: usize,
 
+    /// Independently locked parts of each buffer, for receiving on several cores; messages are spread over them
+    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u16).range(1..=256))]
+    pub store_shards: u16,
+
     /// Bind address
     #[arg(short, long, default_value = "0.0.0.0")]
     pub bind_address: String,
+
+    /// Accept level names and textual or millisecond timestamps instead of rejecting the message
+    #[arg(long)]
+    pub tolerant_parsing: bool,
+
+    /// JSON file with outputs, rules and other structured settings
+    #[arg(short, long)]
+    pub config_file: Option<PathBuf>,
+
+    /// Settings loaded from the config file
+    #[arg(skip)]
+    #[serde(flatten)]
+    pub file: FileConfig,
+}
+
+/// Structured settings that are too rich for command line flags
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub outputs: OutputsConfig,
+    pub alerts: AlertsConfig,
+    pub dedup: DedupConfig,
+    pub hosts: HostsConfig,
+    /// Transforms applied to every message before it is stored, in order
+    pub processors: Vec<ProcessorConfig>,
+    /// Named buffers next to the default one, each receiving the messages matching its filter
+    pub streams: Vec<StreamConfig>,
+    /// Teams with their own buffers and API tokens
+    pub tenancy: TenancyConfig,
+}
+
+/// Error loading the config file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Invalid(String),
 }
 
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "failed to read config file {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "invalid config file {}: {}", path.display(), e),
+            ConfigError::Invalid(message) => write!(f, "invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl Config {
+    /// Parse command line arguments and load the config file, if any
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut config = Self::parse();
+        if let Some(path) = &config.config_file {
+            let contents = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.clone(), e))?;
+            config.file = serde_json::from_str(&contents).map_err(|e| ConfigError::Parse(path.clone(), e))?;
+        }
+        Ok(config)
+    }
+
     pub fn udp_addr(&self) -> Result<SocketAddr, std::net::AddrParseError> {
         format!("{}:{}", self.bind_address, self.udp_port).parse()
     }
@@ -31,4 +104,4 @@ impl Config {
     pub fn http_addr(&self) -> Result<SocketAddr, std::net::AddrParseError> {
         format!("{}:{}", self.bind_address, self.http_port).parse()
     }
-}
\ No newline at end of file
+}
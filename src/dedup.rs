@@ -0,0 +1,237 @@
+use crate::gelf::GelfMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Repeated-message collapsing configuration
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DedupConfig {
+    pub enabled: bool,
+    /// Duplicates arriving within this long of the previous one are collapsed
+    pub window_secs: f64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            window_secs: 10.0,
+        }
+    }
+}
+
+/// Fields that make two messages duplicates of each other
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DedupKey {
+    host: Option<String>,
+    level: Option<u8>,
+    short_message: Option<String>,
+}
+
+impl DedupKey {
+    fn of(message: &GelfMessage) -> Self {
+        Self {
+            host: message.host.clone(),
+            level: message.level,
+            short_message: message.short_message.clone(),
+        }
+    }
+}
+
+/// Hash of the fields duplicates share, so that they can be kept together
+pub fn key_hash(message: &GelfMessage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (&message.host, message.level, &message.short_message).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A stored entry that duplicates are collapsed into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Repeat {
+    pub id: u64,
+    /// Copies of the message received so far, the stored one included
+    pub copies: u64,
+}
+
+/// Tracks the latest stored entry for each (host, level, short_message)
+#[derive(Debug)]
+pub struct Deduplicator {
+    window_secs: f64,
+    /// The entry and when a copy was last seen
+    recent: HashMap<DedupKey, (Repeat, f64)>,
+    collapsed: u64,
+}
+
+impl Deduplicator {
+    pub fn new(config: &DedupConfig) -> Self {
+        Self {
+            window_secs: config.window_secs,
+            recent: HashMap::new(),
+            collapsed: 0,
+        }
+    }
+
+    /// Entry the message should be collapsed into, if any, counting the message as a copy
+    ///
+    /// The window slides: every duplicate extends it, so a steady stream
+    /// of repeats keeps collapsing into the same entry.
+    pub fn find(&mut self, message: &GelfMessage, received_at: f64) -> Option<Repeat> {
+        let (repeat, last_seen) = self.recent.get_mut(&DedupKey::of(message))?;
+        if received_at - *last_seen > self.window_secs {
+            return None;
+        }
+        *last_seen = received_at;
+        repeat.copies += 1;
+        self.collapsed += 1;
+        Some(*repeat)
+    }
+
+    /// Remember a newly stored entry as the target for later duplicates
+    pub fn record(&mut self, id: u64, message: &GelfMessage, received_at: f64) {
+        self.recent
+            .insert(DedupKey::of(message), (Repeat { id, copies: 1 }, received_at));
+    }
+
+    /// Stop collapsing into an entry that left the buffer
+    pub fn remove(&mut self, id: u64, message: &GelfMessage) {
+        let key = DedupKey::of(message);
+        if self.recent.get(&key).is_some_and(|(current, _)| current.id == id) {
+            self.recent.remove(&key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.recent.clear();
+    }
+
+    pub fn counts(&self) -> DedupCounts {
+        DedupCounts {
+            window_secs: self.window_secs,
+            collapsed: self.collapsed,
+            tracked: self.recent.len(),
+        }
+    }
+
+    /// Statistics over the deduplicators of all shards; `None` without any
+    pub fn combined_stats(counts: impl IntoIterator<Item = DedupCounts>) -> Option<serde_json::Value> {
+        let mut counts = counts.into_iter().peekable();
+        let window_secs = counts.peek()?.window_secs;
+        let (collapsed, tracked) = counts.fold((0, 0), |(collapsed, tracked), counts| {
+            (collapsed + counts.collapsed, tracked + counts.tracked)
+        });
+        Some(serde_json::json!({
+            "window_secs": window_secs,
+            "collapsed": collapsed,
+            "tracked": tracked
+        }))
+    }
+}
+
+/// Counters of one shard's deduplicator, read while its lock is held
+#[derive(Debug, Clone, Copy)]
+pub struct DedupCounts {
+    window_secs: f64,
+    collapsed: u64,
+    tracked: usize,
+}
+
+/// Set the repeat fields of a stored entry after another copy arrived
+///
+/// The values come from the collector alone, replacing any fields of the
+/// same name the sender set.
+pub fn collapse(message: &mut GelfMessage, copies: u64, first_received_at: f64, received_at: f64) {
+    let fields = &mut message.additional_fields;
+    fields.insert("_repeat_count".to_string(), serde_json::json!(copies));
+    fields.insert("_first_seen".to_string(), serde_json::json!(first_received_at));
+    fields.insert("_last_seen".to_string(), serde_json::json!(received_at));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(host: &str, level: u8, text: &str) -> GelfMessage {
+        serde_json::from_value(json!({ "host": host, "level": level, "short_message": text })).unwrap()
+    }
+
+    fn dedup(window_secs: f64) -> Deduplicator {
+        Deduplicator::new(&DedupConfig {
+            enabled: true,
+            window_secs,
+        })
+    }
+
+    #[test]
+    fn repeats_within_the_window_collapse_and_extend_it() {
+        let mut dedup = dedup(10.0);
+        let disk_full = message("a", 3, "disk full");
+        assert_eq!(dedup.find(&disk_full, 0.0), None);
+        dedup.record(1, &disk_full, 0.0);
+
+        assert_eq!(dedup.find(&disk_full, 9.0), Some(Repeat { id: 1, copies: 2 }));
+        // 18 seconds after the entry, but only 9 after the last copy
+        assert_eq!(dedup.find(&disk_full, 18.0), Some(Repeat { id: 1, copies: 3 }));
+        assert_eq!(dedup.find(&disk_full, 28.5), None);
+        assert_eq!(Deduplicator::combined_stats([dedup.counts()]).unwrap()["collapsed"], 2);
+    }
+
+    #[test]
+    fn host_level_and_text_must_all_match() {
+        let mut dedup = dedup(10.0);
+        dedup.record(1, &message("a", 3, "disk full"), 0.0);
+
+        assert_eq!(dedup.find(&message("b", 3, "disk full"), 1.0), None);
+        assert_eq!(dedup.find(&message("a", 4, "disk full"), 1.0), None);
+        assert_eq!(dedup.find(&message("a", 3, "disk almost full"), 1.0), None);
+        assert_eq!(key_hash(&message("a", 3, "disk full")), key_hash(&message("a", 3, "disk full")));
+    }
+
+    #[test]
+    fn removed_entries_are_no_longer_targets() {
+        let mut dedup = dedup(10.0);
+        let disk_full = message("a", 3, "disk full");
+        dedup.record(1, &disk_full, 0.0);
+        dedup.record(2, &disk_full, 20.0);
+
+        // Entry 1 was replaced as the target, so removing it changes nothing
+        dedup.remove(1, &disk_full);
+        assert_eq!(dedup.find(&disk_full, 21.0).map(|repeat| repeat.id), Some(2));
+        dedup.remove(2, &disk_full);
+        assert_eq!(dedup.find(&disk_full, 22.0), None);
+    }
+
+    #[test]
+    fn collapsing_counts_copies_and_keeps_the_first_time() {
+        let mut dedup = dedup(10.0);
+        let mut stored = message("a", 3, "disk full");
+        dedup.record(1, &stored, 100.0);
+        for received_at in [105.0, 109.0] {
+            let repeat = dedup.find(&stored, received_at).unwrap();
+            collapse(&mut stored, repeat.copies, 100.0, received_at);
+        }
+
+        assert_eq!(stored.additional_fields["_repeat_count"], 3);
+        assert_eq!(stored.additional_fields["_first_seen"], 100.0);
+        assert_eq!(stored.additional_fields["_last_seen"], 109.0);
+    }
+
+    #[test]
+    fn repeat_fields_sent_by_the_client_are_replaced() {
+        let mut stored: GelfMessage = serde_json::from_value(json!({
+            "host": "a",
+            "short_message": "disk full",
+            "_repeat_count": 40,
+            "_first_seen": 1.0,
+            "_last_seen": 2.0
+        }))
+        .unwrap();
+        collapse(&mut stored, 2, 100.0, 105.0);
+
+        assert_eq!(stored.additional_fields["_repeat_count"], 2);
+        assert_eq!(stored.additional_fields["_first_seen"], 100.0);
+        assert_eq!(stored.additional_fields["_last_seen"], 105.0);
+    }
+}
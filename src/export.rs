@@ -0,0 +1,217 @@
+use crate::gelf::StoredMessage;
+use crate::query::field_value;
+use std::fmt::Write;
+
+/// Columns used for CSV exports when none are requested
+pub const DEFAULT_CSV_COLUMNS: &[&str] = &[
+    "received_at",
+    "timestamp",
+    "host",
+    "level",
+    "facility",
+    "short_message",
+];
+
+/// Output format for `/export`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportFormat {
+    /// One JSON message per line, as returned by `/logs`
+    Ndjson,
+    /// Comma-separated values with a header row
+    Csv { columns: Vec<String> },
+    /// The original payloads as received, one per line; see `single_line`
+    Raw,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str, columns: Option<&str>) -> Result<Self, String> {
+        match format {
+            "ndjson" | "json" => Ok(ExportFormat::Ndjson),
+            "csv" => {
+                let columns: Vec<String> = match columns {
+                    Some(columns) => columns
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|column| !column.is_empty())
+                        .map(String::from)
+                        .collect(),
+                    None => DEFAULT_CSV_COLUMNS.iter().map(|c| c.to_string()).collect(),
+                };
+                if columns.is_empty() {
+                    return Err("columns must name at least one field".to_string());
+                }
+                Ok(ExportFormat::Csv { columns })
+            }
+            "raw" | "gelf" => Ok(ExportFormat::Raw),
+            other => Err(format!("unknown export format '{}', expected ndjson, csv or raw", other)),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv { .. } => "text/csv; charset=utf-8",
+            ExportFormat::Raw => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv { .. } => "csv",
+            ExportFormat::Raw => "gelf",
+        }
+    }
+
+    /// Text written before the first message
+    pub fn header(&self) -> String {
+        match self {
+            ExportFormat::Csv { columns } => {
+                let mut header = columns
+                    .iter()
+                    .map(|column| csv_escape(column))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                header.push('\n');
+                header
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// Append one message to the output buffer
+    pub fn write_message(&self, out: &mut String, stored: &StoredMessage) {
+        match self {
+            ExportFormat::Ndjson => {
+                if let Ok(json) = serde_json::to_string(&stored.to_response()) {
+                    out.push_str(&json);
+                    out.push('\n');
+                }
+            }
+            ExportFormat::Csv { columns } => {
+                for (i, column) in columns.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    if let Some(value) = field_value(&stored.gelf_message, stored.received_at, column) {
+                        let _ = write!(out, "{}", csv_escape(&value.as_text()));
+                    }
+                }
+                out.push('\n');
+            }
+            ExportFormat::Raw => {
+                out.push_str(&single_line(&stored.raw_message));
+                out.push('\n');
+            }
+        }
+    }
+}
+
+/// A payload on one line, so that a line break always separates two messages
+///
+/// Payloads spanning several lines are re-serialized as compact JSON; those
+/// that are not valid JSON get their line breaks escaped instead.
+fn single_line(raw: &str) -> std::borrow::Cow<'_, str> {
+    if !raw.contains(['\n', '\r']) {
+        return std::borrow::Cow::Borrowed(raw);
+    }
+    match serde_json::from_str::<serde_json::Value>(raw) {
+        Ok(value) => std::borrow::Cow::Owned(value.to_string()),
+        Err(_) => std::borrow::Cow::Owned(raw.replace('\r', "\\r").replace('\n', "\\n")),
+    }
+}
+
+/// Quote a CSV cell when it contains separators, quotes or line breaks
+fn csv_escape(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        std::borrow::Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        std::borrow::Cow::Borrowed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gelf::ReceiveMetadata;
+    use serde_json::json;
+
+    fn stored(message: serde_json::Value, raw_message: &str) -> StoredMessage {
+        StoredMessage {
+            id: 7,
+            gelf_message: serde_json::from_value(message).unwrap(),
+            received_at: 1700000000.5,
+            raw_message: raw_message.to_string(),
+            metadata: ReceiveMetadata::default(),
+            pattern_id: None,
+            corrected_timestamp: 1700000000.5,
+        }
+    }
+
+    fn export(format: &ExportFormat, messages: &[StoredMessage]) -> String {
+        let mut out = format.header();
+        for message in messages {
+            format.write_message(&mut out, message);
+        }
+        out
+    }
+
+    #[test]
+    fn formats_are_parsed_with_their_aliases() {
+        assert_eq!(ExportFormat::parse("json", None), Ok(ExportFormat::Ndjson));
+        assert_eq!(ExportFormat::parse("gelf", None), Ok(ExportFormat::Raw));
+        assert_eq!(
+            ExportFormat::parse("csv", Some(" host, ,_service")),
+            Ok(ExportFormat::Csv { columns: vec!["host".to_string(), "_service".to_string()] })
+        );
+        let ExportFormat::Csv { columns } = ExportFormat::parse("csv", None).unwrap() else {
+            panic!("expected csv");
+        };
+        assert_eq!(columns, DEFAULT_CSV_COLUMNS);
+        assert!(ExportFormat::parse("csv", Some(" , ")).is_err());
+        assert!(ExportFormat::parse("xml", None).is_err());
+    }
+
+    #[test]
+    fn ndjson_writes_one_response_per_line() {
+        let message = stored(json!({ "host": "web-1", "short_message": "line one\nline two" }), "");
+        let out = export(&ExportFormat::Ndjson, &[message.clone(), message]);
+
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!((first["id"].clone(), first["short_message"].clone()), (json!(7), json!("line one\nline two")));
+    }
+
+    #[test]
+    fn csv_quotes_cells_that_need_it() {
+        let format = ExportFormat::parse("csv", Some("host,short_message,_service,level")).unwrap();
+        let message = stored(
+            json!({ "host": "web-1", "short_message": "said \"hi\", then\nleft", "level": 3 }),
+            "",
+        );
+        assert_eq!(
+            export(&format, &[message]),
+            "host,short_message,_service,level\nweb-1,\"said \"\"hi\"\", then\nleft\",,3\n"
+        );
+    }
+
+    #[test]
+    fn raw_payloads_stay_on_one_line() {
+        let compact = r#"{"host":"web-1","short_message":"ok"}"#;
+        let pretty = "{\n  \"host\": \"web-1\",\n  \"short_message\": \"multi\\nline\"\n}";
+        let broken = "not json\r\nsecond line";
+        let messages: Vec<StoredMessage> = [compact, pretty, broken]
+            .iter()
+            .map(|raw| stored(json!({ "host": "web-1" }), raw))
+            .collect();
+
+        let out = export(&ExportFormat::Raw, &messages);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], compact);
+        let reparsed: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(reparsed, json!({ "host": "web-1", "short_message": "multi\nline" }));
+        assert_eq!(lines[2], r"not json\r\nsecond line");
+    }
+}
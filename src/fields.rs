@@ -0,0 +1,244 @@
+use crate::gelf::GelfMessage;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+const MAX_EXAMPLES: usize = 5;
+const MAX_EXAMPLE_LENGTH: usize = 100;
+
+/// Observed information about one additional field
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldInfo {
+    pub name: String,
+    /// Occurrences per JSON type among buffered messages
+    pub types: BTreeMap<&'static str, usize>,
+    /// Number of buffered messages carrying the field
+    pub count: usize,
+    /// Number of messages carrying the field since it was first seen
+    pub total_seen: u64,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    pub examples: Vec<String>,
+}
+
+impl FieldInfo {
+    /// Combine with the same field's information from another shard
+    fn merge(&mut self, other: FieldInfo) {
+        for (type_name, count) in other.types {
+            *self.types.entry(type_name).or_insert(0) += count;
+        }
+        self.count += other.count;
+        self.total_seen += other.total_seen;
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+        for example in other.examples {
+            if self.examples.len() < MAX_EXAMPLES && !self.examples.contains(&example) {
+                self.examples.push(example);
+            }
+        }
+    }
+}
+
+/// Combine the field lists of several shards into one sorted by name
+pub fn merge_lists(lists: impl IntoIterator<Item = Vec<FieldInfo>>) -> Vec<FieldInfo> {
+    let mut merged: BTreeMap<String, FieldInfo> = BTreeMap::new();
+    for info in lists.into_iter().flatten() {
+        match merged.get_mut(&info.name) {
+            Some(existing) => existing.merge(info),
+            None => {
+                merged.insert(info.name.clone(), info);
+            }
+        }
+    }
+    merged.into_values().collect()
+}
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+fn example_text(value: &serde_json::Value) -> String {
+    let text = match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    match text.char_indices().nth(MAX_EXAMPLE_LENGTH) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+/// Live catalogue of additional fields present in the buffer
+#[derive(Debug, Default)]
+pub struct FieldCatalogue {
+    fields: HashMap<String, FieldInfo>,
+}
+
+impl FieldCatalogue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, message: &GelfMessage, received_at: f64) {
+        for (name, value) in &message.additional_fields {
+            let info = self.fields.entry(name.clone()).or_insert_with(|| FieldInfo {
+                name: name.clone(),
+                types: BTreeMap::new(),
+                count: 0,
+                total_seen: 0,
+                first_seen: received_at,
+                last_seen: received_at,
+                examples: Vec::new(),
+            });
+
+            *info.types.entry(json_type(value)).or_insert(0) += 1;
+            info.count += 1;
+            info.total_seen += 1;
+            info.last_seen = received_at;
+
+            if info.examples.len() < MAX_EXAMPLES && !value.is_null() {
+                let example = example_text(value);
+                if !info.examples.contains(&example) {
+                    info.examples.push(example);
+                }
+            }
+        }
+    }
+
+    /// Forget a message that left the buffer; fields no longer present are dropped
+    pub fn remove(&mut self, message: &GelfMessage) {
+        for (name, value) in &message.additional_fields {
+            let Some(info) = self.fields.get_mut(name) else {
+                continue;
+            };
+
+            let type_name = json_type(value);
+            if let Some(count) = info.types.get_mut(type_name) {
+                *count -= 1;
+                if *count == 0 {
+                    info.types.remove(type_name);
+                }
+            }
+
+            info.count -= 1;
+            if info.count == 0 {
+                self.fields.remove(name);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Fields sorted by name, optionally restricted to a name prefix
+    pub fn list(&self, prefix: Option<&str>) -> Vec<FieldInfo> {
+        let mut fields: Vec<FieldInfo> = self
+            .fields
+            .values()
+            .filter(|info| prefix.is_none_or(|prefix| info.name.starts_with(prefix)))
+            .cloned()
+            .collect();
+        fields.sort_by(|a, b| a.name.cmp(&b.name));
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(fields: serde_json::Value) -> GelfMessage {
+        let mut message = json!({ "host": "web-1", "short_message": "hello" });
+        message.as_object_mut().unwrap().extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(message).unwrap()
+    }
+
+    fn catalogue(messages: &[GelfMessage]) -> FieldCatalogue {
+        let mut catalogue = FieldCatalogue::new();
+        for (i, message) in messages.iter().enumerate() {
+            catalogue.insert(message, i as f64);
+        }
+        catalogue
+    }
+
+    #[test]
+    fn fields_are_discovered_with_their_types() {
+        let catalogue = catalogue(&[
+            message(json!({ "_status": 200, "_user": "alice" })),
+            message(json!({ "_status": "timeout", "_tags": ["a"], "_ctx": { "k": 1 } })),
+            message(json!({ "_status": null, "_ok": true })),
+        ]);
+
+        let names: Vec<String> = catalogue.list(None).into_iter().map(|info| info.name).collect();
+        assert_eq!(names, ["_ctx", "_ok", "_status", "_tags", "_user"]);
+        let status = &catalogue.list(Some("_st"))[0];
+        assert_eq!(
+            status.types,
+            BTreeMap::from([("null", 1), ("number", 1), ("string", 1)])
+        );
+        assert_eq!((status.count, status.first_seen, status.last_seen), (3, 0.0, 2.0));
+        assert_eq!(status.examples, ["200", "timeout"]);
+        assert_eq!(catalogue.list(Some("_ctx"))[0].examples, [r#"{"k":1}"#]);
+        assert!(catalogue.list(Some("status")).is_empty());
+    }
+
+    #[test]
+    fn examples_are_distinct_capped_and_shortened() {
+        let long = "x".repeat(MAX_EXAMPLE_LENGTH + 20);
+        let mut messages: Vec<GelfMessage> = (0..20)
+            .map(|i| message(json!({ "_request_id": format!("req-{}", i % 8) })))
+            .collect();
+        messages.push(message(json!({ "_body": long })));
+        let catalogue = catalogue(&messages);
+
+        let request_id = &catalogue.list(Some("_request_id"))[0];
+        assert_eq!(request_id.examples, ["req-0", "req-1", "req-2", "req-3", "req-4"]);
+        assert_eq!(request_id.count, 20);
+        let body = &catalogue.list(Some("_body"))[0].examples[0];
+        assert_eq!(body.chars().count(), MAX_EXAMPLE_LENGTH + 1);
+        assert!(body.ends_with('…'));
+    }
+
+    #[test]
+    fn fields_leave_with_their_last_message() {
+        let first = message(json!({ "_status": 200, "_user": "alice" }));
+        let second = message(json!({ "_status": "timeout" }));
+        let mut catalogue = catalogue(&[first.clone(), second]);
+
+        catalogue.remove(&first);
+        assert_eq!(catalogue.len(), 1);
+        let status = &catalogue.list(None)[0];
+        assert_eq!(status.types, BTreeMap::from([("string", 1)]));
+        assert_eq!((status.count, status.total_seen), (1, 2));
+    }
+
+    #[test]
+    fn shard_lists_merge_by_name() {
+        let left = catalogue(&[message(json!({ "_a": 1 })), message(json!({ "_b": "x" }))]).list(None);
+        let mut right = FieldCatalogue::new();
+        right.insert(&message(json!({ "_a": "one" })), 5.0);
+
+        let merged = merge_lists([left, right.list(None)]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].types, BTreeMap::from([("number", 1), ("string", 1)]));
+        assert_eq!((merged[0].count, merged[0].first_seen, merged[0].last_seen), (2, 0.0, 5.0));
+        assert_eq!(merged[0].examples, ["1", "one"]);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::gelf::{GelfMessage, MessageResponse, StoredMessage};
+use crate::query::{Query, QueryError};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A stored message, or a copy of one, that a filter can be applied to
+pub trait Filterable {
+    fn gelf_message(&self) -> &GelfMessage;
+    fn received_at(&self) -> f64;
+    fn pattern_id(&self) -> Option<u64>;
+}
+
+impl Filterable for StoredMessage {
+    fn gelf_message(&self) -> &GelfMessage {
+        &self.gelf_message
+    }
+
+    fn received_at(&self) -> f64 {
+        self.received_at
+    }
+
+    fn pattern_id(&self) -> Option<u64> {
+        self.pattern_id
+    }
+}
+
+impl Filterable for MessageResponse {
+    fn gelf_message(&self) -> &GelfMessage {
+        &self.gelf_message
+    }
+
+    fn received_at(&self) -> f64 {
+        self.received_at
+    }
+
+    fn pattern_id(&self) -> Option<u64> {
+        self.pattern_id
+    }
+}
+
+/// Why HTTP parameters could not be turned into a filter
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterError {
+    Query(QueryError),
+    InvalidParameter { name: String, value: String },
+    UnknownParameter(String),
+}
+
+impl FilterError {
+    /// Body of the 400 response, with the position of query syntax errors
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            FilterError::Query(error) => serde_json::json!({
+                "error": error.message,
+                "position": error.position
+            }),
+            other => serde_json::json!({ "error": other.to_string() }),
+        }
+    }
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::Query(error) => error.fmt(f),
+            FilterError::InvalidParameter { name, value } => write!(f, "invalid value '{}' for '{}'", value, name),
+            FilterError::UnknownParameter(name) => write!(f, "unknown parameter '{}'", name),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl From<QueryError> for FilterError {
+    fn from(error: QueryError) -> Self {
+        FilterError::Query(error)
+    }
+}
+
+/// Parse an optional numeric parameter, rejecting values that are not numbers
+fn parse_param<T: std::str::FromStr>(params: &HashMap<String, String>, name: &str) -> Result<Option<T>, FilterError> {
+    params
+        .get(name)
+        .map(|value| {
+            value.trim().parse::<T>().map_err(|_| FilterError::InvalidParameter {
+                name: name.to_string(),
+                value: value.clone(),
+            })
+        })
+        .transpose()
+}
+
+/// Criteria for selecting stored messages
+#[derive(Debug, Clone, Default)]
+pub struct MessageFilter {
+    pub host: Option<String>,
+    pub max_level: Option<u8>,
+    pub facility: Option<String>,
+    pub since: Option<f64>,
+    pub until: Option<f64>,
+    pub search: Option<String>,
+    pub pattern: Option<u64>,
+    pub query: Option<Query>,
+}
+
+impl MessageFilter {
+    /// HTTP query parameters read by `from_params`
+    pub const PARAMS: &'static [&'static str] = &["host", "level", "facility", "since", "until", "search", "pattern", "q"];
+
+    /// Build a filter from HTTP query parameters
+    ///
+    /// Numeric parameters that are not numbers and invalid `q` query
+    /// expressions are reported as errors; other parameters are left to the
+    /// caller.
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, FilterError> {
+        let query = match params.get("q").map(|q| q.trim()) {
+            Some(q) if !q.is_empty() => Some(Query::parse(q)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            host: params.get("host").cloned(),
+            max_level: parse_param(params, "level")?,
+            facility: params.get("facility").cloned(),
+            since: parse_param(params, "since")?,
+            until: parse_param(params, "until")?,
+            search: params.get("search").map(|s| s.to_lowercase()),
+            pattern: parse_param(params, "pattern")?,
+            query,
+        })
+    }
+
+    /// Reject parameters that are neither filter parameters nor in `allowed`
+    pub fn check_params(params: &HashMap<String, String>, allowed: &[&str]) -> Result<(), FilterError> {
+        match params
+            .keys()
+            .find(|name| !Self::PARAMS.contains(&name.as_str()) && !allowed.contains(&name.as_str()))
+        {
+            Some(name) => Err(FilterError::UnknownParameter(name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns true if the filter selects every message
+    pub fn is_empty(&self) -> bool {
+        self.host.is_none()
+            && self.max_level.is_none()
+            && self.facility.is_none()
+            && self.since.is_none()
+            && self.until.is_none()
+            && self.search.is_none()
+            && self.pattern.is_none()
+            && self.query.is_none()
+    }
+
+    pub fn matches(&self, entry: &impl Filterable) -> bool {
+        let message = entry.gelf_message();
+        let received_at = entry.received_at();
+
+        if self.pattern.is_some_and(|pattern| entry.pattern_id() != Some(pattern)) {
+            return false;
+        }
+
+        if self.host.as_deref().is_some_and(|host| message.host.as_deref() != Some(host)) {
+            return false;
+        }
+
+        if let Some(max_level) = self.max_level {
+            // Messages without a level are treated as informational (6)
+            if message.level.unwrap_or(6) > max_level {
+                return false;
+            }
+        }
+
+        if self
+            .facility
+            .as_deref()
+            .is_some_and(|facility| message.facility.as_deref() != Some(facility))
+        {
+            return false;
+        }
+
+        if self.since.is_some_and(|since| received_at < since) {
+            return false;
+        }
+
+        if self.until.is_some_and(|until| received_at > until) {
+            return false;
+        }
+
+        if let Some(search) = &self.search {
+            let found = [&message.short_message, &message.full_message]
+                .into_iter()
+                .flatten()
+                .any(|text| text.to_lowercase().contains(search.as_str()));
+            if !found {
+                return false;
+            }
+        }
+
+        if let Some(query) = &self.query {
+            return query.matches(message, received_at);
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn parameters_build_a_filter() {
+        let filter = MessageFilter::from_params(&params(&[("level", "3"), ("since", "1.5"), ("host", "web")])).unwrap();
+        assert_eq!(filter.max_level, Some(3));
+        assert_eq!(filter.since, Some(1.5));
+        assert_eq!(filter.host.as_deref(), Some("web"));
+        assert!(MessageFilter::from_params(&params(&[("q", "  ")])).unwrap().is_empty());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for (name, value) in [("level", "error"), ("level", "300"), ("since", "yesterday"), ("pattern", "-1")] {
+            assert_eq!(
+                MessageFilter::from_params(&params(&[(name, value)])).unwrap_err(),
+                FilterError::InvalidParameter {
+                    name: name.to_string(),
+                    value: value.to_string()
+                }
+            );
+        }
+        assert!(matches!(
+            MessageFilter::from_params(&params(&[("q", "(a")])),
+            Err(FilterError::Query(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_parameters_are_reported() {
+        assert_eq!(
+            MessageFilter::check_params(&params(&[("levle", "3")]), &["all"]),
+            Err(FilterError::UnknownParameter("levle".to_string()))
+        );
+        assert_eq!(MessageFilter::check_params(&params(&[("level", "3"), ("all", "true")]), &["all"]), Ok(()));
+    }
+}
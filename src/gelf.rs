@@ -18,31 +18,65 @@ pub struct GelfMessage {
     pub additional_fields: serde_json::Map<String, serde_json::Value>,
 }
 
+/// Details of how a message arrived at the collector
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReceiveMetadata {
+    /// Address of the sender
+    pub source: Option<String>,
+    /// Compression detected on the wire, if any
+    pub compression: Option<String>,
+    /// Size of the payload as received, before decompression
+    pub size_bytes: usize,
+    /// Name of the parser that produced the message
+    pub parser: String,
+    /// Local UDP port the message arrived on
+    pub port: Option<u16>,
+}
+
 /// Stored message with metadata
 #[derive(Debug, Clone)]
 pub struct StoredMessage {
+    pub id: u64,
     pub gelf_message: GelfMessage,
     pub received_at: f64,
     pub raw_message: String,
+    pub metadata: ReceiveMetadata,
+    /// Pattern the `short_message` was grouped into
+    pub pattern_id: Option<u64>,
+    /// `timestamp` adjusted by the host's average clock skew
+    pub corrected_timestamp: f64,
 }
 
 /// Message response for API
 #[derive(Debug, Clone, Serialize)]
 pub struct MessageResponse {
+    pub id: u64,
     #[serde(flatten)]
     pub gelf_message: GelfMessage,
     pub received_at: f64,
+    pub corrected_timestamp: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern_id: Option<u64>,
 }
 
 /// Trait for parsing GELF messages
 pub trait GelfParser {
     fn parse(&self, message_str: &str) -> Result<GelfMessage, serde_json::Error>;
+
+    /// Name recorded in each message's metadata
+    fn name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
 }
 
 /// Default JSON-based GELF parser
 pub struct JsonGelfParser;
 
 impl GelfParser for JsonGelfParser {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
     fn parse(&self, message_str: &str) -> Result<GelfMessage, serde_json::Error> {
         debug!("Parsing GELF JSON message of {} characters", message_str.len());
 
@@ -58,45 +92,337 @@ impl GelfParser for JsonGelfParser {
                 Ok(gelf_msg)
             }
             Err(e) => {
+                // The content itself is not logged, it has not been redacted yet
                 debug!("GELF JSON parsing failed: {:?}", e);
-                
-                // Safe string truncation that respects UTF-8 character boundaries
-                let preview = if message_str.len() <= 100 {
-                    message_str
-                } else {
-                    // Find a safe character boundary at or before 100 bytes
-                    let mut end = 100;
-                    while end > 0 && !message_str.is_char_boundary(end) {
-                        end -= 1;
-                    }
-                    &message_str[..end]
-                };
-                
-                debug!("Failed to parse as GELF, message preview: {}", preview);
                 Err(e)
             }
         }
     }
 }
 
+/// JSON parser that also accepts level names and textual or millisecond timestamps
+///
+/// Values that had to be converted are kept in `_original_level` and
+/// `_original_timestamp`; values that cannot be understood are removed
+/// instead of rejecting the whole message.
+pub struct TolerantGelfParser;
+
+impl TolerantGelfParser {
+    /// Map a level name from syslog, log4j, java.util.logging or Python to a syslog severity
+    pub fn parse_level(value: &serde_json::Value) -> Option<u8> {
+        if let Some(number) = value.as_f64() {
+            return (number.fract() == 0.0 && (0.0..=7.0).contains(&number)).then_some(number as u8);
+        }
+        let name = value.as_str()?.trim().to_ascii_lowercase();
+        if let Ok(number) = name.parse::<u8>() {
+            return (number <= 7).then_some(number);
+        }
+        Some(match name.as_str() {
+            "emerg" | "emergency" | "panic" => 0,
+            "alert" => 1,
+            "crit" | "critical" | "fatal" => 2,
+            "err" | "error" | "severe" => 3,
+            "warn" | "warning" => 4,
+            "notice" => 5,
+            "info" | "informational" | "information" | "config" => 6,
+            "debug" | "trace" | "fine" | "finer" | "finest" | "verbose" | "all" => 7,
+            _ => return None,
+        })
+    }
+
+    /// Convert an RFC 3339/ISO 8601 string or an epoch in seconds, milliseconds,
+    /// microseconds or nanoseconds to epoch seconds
+    ///
+    /// ISO strings without a UTC offset are taken as UTC.
+    pub fn parse_timestamp(value: &serde_json::Value) -> Option<f64> {
+        let mut seconds = match value {
+            serde_json::Value::Number(number) => number.as_f64()?,
+            serde_json::Value::String(text) => {
+                let text = text.trim();
+                match text.parse::<f64>() {
+                    Ok(number) => number,
+                    Err(_) => return Self::parse_datetime(text),
+                }
+            }
+            _ => return None,
+        };
+        // "inf" and "1e400" parse as infinity, which no division brings below 1e11
+        if !seconds.is_finite() {
+            return None;
+        }
+        // Seconds only pass 1e11 in the year 5138, so larger values use finer units
+        while seconds.abs() >= 1e11 {
+            seconds /= 1000.0;
+        }
+        Some(seconds)
+    }
+
+    fn parse_datetime(text: &str) -> Option<f64> {
+        let to_seconds = |utc: chrono::DateTime<chrono::Utc>| utc.timestamp_micros() as f64 / 1e6;
+
+        if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(text) {
+            return Some(to_seconds(datetime.to_utc()));
+        }
+        for format in ["%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d %H:%M:%S%.f%z", "%Y-%m-%d %H:%M:%S%.f %z"] {
+            if let Ok(datetime) = chrono::DateTime::parse_from_str(text, format) {
+                return Some(to_seconds(datetime.to_utc()));
+            }
+        }
+        for format in ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M"] {
+            if let Ok(datetime) = chrono::NaiveDateTime::parse_from_str(text, format) {
+                return Some(to_seconds(datetime.and_utc()));
+            }
+        }
+        None
+    }
+
+    /// Replace `field` with its normalized form, keeping the original when it changed
+    fn normalize<T: Into<serde_json::Value> + Copy + PartialEq>(
+        object: &mut serde_json::Map<String, serde_json::Value>,
+        field: &str,
+        convert: impl Fn(&serde_json::Value) -> Option<T>,
+    ) {
+        let Some(original) = object.remove(field) else {
+            return;
+        };
+        if original.is_null() {
+            object.insert(field.to_string(), original);
+            return;
+        }
+
+        let converted = convert(&original);
+        let unchanged = converted.is_some_and(|value| original.is_number() && value.into().as_f64() == original.as_f64());
+        if let Some(value) = converted {
+            object.insert(field.to_string(), value.into());
+        }
+        if !unchanged {
+            debug!("Normalized GELF {} {} to {:?}", field, original, converted.map(Into::into));
+            object.insert(format!("_original_{}", field), original);
+        }
+    }
+}
+
+impl GelfParser for TolerantGelfParser {
+    fn name(&self) -> &'static str {
+        "json_tolerant"
+    }
+
+    fn parse(&self, message_str: &str) -> Result<GelfMessage, serde_json::Error> {
+        let mut value: serde_json::Value = serde_json::from_str(message_str)?;
+        if let Some(object) = value.as_object_mut() {
+            Self::normalize(object, "level", Self::parse_level);
+            Self::normalize(object, "timestamp", Self::parse_timestamp);
+        }
+        serde_json::from_value(value)
+    }
+}
+
+impl GelfMessage {
+    /// Key of an additional field in `additional_fields`, which always has the `_` prefix
+    pub fn additional_field_key(name: &str) -> String {
+        if name.starts_with('_') {
+            name.to_string()
+        } else {
+            format!("_{}", name)
+        }
+    }
+
+    fn additional_key_of(&self, name: &str) -> Option<String> {
+        [name.to_string(), Self::additional_field_key(name)]
+            .into_iter()
+            .find(|key| self.additional_fields.contains_key(key))
+    }
+
+    /// Read a standard or additional field by name
+    ///
+    /// Additional fields are found with or without their leading underscore.
+    pub fn get_field(&self, name: &str) -> Option<serde_json::Value> {
+        let value = match name {
+            "version" => serde_json::to_value(&self.version),
+            "host" => serde_json::to_value(&self.host),
+            "short_message" => serde_json::to_value(&self.short_message),
+            "full_message" => serde_json::to_value(&self.full_message),
+            "timestamp" => serde_json::to_value(self.timestamp),
+            "level" => serde_json::to_value(self.level),
+            "facility" => serde_json::to_value(&self.facility),
+            "line" => serde_json::to_value(self.line),
+            "file" => serde_json::to_value(&self.file),
+            _ => return self.additional_key_of(name).map(|key| self.additional_fields[&key].clone()),
+        };
+        value.ok().filter(|value| !value.is_null())
+    }
+
+    /// Set a standard or additional field by name
+    ///
+    /// Standard fields only accept values of their own type; new additional
+    /// fields are stored with the `_` prefix. `_id` is reserved by the GELF
+    /// specification and cannot be set.
+    pub fn set_field(&mut self, name: &str, value: serde_json::Value) -> Result<(), String> {
+        fn typed<T: serde::de::DeserializeOwned>(name: &str, value: serde_json::Value) -> Result<T, String> {
+            serde_json::from_value(value).map_err(|e| format!("invalid value for {}: {}", name, e))
+        }
+
+        match name {
+            "version" => self.version = typed(name, value)?,
+            "host" => self.host = typed(name, value)?,
+            "short_message" => self.short_message = typed(name, value)?,
+            "full_message" => self.full_message = typed(name, value)?,
+            "timestamp" => self.timestamp = typed(name, value)?,
+            "level" => self.level = typed(name, value)?,
+            "facility" => self.facility = typed(name, value)?,
+            "line" => self.line = typed(name, value)?,
+            "file" => self.file = typed(name, value)?,
+            _ => {
+                let key = self
+                    .additional_key_of(name)
+                    .unwrap_or_else(|| Self::additional_field_key(name));
+                if key == "_id" {
+                    return Err("_id is reserved by the GELF specification".to_string());
+                }
+                self.additional_fields.insert(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a standard or additional field by name, returning its value
+    pub fn remove_field(&mut self, name: &str) -> Option<serde_json::Value> {
+        let value = self.get_field(name);
+        match name {
+            "version" => self.version = None,
+            "host" => self.host = None,
+            "short_message" => self.short_message = None,
+            "full_message" => self.full_message = None,
+            "timestamp" => self.timestamp = None,
+            "level" => self.level = None,
+            "facility" => self.facility = None,
+            "line" => self.line = None,
+            "file" => self.file = None,
+            _ => {
+                let key = self.additional_key_of(name)?;
+                return self.additional_fields.remove(&key);
+            }
+        }
+        value
+    }
+
+    /// Encode as a GELF 1.1 payload for sending to another GELF server
+    ///
+    /// Required fields are filled in when missing, null fields are omitted and
+    /// additional fields are given the mandatory `_` prefix.
+    pub fn to_gelf_payload(&self, fallback_timestamp: f64) -> serde_json::Value {
+        let mut payload = serde_json::Map::new();
+        payload.insert("version".into(), "1.1".into());
+        payload.insert("host".into(), self.host.as_deref().unwrap_or("unknown").into());
+        payload.insert("short_message".into(), self.short_message.as_deref().unwrap_or("").into());
+        payload.insert("timestamp".into(), self.timestamp.unwrap_or(fallback_timestamp).into());
+
+        if let Some(full_message) = &self.full_message {
+            payload.insert("full_message".into(), full_message.as_str().into());
+        }
+        if let Some(level) = self.level {
+            payload.insert("level".into(), level.into());
+        }
+        if let Some(facility) = &self.facility {
+            payload.insert("_facility".into(), facility.as_str().into());
+        }
+        if let Some(line) = self.line {
+            payload.insert("_line".into(), line.into());
+        }
+        if let Some(file) = &self.file {
+            payload.insert("_file".into(), file.as_str().into());
+        }
+
+        for (key, value) in &self.additional_fields {
+            if value.is_null() {
+                continue;
+            }
+            let key = if key.starts_with('_') { key.clone() } else { format!("_{}", key) };
+            // `_id` is reserved by the GELF specification
+            if key != "_id" {
+                payload.insert(key, value.clone());
+            }
+        }
+
+        serde_json::Value::Object(payload)
+    }
+}
+
 impl StoredMessage {
-    pub fn new(gelf_message: GelfMessage, raw_message: String) -> Self {
+    pub fn new(id: u64, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) -> Self {
         let received_at = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs_f64();
 
         Self {
+            id,
             gelf_message,
             received_at,
             raw_message,
+            metadata,
+            pattern_id: None,
+            corrected_timestamp: received_at,
         }
     }
 
     pub fn to_response(&self) -> MessageResponse {
         MessageResponse {
+            id: self.id,
             gelf_message: self.gelf_message.clone(),
             received_at: self.received_at,
+            corrected_timestamp: self.corrected_timestamp,
+            pattern_id: self.pattern_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn level_names_and_numbers_map_to_syslog_severities() {
+        assert_eq!(TolerantGelfParser::parse_level(&json!("ERROR")), Some(3));
+        assert_eq!(TolerantGelfParser::parse_level(&json!(" warning ")), Some(4));
+        assert_eq!(TolerantGelfParser::parse_level(&json!("7")), Some(7));
+        assert_eq!(TolerantGelfParser::parse_level(&json!(0)), Some(0));
+        assert_eq!(TolerantGelfParser::parse_level(&json!(2.5)), None);
+        assert_eq!(TolerantGelfParser::parse_level(&json!("loud")), None);
+    }
+
+    #[test]
+    fn levels_above_seven_are_rejected() {
+        assert_eq!(TolerantGelfParser::parse_level(&json!(8)), None);
+        assert_eq!(TolerantGelfParser::parse_level(&json!(255)), None);
+        assert_eq!(TolerantGelfParser::parse_level(&json!("200")), None);
+    }
+
+    #[test]
+    fn timestamps_in_finer_units_are_scaled_to_seconds() {
+        assert_eq!(TolerantGelfParser::parse_timestamp(&json!(1_700_000_000)), Some(1_700_000_000.0));
+        assert_eq!(TolerantGelfParser::parse_timestamp(&json!(1_700_000_000_250u64)), Some(1_700_000_000.25));
+        assert_eq!(TolerantGelfParser::parse_timestamp(&json!("1700000000000000")), Some(1_700_000_000.0));
+        assert_eq!(TolerantGelfParser::parse_timestamp(&json!("2024-05-01T10:00:00.250Z")), Some(1_714_557_600.25));
+        assert_eq!(TolerantGelfParser::parse_timestamp(&json!("2024-05-01 12:00:00+0200")), Some(1_714_557_600.0));
+    }
+
+    #[test]
+    fn infinite_timestamps_are_rejected() {
+        for text in ["inf", "-inf", "infinity", "1e400", "NaN"] {
+            assert_eq!(TolerantGelfParser::parse_timestamp(&json!(text)), None, "{}", text);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unparseable_values_are_dropped_and_kept_as_original() {
+        let message = TolerantGelfParser
+            .parse(r#"{"short_message":"hi","level":"loud","timestamp":"inf"}"#)
+            .unwrap();
+        assert_eq!(message.level, None);
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.additional_fields["_original_level"], json!("loud"));
+        assert_eq!(message.additional_fields["_original_timestamp"], json!("inf"));
+    }
+}
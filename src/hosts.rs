@@ -0,0 +1,474 @@
+use crate::config::ConfigError;
+use crate::gelf::{GelfMessage, ReceiveMetadata};
+use crate::storage::MessageStore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Per-host tracking configuration
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostsConfig {
+    /// Messages whose timestamp is off by more than this many seconds get a `_clock_skew` field
+    pub skew_threshold_secs: f64,
+    /// Set `timestamp` to the time of arrival on messages without one
+    pub fill_missing_timestamp: bool,
+    /// Hosts that sent nothing for this long are marked as stale
+    pub silence_secs: Option<f64>,
+    /// Store a warning message when a host becomes stale
+    pub silence_warning: bool,
+    /// Hosts tracked at most; the least recently seen one is forgotten to make room
+    pub max_hosts: usize,
+    /// Sender addresses tracked at most per host
+    pub max_sources_per_host: usize,
+    /// Hosts and sender addresses that sent nothing for this long are forgotten
+    pub forget_after_secs: f64,
+}
+
+impl Default for HostsConfig {
+    fn default() -> Self {
+        Self {
+            skew_threshold_secs: 30.0,
+            fill_missing_timestamp: true,
+            silence_secs: None,
+            silence_warning: false,
+            max_hosts: 10_000,
+            max_sources_per_host: 64,
+            forget_after_secs: 7.0 * 24.0 * 3600.0,
+        }
+    }
+}
+
+/// Difference between a host's timestamps and the time its messages arrived
+///
+/// Positive values mean the host's clock is ahead of the collector's.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SkewStats {
+    /// Messages that carried a timestamp
+    pub samples: u64,
+    pub min: f64,
+    /// Exponentially weighted average, following a clock that was corrected
+    pub avg: f64,
+    pub max: f64,
+    pub last: f64,
+    /// Messages whose skew exceeded the threshold
+    pub flagged: u64,
+}
+
+/// Weight of the newest sample in the average skew, roughly the last 20 messages
+const SKEW_SMOOTHING: f64 = 0.05;
+
+impl SkewStats {
+    fn record(&mut self, skew: f64) {
+        if self.samples == 0 {
+            self.min = skew;
+            self.max = skew;
+            self.avg = skew;
+        } else {
+            self.min = self.min.min(skew);
+            self.max = self.max.max(skew);
+            self.avg += SKEW_SMOOTHING * (skew - self.avg);
+        }
+        self.samples += 1;
+        self.last = skew;
+    }
+}
+
+/// Activity of one sender address of a host
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceStats {
+    pub messages: u64,
+    pub bytes: u64,
+    pub last_seen: f64,
+}
+
+/// What is known about one sending host
+#[derive(Debug, Clone, Serialize)]
+pub struct HostInfo {
+    pub host: String,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    pub messages: u64,
+    /// Payload bytes as received, before decompression
+    pub bytes: u64,
+    /// Message counts by level; `none` counts messages without a level
+    pub levels: BTreeMap<String, u64>,
+    /// Activity by sender IP address
+    pub sources: BTreeMap<String, SourceStats>,
+    /// Nothing was received for longer than the silence threshold
+    pub stale: bool,
+    pub skew: SkewStats,
+}
+
+/// Seconds between scans for hosts and sender addresses to forget
+const SWEEP_INTERVAL_SECS: f64 = 60.0;
+
+/// Name used for messages without a `host`
+const UNKNOWN_HOST: &str = "unknown";
+
+/// Parser name of messages the collector stores itself, which are not tracked
+pub const INTERNAL_PARSER: &str = "internal";
+
+/// Host of messages the collector stores itself
+pub const INTERNAL_HOST: &str = "light-gelf-collector";
+
+fn host_name(message: &GelfMessage) -> &str {
+    message.host.as_deref().unwrap_or(UNKNOWN_HOST)
+}
+
+/// Sender IP address without the port
+fn source_ip(metadata: &ReceiveMetadata) -> Option<String> {
+    let source = metadata.source.as_deref()?;
+    let ip = match source.parse::<std::net::SocketAddr>() {
+        Ok(address) => address.ip().to_string(),
+        Err(_) => source.to_string(),
+    };
+    Some(ip)
+}
+
+/// Per-host statistics kept by the store
+///
+/// Host names and sender addresses are chosen by senders, so both are capped
+/// and forgotten after `forget_after_secs` of inactivity.
+#[derive(Debug)]
+pub struct HostTracker {
+    config: HostsConfig,
+    hosts: HashMap<String, HostInfo>,
+    last_sweep: f64,
+}
+
+impl HostTracker {
+    pub fn new(config: &HostsConfig) -> Self {
+        Self {
+            config: config.clone(),
+            hosts: HashMap::new(),
+            last_sweep: 0.0,
+        }
+    }
+
+    /// Forget hosts and sender addresses idle for longer than `forget_after_secs`
+    fn sweep(&mut self, now: f64) {
+        self.last_sweep = now;
+        let cutoff = now - self.config.forget_after_secs;
+        self.hosts.retain(|_, info| info.last_seen >= cutoff);
+        for info in self.hosts.values_mut() {
+            info.sources.retain(|_, source| source.last_seen >= cutoff);
+        }
+    }
+
+    /// Make room for a new host, forgetting the least recently seen one
+    fn evict_host(&mut self) {
+        let oldest = self
+            .hosts
+            .values()
+            .min_by(|a, b| a.last_seen.total_cmp(&b.last_seen))
+            .map(|info| info.host.clone());
+        if let Some(host) = oldest {
+            debug!("Forgetting host {} to stay within max_hosts", host);
+            self.hosts.remove(&host);
+        }
+    }
+
+    /// Record a message's activity and clock skew, flagging it or filling in a missing timestamp
+    ///
+    /// Returns the message's timestamp adjusted by its host's average skew. Only a
+    /// timestamp the sender set is adjusted; a filled-in one is `received_at` already.
+    pub fn observe(&mut self, message: &mut GelfMessage, metadata: &ReceiveMetadata, received_at: f64) -> f64 {
+        if metadata.parser == INTERNAL_PARSER {
+            return message.timestamp.unwrap_or(received_at);
+        }
+
+        if received_at - self.last_sweep >= SWEEP_INTERVAL_SECS {
+            self.sweep(received_at);
+        }
+        let host = host_name(message).to_string();
+        if !self.hosts.contains_key(&host) && self.hosts.len() >= self.config.max_hosts {
+            self.evict_host();
+        }
+        let info = self.hosts.entry(host.clone()).or_insert_with(|| HostInfo {
+            host,
+            first_seen: received_at,
+            last_seen: received_at,
+            messages: 0,
+            bytes: 0,
+            levels: BTreeMap::new(),
+            sources: BTreeMap::new(),
+            stale: false,
+            skew: SkewStats::default(),
+        });
+
+        info.last_seen = received_at;
+        info.messages += 1;
+        info.bytes += metadata.size_bytes as u64;
+        let level = message.level.map_or_else(|| "none".to_string(), |level| level.to_string());
+        *info.levels.entry(level).or_default() += 1;
+        if let Some(ip) = source_ip(metadata) {
+            if !info.sources.contains_key(&ip)
+                && info.sources.len() >= self.config.max_sources_per_host
+                && let Some(oldest) = info
+                    .sources
+                    .iter()
+                    .min_by(|a, b| a.1.last_seen.total_cmp(&b.1.last_seen))
+                    .map(|(ip, _)| ip.clone())
+            {
+                info.sources.remove(&oldest);
+            }
+            let source = info.sources.entry(ip).or_insert(SourceStats {
+                messages: 0,
+                bytes: 0,
+                last_seen: received_at,
+            });
+            source.messages += 1;
+            source.bytes += metadata.size_bytes as u64;
+            source.last_seen = received_at;
+        }
+
+        let Some(timestamp) = message.timestamp else {
+            if self.config.fill_missing_timestamp {
+                message.timestamp = Some(received_at);
+            }
+            return received_at;
+        };
+
+        let skew = timestamp - received_at;
+        info.skew.record(skew);
+        if skew.abs() > self.config.skew_threshold_secs {
+            info.skew.flagged += 1;
+            // Rounded to milliseconds, which is finer than most loggers' clocks
+            let rounded = (skew * 1000.0).round() / 1000.0;
+            message
+                .additional_fields
+                .insert("_clock_skew".to_string(), rounded.into());
+        }
+        timestamp - info.skew.avg
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Hosts sorted by name, with `stale` evaluated at `now`
+    pub fn list(&self, now: f64) -> Vec<HostInfo> {
+        let mut hosts: Vec<HostInfo> = self
+            .hosts
+            .values()
+            .map(|info| HostInfo {
+                stale: self
+                    .config
+                    .silence_secs
+                    .is_some_and(|silence| now - info.last_seen > silence),
+                ..info.clone()
+            })
+            .collect();
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        hosts
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// Warning stored when a host goes silent
+fn silence_message(info: &HostInfo, silence_secs: f64) -> GelfMessage {
+    let mut additional_fields = serde_json::Map::new();
+    additional_fields.insert("_silent_host".to_string(), info.host.clone().into());
+    additional_fields.insert("_host_last_seen".to_string(), info.last_seen.into());
+    additional_fields.insert("_silence_secs".to_string(), silence_secs.into());
+
+    GelfMessage {
+        version: Some("1.1".to_string()),
+        host: Some(INTERNAL_HOST.to_string()),
+        short_message: Some(format!(
+            "Host {} has not sent messages for {:.0} seconds",
+            info.host,
+            now() - info.last_seen
+        )),
+        full_message: None,
+        timestamp: Some(now()),
+        level: Some(4),
+        facility: Some("collector".to_string()),
+        line: None,
+        file: None,
+        additional_fields,
+    }
+}
+
+/// Periodically check for hosts that went silent and store a warning for each
+///
+/// A host is warned about once per silence; it is warned about again only
+/// after it sent messages and went silent once more.
+pub fn spawn_silence_monitor<S: MessageStore>(store: S, config: &HostsConfig) -> Result<(), ConfigError> {
+    if config.silence_secs.is_some_and(|silence| silence <= 0.0) {
+        return Err(ConfigError::Invalid("hosts.silence_secs must be positive".to_string()));
+    }
+    if config.max_hosts == 0 || config.max_sources_per_host == 0 {
+        return Err(ConfigError::Invalid(
+            "hosts.max_hosts and hosts.max_sources_per_host must be at least 1".to_string(),
+        ));
+    }
+    if config.forget_after_secs <= config.silence_secs.unwrap_or(0.0) {
+        return Err(ConfigError::Invalid(
+            "hosts.forget_after_secs must be positive and longer than silence_secs".to_string(),
+        ));
+    }
+    let silence_secs = match config.silence_secs {
+        Some(silence_secs) if config.silence_warning => silence_secs,
+        None if config.silence_warning => {
+            return Err(ConfigError::Invalid("hosts.silence_warning needs silence_secs".to_string()));
+        }
+        _ => return Ok(()),
+    };
+    info!("Warning about hosts silent for more than {} seconds", silence_secs);
+
+    let interval = Duration::from_secs_f64((silence_secs / 4.0).clamp(1.0, 30.0));
+    tokio::spawn(async move {
+        let mut warned: HashSet<String> = HashSet::new();
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let hosts = store.get_hosts().await;
+            // Forgotten hosts start over if they come back
+            let known: HashSet<&str> = hosts.iter().map(|info| info.host.as_str()).collect();
+            warned.retain(|host| known.contains(host.as_str()));
+            for info in hosts {
+                if !info.stale {
+                    warned.remove(&info.host);
+                    continue;
+                }
+                if warned.insert(info.host.clone()) {
+                    warn!("Host {} went silent", info.host);
+                    let metadata = ReceiveMetadata {
+                        parser: INTERNAL_PARSER.to_string(),
+                        ..ReceiveMetadata::default()
+                    };
+                    let message = silence_message(&info, silence_secs);
+                    let raw_message = serde_json::to_string(&message).unwrap_or_default();
+                    store.add_message(message, raw_message, metadata).await;
+                    debug!("Stored silence warning for host {}", info.host);
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(host: &str, timestamp: Option<f64>) -> GelfMessage {
+        serde_json::from_value(serde_json::json!({ "host": host, "timestamp": timestamp })).unwrap()
+    }
+
+    fn from(source: &str) -> ReceiveMetadata {
+        ReceiveMetadata {
+            source: Some(source.to_string()),
+            size_bytes: 100,
+            ..ReceiveMetadata::default()
+        }
+    }
+
+    fn hosts(tracker: &HostTracker) -> Vec<String> {
+        tracker.list(0.0).into_iter().map(|info| info.host).collect()
+    }
+
+    #[test]
+    fn least_recently_seen_host_is_forgotten_at_the_cap() {
+        let mut tracker = HostTracker::new(&HostsConfig {
+            max_hosts: 2,
+            ..HostsConfig::default()
+        });
+        tracker.observe(&mut message("a", None), &from("10.0.0.1:1"), 1.0);
+        tracker.observe(&mut message("b", None), &from("10.0.0.2:1"), 2.0);
+        tracker.observe(&mut message("a", None), &from("10.0.0.1:1"), 3.0);
+        tracker.observe(&mut message("c", None), &from("10.0.0.3:1"), 4.0);
+
+        assert_eq!(hosts(&tracker), ["a", "c"]);
+    }
+
+    #[test]
+    fn sources_are_capped_per_host() {
+        let mut tracker = HostTracker::new(&HostsConfig {
+            max_sources_per_host: 2,
+            ..HostsConfig::default()
+        });
+        for (i, source) in ["10.0.0.1:1", "10.0.0.2:1", "10.0.0.1:2", "10.0.0.3:1"].iter().enumerate() {
+            tracker.observe(&mut message("a", None), &from(source), i as f64);
+        }
+
+        let sources: Vec<String> = tracker.list(0.0)[0].sources.keys().cloned().collect();
+        assert_eq!(sources, ["10.0.0.1", "10.0.0.3"]);
+        assert_eq!(tracker.list(0.0)[0].messages, 4);
+    }
+
+    #[test]
+    fn idle_hosts_and_sources_are_forgotten() {
+        let mut tracker = HostTracker::new(&HostsConfig {
+            forget_after_secs: 100.0,
+            ..HostsConfig::default()
+        });
+        tracker.observe(&mut message("idle", None), &from("10.0.0.1:1"), 1000.0);
+        tracker.observe(&mut message("busy", None), &from("10.0.0.2:1"), 1000.0);
+        tracker.observe(&mut message("busy", None), &from("10.0.0.3:1"), 1090.0);
+        tracker.observe(&mut message("busy", None), &from("10.0.0.3:1"), 1150.0);
+
+        assert_eq!(hosts(&tracker), ["busy"]);
+        let sources: Vec<String> = tracker.list(0.0)[0].sources.keys().cloned().collect();
+        assert_eq!(sources, ["10.0.0.3"]);
+    }
+
+    #[test]
+    fn average_skew_follows_a_corrected_clock() {
+        let mut tracker = HostTracker::new(&HostsConfig::default());
+        for i in 0..100 {
+            let received_at = 1000.0 + i as f64;
+            tracker.observe(&mut message("a", Some(received_at + 60.0)), &from("10.0.0.1:1"), received_at);
+        }
+        for i in 100..200 {
+            let received_at = 1000.0 + i as f64;
+            tracker.observe(&mut message("a", Some(received_at)), &from("10.0.0.1:1"), received_at);
+        }
+
+        let skew = &tracker.list(0.0)[0].skew;
+        assert_eq!((skew.samples, skew.min, skew.max, skew.flagged), (200, 0.0, 60.0, 100));
+        assert!(skew.avg < 1.0, "average skew still {}", skew.avg);
+        assert!(tracker.observe(&mut message("a", Some(2000.0)), &from("10.0.0.1:1"), 2000.0) > 1999.0);
+    }
+
+    #[test]
+    fn filled_in_timestamps_are_not_corrected() {
+        let mut tracker = HostTracker::new(&HostsConfig {
+            fill_missing_timestamp: true,
+            ..HostsConfig::default()
+        });
+        for i in 0..20 {
+            let received_at = 1000.0 + i as f64;
+            tracker.observe(&mut message("a", Some(received_at + 60.0)), &from("10.0.0.1:1"), received_at);
+        }
+
+        let mut missing = message("a", None);
+        let corrected = tracker.observe(&mut missing, &from("10.0.0.1:1"), 2000.0);
+        assert_eq!(corrected, 2000.0);
+        assert_eq!(missing.timestamp, Some(2000.0));
+        assert!(tracker.observe(&mut message("a", Some(2060.0)), &from("10.0.0.1:1"), 2000.0) < 2060.0);
+    }
+
+    #[test]
+    fn silence_warning_fields_do_not_collide_with_dedup_fields() {
+        let mut tracker = HostTracker::new(&HostsConfig::default());
+        tracker.observe(&mut message("a", None), &from("10.0.0.1:1"), 1000.0);
+        let warning = silence_message(&tracker.list(2000.0)[0], 300.0);
+
+        assert_eq!(warning.additional_fields["_host_last_seen"], 1000.0);
+        assert!(!warning.additional_fields.contains_key("_last_seen"));
+    }
+}
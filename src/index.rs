@@ -0,0 +1,304 @@
+use crate::gelf::GelfMessage;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Approximate per-entry overheads used for memory accounting
+const TERM_OVERHEAD_BYTES: usize = 64;
+const POSTING_BYTES: usize = 16;
+
+/// Split text into lowercase alphanumeric tokens
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+}
+
+/// Returns the texts of a message that are covered by the index
+fn indexed_texts(message: &GelfMessage) -> impl Iterator<Item = &str> {
+    let additional = message
+        .additional_fields
+        .values()
+        .filter_map(|value| value.as_str());
+
+    [&message.short_message, &message.full_message]
+        .into_iter()
+        .flatten()
+        .map(|text| text.as_str())
+        .chain(additional)
+}
+
+fn document_terms(message: &GelfMessage) -> HashSet<String> {
+    indexed_texts(message).flat_map(tokenize).collect()
+}
+
+/// A single clause of a search query
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchClause {
+    Term(String),
+    Phrase(Vec<String>),
+}
+
+impl SearchClause {
+    fn terms(&self) -> &[String] {
+        match self {
+            SearchClause::Term(term) => std::slice::from_ref(term),
+            SearchClause::Phrase(terms) => terms,
+        }
+    }
+}
+
+/// Full-text search query in disjunctive normal form
+///
+/// Whitespace-separated clauses are combined with AND, `OR` separates
+/// alternatives and double quotes group a phrase, e.g.
+/// `timeout database OR "connection refused"`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    alternatives: Vec<Vec<SearchClause>>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut alternatives = Vec::new();
+        let mut current = Vec::new();
+        let mut chars = query.char_indices().peekable();
+
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+
+            if c == '"' {
+                chars.next();
+                let mut phrase = String::new();
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                    phrase.push(c);
+                }
+                if !closed {
+                    return Err(format!("unterminated phrase starting at position {}", start));
+                }
+
+                let terms: Vec<String> = tokenize(&phrase).collect();
+                match terms.len() {
+                    0 => {}
+                    1 => current.push(SearchClause::Term(terms.into_iter().next().unwrap())),
+                    _ => current.push(SearchClause::Phrase(terms)),
+                }
+                continue;
+            }
+
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+
+            match word.as_str() {
+                "OR" => {
+                    if current.is_empty() {
+                        return Err(format!("OR without a left-hand term at position {}", start));
+                    }
+                    alternatives.push(std::mem::take(&mut current));
+                }
+                "AND" => {}
+                _ => current.extend(tokenize(&word).map(SearchClause::Term)),
+            }
+        }
+
+        if current.is_empty() {
+            if alternatives.is_empty() {
+                return Err("query contains no search terms".to_string());
+            }
+            return Err("OR without a right-hand term".to_string());
+        }
+        alternatives.push(current);
+
+        Ok(Self { alternatives })
+    }
+}
+
+/// Inverted index from tokens to the ids of messages containing them
+#[derive(Debug, Default)]
+pub struct InvertedIndex {
+    postings: HashMap<String, BTreeSet<u64>>,
+    posting_count: usize,
+    term_bytes: usize,
+}
+
+impl InvertedIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, id: u64, message: &GelfMessage) {
+        for term in document_terms(message) {
+            let term_len = term.len();
+            let ids = self.postings.entry(term).or_insert_with(|| {
+                self.term_bytes += term_len;
+                BTreeSet::new()
+            });
+            if ids.insert(id) {
+                self.posting_count += 1;
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: u64, message: &GelfMessage) {
+        for term in document_terms(message) {
+            if let Some(ids) = self.postings.get_mut(&term) {
+                if ids.remove(&id) {
+                    self.posting_count -= 1;
+                }
+                if ids.is_empty() {
+                    self.postings.remove(&term);
+                    self.term_bytes -= term.len();
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.postings.clear();
+        self.posting_count = 0;
+        self.term_bytes = 0;
+    }
+
+    /// Evaluate a query, returning matching ids newest first.
+    ///
+    /// Phrases are verified against the message text returned by `lookup`.
+    pub fn search<'a, F>(&self, query: &SearchQuery, lookup: F) -> Vec<u64>
+    where
+        F: Fn(u64) -> Option<&'a GelfMessage>,
+    {
+        let mut matches = BTreeSet::new();
+
+        for clauses in &query.alternatives {
+            // A term missing from the index means nothing can match this alternative
+            let Some(mut postings) = clauses
+                .iter()
+                .flat_map(|clause| clause.terms())
+                .map(|term| self.postings.get(term))
+                .collect::<Option<Vec<&BTreeSet<u64>>>>()
+            else {
+                continue;
+            };
+            // Walk the rarest term's ids and probe the others, so common terms cost lookups rather than copies
+            postings.sort_unstable_by_key(|ids| ids.len());
+            let Some((smallest, rest)) = postings.split_first() else {
+                continue;
+            };
+            let candidates = smallest
+                .iter()
+                .copied()
+                .filter(|id| rest.iter().all(|ids| ids.contains(id)));
+
+            let phrases: Vec<&[String]> = clauses
+                .iter()
+                .filter_map(|clause| match clause {
+                    SearchClause::Phrase(terms) => Some(terms.as_slice()),
+                    SearchClause::Term(_) => None,
+                })
+                .collect();
+
+            for id in candidates {
+                let verified = phrases.is_empty()
+                    || lookup(id).is_some_and(|message| {
+                        phrases.iter().all(|phrase| contains_phrase(message, phrase))
+                    });
+                if verified {
+                    matches.insert(id);
+                }
+            }
+        }
+
+        matches.into_iter().rev().collect()
+    }
+
+    pub fn term_count(&self) -> usize {
+        self.postings.len()
+    }
+
+    pub fn posting_count(&self) -> usize {
+        self.posting_count
+    }
+
+    /// Estimated heap usage of the index in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.term_bytes
+            + self.postings.len() * TERM_OVERHEAD_BYTES
+            + self.posting_count * POSTING_BYTES
+    }
+}
+
+fn contains_phrase(message: &GelfMessage, phrase: &[String]) -> bool {
+    indexed_texts(message).any(|text| {
+        let tokens: Vec<String> = tokenize(text).collect();
+        tokens.windows(phrase.len()).any(|window| window == phrase)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn indexed(texts: &[&str]) -> (InvertedIndex, HashMap<u64, GelfMessage>) {
+        let mut index = InvertedIndex::new();
+        let mut messages = HashMap::new();
+        for (id, text) in texts.iter().enumerate() {
+            let message: GelfMessage = serde_json::from_value(serde_json::json!({ "short_message": text })).unwrap();
+            index.insert(id as u64, &message);
+            messages.insert(id as u64, message);
+        }
+        (index, messages)
+    }
+
+    fn search(texts: &[&str], query: &str) -> Vec<u64> {
+        let (index, messages) = indexed(texts);
+        index.search(&SearchQuery::parse(query).unwrap(), |id| messages.get(&id))
+    }
+
+    const TEXTS: [&str; 4] = [
+        "database timeout after 30s",
+        "connection refused by database",
+        "refused connection to cache",
+        "cache timeout",
+    ];
+
+    #[test]
+    fn terms_are_intersected() {
+        assert_eq!(search(&TEXTS, "timeout"), vec![3, 0]);
+        assert_eq!(search(&TEXTS, "Database TIMEOUT"), vec![0]);
+        assert_eq!(search(&TEXTS, "timeout database cache"), Vec::<u64>::new());
+        assert_eq!(search(&TEXTS, "timeout nowhere"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn alternatives_are_united() {
+        assert_eq!(search(&TEXTS, "cache OR database timeout"), vec![3, 2, 0]);
+        assert_eq!(search(&TEXTS, "nowhere OR cache"), vec![3, 2]);
+    }
+
+    #[test]
+    fn phrases_need_adjacent_terms() {
+        assert_eq!(search(&TEXTS, "\"connection refused\""), vec![1]);
+        assert_eq!(search(&TEXTS, "refused connection"), vec![2, 1]);
+    }
+
+    #[test]
+    fn removed_messages_are_not_found() {
+        let (mut index, messages) = indexed(&TEXTS);
+        index.remove(0, &messages[&0]);
+        let query = SearchQuery::parse("timeout").unwrap();
+        assert_eq!(index.search(&query, |id| messages.get(&id)), vec![3]);
+        assert_eq!(index.posting_count(), 10);
+    }
+}
@@ -1,12 +1,27 @@
 // Core library modules
+pub mod aggregate;
+pub mod alerting;
 pub mod config;
 pub mod compression;
+pub mod dedup;
+pub mod export;
+pub mod fields;
+pub mod filter;
 pub mod gelf;
+pub mod hosts;
+pub mod index;
+pub mod output;
+pub mod patterns;
+pub mod processing;
+pub mod query;
 pub mod storage;
+pub mod streams;
+pub mod tenants;
 pub mod web;
 pub mod udp_handler;
 
 // Re-export commonly used types
 pub use config::Config;
+pub use filter::MessageFilter;
 pub use gelf::{GelfMessage, MessageResponse, StoredMessage};
-pub use storage::{MessageStore, InMemoryMessageStore};
\ No newline at end of file
+pub use storage::{MessageStore, InMemoryMessageStore, MessageContext, StoreEvent};
\ No newline at end of file
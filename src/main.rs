@@ -1,15 +1,53 @@
-use clap::Parser;
 use light_gelf_collector_rs::{Config, InMemoryMessageStore};
 use std::sync::Arc;
 use tokio::net::UdpSocket;
 use tracing::{debug, error, info};
 
-use light_gelf_collector_rs::udp_handler::handle_udp_messages;
+use light_gelf_collector_rs::alerting::AlertEngine;
+use light_gelf_collector_rs::gelf::{GelfParser, JsonGelfParser, TolerantGelfParser};
+use light_gelf_collector_rs::hosts::spawn_silence_monitor;
+use light_gelf_collector_rs::output::{spawn_outputs, Outputs};
+use light_gelf_collector_rs::processing::Pipeline;
+use light_gelf_collector_rs::streams::StreamRouter;
+use light_gelf_collector_rs::tenants::TenantRouter;
+use light_gelf_collector_rs::udp_handler::UdpMessageHandler;
 use light_gelf_collector_rs::web::create_routes;
 
+fn spawn_udp_handler<P: GelfParser + Send + Sync + 'static>(
+    handler: UdpMessageHandler<TenantRouter, P>,
+) -> tokio::task::JoinHandle<()> {
+    debug!("Spawning UDP message handler task with {} parser", handler.parser_name());
+    tokio::spawn(async move {
+        debug!("UDP message handler task started");
+        handler.run().await;
+    })
+}
 
-
-
+/// Spawn a handler for a bound socket with the configured parser
+fn spawn_udp_listener(
+    socket: Arc<UdpSocket>,
+    tenants: TenantRouter,
+    config: &Config,
+    pipeline: Arc<Pipeline>,
+    alerts: Arc<AlertEngine>,
+    outputs: Outputs,
+) -> tokio::task::JoinHandle<()> {
+    if config.tolerant_parsing {
+        spawn_udp_handler(
+            UdpMessageHandler::with_parser(socket, tenants, TolerantGelfParser)
+                .with_pipeline(pipeline)
+                .with_alerts(alerts)
+                .with_outputs(outputs),
+        )
+    } else {
+        spawn_udp_handler(
+            UdpMessageHandler::with_parser(socket, tenants, JsonGelfParser)
+                .with_pipeline(pipeline)
+                .with_alerts(alerts)
+                .with_outputs(outputs),
+        )
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -23,15 +61,65 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     debug!("Tracing initialized with debug level");
 
-    let config = Config::parse();
+    let config = Config::load()?;
     debug!(
         "Parsed command line arguments: UDP port: {}, HTTP port: {}, bind address: {}, max messages: {}",
         config.udp_port, config.http_port, config.bind_address, config.max_messages
     );
 
-    let store = InMemoryMessageStore::new(config.max_messages);
+    let store = InMemoryMessageStore::new(config.max_messages)
+        .with_shards(config.store_shards.into())
+        .with_dedup(&config.file.dedup)
+        .with_hosts(&config.file.hosts);
     debug!("Created log store with max capacity: {}", config.max_messages);
 
+    let router = StreamRouter::from_config(
+        store.clone(),
+        &config.file.streams,
+        &config.file.dedup,
+        &config.file.hosts,
+    )?;
+    if !config.file.streams.is_empty() {
+        info!("Routing messages to {} streams besides the default one", config.file.streams.len());
+    }
+    let tenants = TenantRouter::from_config(
+        router,
+        &config.file.tenancy,
+        config.udp_port,
+        &config.file.streams,
+        &config.file.dedup,
+        &config.file.hosts,
+    )?;
+    if tenants.is_enabled() {
+        info!("Separating messages of {} tenants", config.file.tenancy.tenants.len());
+    }
+    tenants.spawn_expiry();
+
+    // Start configured outputs before any message arrives
+    let outputs = spawn_outputs(&store, &config.file.outputs)?;
+    spawn_silence_monitor(store.clone(), &config.file.hosts)?;
+    for tenant_store in tenants.tenant_stores() {
+        spawn_silence_monitor(tenant_store, &config.file.hosts)?;
+    }
+
+    let pipeline = Arc::new(Pipeline::from_config(&config.file.processors)?);
+    if !pipeline.is_empty() {
+        info!("Loaded {} message processors", config.file.processors.len());
+        store.register_stats("pipeline", pipeline.clone());
+        for tenant_store in tenants.tenant_stores() {
+            tenant_store.register_stats("pipeline", pipeline.clone());
+        }
+    }
+
+    let alerts = Arc::new(AlertEngine::new(&config.file.alerts)?);
+    if !config.file.alerts.rules.is_empty() {
+        info!("Loaded {} alert rules", config.file.alerts.rules.len());
+        store.register_stats("alerts", alerts.clone());
+        for tenant_store in tenants.tenant_stores() {
+            tenant_store.register_stats("alerts", alerts.clone());
+        }
+    }
+
     info!("Starting GELF collector...");
     info!("UDP port: {}", config.udp_port);
     info!("HTTP port: {}", config.http_port);
@@ -46,16 +134,33 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     debug!("UDP socket successfully bound and ready to receive messages");
 
     // Start UDP message handler
-    let store_clone = store.clone();
-    debug!("Spawning UDP message handler task");
-    let udp_task = tokio::spawn(async move {
-        debug!("UDP message handler task started");
-        handle_udp_messages(socket, store_clone).await;
-    });
+    let udp_task = spawn_udp_listener(
+        socket,
+        tenants.clone(),
+        &config,
+        pipeline.clone(),
+        alerts.clone(),
+        outputs.clone(),
+    );
+
+    // Tenants' own ports are served by the same pipeline, alert rules and outputs
+    for port in tenants.udp_ports() {
+        let addr = format!("{}:{}", config.bind_address, port).parse::<std::net::SocketAddr>()?;
+        let socket = Arc::new(UdpSocket::bind(addr).await?);
+        info!("UDP listener for tenant port started on {}", addr);
+        spawn_udp_listener(
+            socket,
+            tenants.clone(),
+            &config,
+            pipeline.clone(),
+            alerts.clone(),
+            outputs.clone(),
+        );
+    }
 
     // Setup HTTP routes
     debug!("Setting up HTTP routes");
-    let routes = create_routes(store);
+    let routes = create_routes(tenants, config.clone(), alerts);
 
     // Start HTTP server
     let http_addr = config.http_addr()?;
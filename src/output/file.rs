@@ -0,0 +1,569 @@
+use super::{Output, OutputMessage};
+use crate::config::ConfigError;
+use crate::query::{field_value, Query};
+use crate::storage::StatsProvider;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info};
+
+/// How often buffered writes are flushed and housekeeping runs
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often old archives are checked against the retention period
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Files not written for this long are closed (and rotated)
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Messages waiting for the writer before new ones are dropped
+const CHANNEL_CAPACITY: usize = 10_000;
+/// Suffix of rotated files: the rotation time, a counter if that name was taken, and `.gz` once compressed
+const ROTATED_SUFFIX: &str = r"\.\d{8}T\d{6}\.\d{3}(-\d+)?(\.gz)?";
+
+fn default_max_size_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_max_open_files() -> usize {
+    64
+}
+
+/// Append-only NDJSON archive on local disk
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileOutputConfig {
+    /// Path template; supports `{host}`, `{facility}`, `{level}`, `{date}`,
+    /// `{hour}` and additional fields such as `{_service}`
+    pub path: String,
+    /// Only archive messages matching this query
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Rotate a file once it reaches this size
+    #[serde(default = "default_max_size_bytes")]
+    pub max_size_bytes: u64,
+    /// Rotate a file once it has been open this long, e.g. 3600 for hourly
+    #[serde(default)]
+    pub rotate_interval_secs: Option<u64>,
+    /// Gzip files after they are rotated
+    #[serde(default)]
+    pub compress: bool,
+    /// Delete rotated files older than this
+    #[serde(default)]
+    pub retention_secs: Option<u64>,
+    /// Files kept open at once; opening another rotates the least recently written one
+    #[serde(default = "default_max_open_files")]
+    pub max_open_files: usize,
+}
+
+struct OpenFile {
+    writer: BufWriter<File>,
+    size: u64,
+    opened_at: Instant,
+    last_write: Instant,
+}
+
+#[derive(Default)]
+struct FileOutputStats {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    filtered: AtomicU64,
+    rotations: AtomicU64,
+    deleted: AtomicU64,
+    errors: AtomicU64,
+    open_files: AtomicU64,
+}
+
+/// Where the rotated files of a path template can be, for retention
+struct ArchiveLayout {
+    /// Longest leading directory of the template without placeholders
+    root: PathBuf,
+    /// Names of the directory levels below `root`
+    dirs: Vec<Regex>,
+    /// Names of rotated files
+    rotated: Regex,
+}
+
+impl ArchiveLayout {
+    fn new(template: &str) -> Self {
+        let mut components: Vec<String> = Path::new(template)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let file_name = components.pop().unwrap_or_default();
+        let fixed = components
+            .iter()
+            .position(|component| component.contains('{'))
+            .unwrap_or(components.len());
+        let root: PathBuf = components[..fixed].iter().collect();
+
+        Self {
+            root: if root.as_os_str().is_empty() { PathBuf::from(".") } else { root },
+            dirs: components[fixed..]
+                .iter()
+                .map(|component| template_regex(component, ""))
+                .collect(),
+            rotated: template_regex(&file_name, ROTATED_SUFFIX),
+        }
+    }
+
+    /// Rotated files under the root, however long ago they were written
+    fn rotated_files(&self) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+        self.walk(&self.root, &self.dirs, &mut found);
+        found
+    }
+
+    fn walk(&self, dir: &Path, levels: &[Regex], found: &mut Vec<PathBuf>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            match levels.split_first() {
+                Some((level, below)) if file_type.is_dir() && level.is_match(name) => {
+                    self.walk(&entry.path(), below, found)
+                }
+                None if file_type.is_file() && self.rotated.is_match(name) => found.push(entry.path()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Writes every accepted message to rotating files
+pub struct FileOutput {
+    config: FileOutputConfig,
+    filter: Option<Query>,
+    layout: ArchiveLayout,
+    stats: FileOutputStats,
+    sender: SyncSender<Arc<OutputMessage>>,
+    receiver: Mutex<Option<Receiver<Arc<OutputMessage>>>>,
+}
+
+impl FileOutput {
+    pub fn new(config: FileOutputConfig) -> Result<Self, ConfigError> {
+        let filter = config
+            .filter
+            .as_deref()
+            .map(Query::parse)
+            .transpose()
+            .map_err(|e| ConfigError::Invalid(format!("file output filter for {}: {}", config.path, e)))?;
+
+        if config.path.trim().is_empty() {
+            return Err(ConfigError::Invalid("file output path must not be empty".to_string()));
+        }
+        if config.max_open_files == 0 {
+            return Err(ConfigError::Invalid(format!(
+                "file output max_open_files for {} must be at least 1",
+                config.path
+            )));
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        Ok(Self {
+            layout: ArchiveLayout::new(&config.path),
+            config,
+            filter,
+            stats: FileOutputStats::default(),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        })
+    }
+
+    /// Start the writer; messages arrive through `offer`
+    ///
+    /// Writes, renames and directory scans block, so the writer runs on its
+    /// own thread rather than on the async runtime.
+    pub fn spawn(self: Arc<Self>) {
+        let Some(rx) = self.receiver.lock().unwrap().take() else {
+            return;
+        };
+        let path = self.config.path.clone();
+        if let Err(e) = std::thread::Builder::new()
+            .name("file-output".to_string())
+            .spawn(move || self.run(rx))
+        {
+            error!("Failed to start file output {}: {}", path, e);
+        }
+    }
+
+    fn run(&self, rx: Receiver<Arc<OutputMessage>>) {
+        let mut files: HashMap<PathBuf, OpenFile> = HashMap::new();
+        let mut last_tick = Instant::now();
+        let mut last_retention_check = Instant::now();
+
+        loop {
+            match rx.recv_timeout(TICK_INTERVAL.saturating_sub(last_tick.elapsed())) {
+                Ok(message) => {
+                    let path = PathBuf::from(render_path(&self.config.path, &message));
+                    if let Err(e) = self.write(&mut files, path, &message) {
+                        error!("Failed to write archive file: {}", e);
+                        self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if last_tick.elapsed() >= TICK_INTERVAL {
+                last_tick = Instant::now();
+                self.housekeeping(&mut files);
+                if last_retention_check.elapsed() >= RETENTION_CHECK_INTERVAL {
+                    last_retention_check = Instant::now();
+                    self.enforce_retention();
+                }
+            }
+        }
+
+        for (path, file) in files.drain() {
+            self.close(path, file);
+        }
+    }
+
+    fn write(&self, files: &mut HashMap<PathBuf, OpenFile>, path: PathBuf, message: &OutputMessage) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+
+        if !files.contains_key(&path) {
+            // Hostnames come from senders, so a spray of them must not exhaust file descriptors
+            if files.len() >= self.config.max_open_files {
+                let least_recent = files
+                    .iter()
+                    .min_by_key(|(_, file)| file.last_write)
+                    .map(|(path, _)| path.clone());
+                if let Some(file) = least_recent.and_then(|least_recent| files.remove_entry(&least_recent)) {
+                    debug!("Closing archive file {} to stay within max_open_files", file.0.display());
+                    self.close(file.0, file.1);
+                }
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let size = file.metadata()?.len();
+            debug!("Opened archive file {}", path.display());
+            files.insert(
+                path.clone(),
+                OpenFile {
+                    writer: BufWriter::new(file),
+                    size,
+                    opened_at: Instant::now(),
+                    last_write: Instant::now(),
+                },
+            );
+            self.stats.open_files.store(files.len() as u64, Ordering::Relaxed);
+        }
+
+        let Some(file) = files.get_mut(&path) else {
+            return Ok(());
+        };
+        file.writer.write_all(&line)?;
+        file.size += line.len() as u64;
+        file.last_write = Instant::now();
+        self.stats.written.fetch_add(1, Ordering::Relaxed);
+
+        if file.size >= self.config.max_size_bytes {
+            if let Some(file) = files.remove(&path) {
+                self.close(path, file);
+            }
+            self.stats.open_files.store(files.len() as u64, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    /// Flush buffers and rotate files that are due by age or idle
+    fn housekeeping(&self, files: &mut HashMap<PathBuf, OpenFile>) {
+        let interval = self.config.rotate_interval_secs.map(Duration::from_secs);
+        let due: Vec<PathBuf> = files
+            .iter()
+            .filter(|(_, file)| {
+                interval.is_some_and(|interval| file.opened_at.elapsed() >= interval)
+                    || file.last_write.elapsed() >= IDLE_TIMEOUT
+            })
+            .map(|(path, _)| path.clone())
+            .collect();
+
+        for path in due {
+            if let Some(file) = files.remove(&path) {
+                self.close(path, file);
+            }
+        }
+        self.stats.open_files.store(files.len() as u64, Ordering::Relaxed);
+
+        for (path, file) in files.iter_mut() {
+            if let Err(e) = file.writer.flush() {
+                error!("Failed to flush archive file {}: {}", path.display(), e);
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Close an active file and move it aside so the path can be reused
+    fn close(&self, path: PathBuf, mut file: OpenFile) {
+        if let Err(e) = file.writer.flush() {
+            error!("Failed to flush archive file {}: {}", path.display(), e);
+            self.stats.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        drop(file);
+
+        let rotated = rotated_path(&path);
+        if let Err(e) = fs::rename(&path, &rotated) {
+            error!("Failed to rotate archive file {}: {}", path.display(), e);
+            self.stats.errors.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.stats.rotations.fetch_add(1, Ordering::Relaxed);
+        info!("Rotated archive file {} to {}", path.display(), rotated.display());
+
+        if self.config.compress {
+            // Compression can take a while on large files, keep it off the writer
+            std::thread::spawn(move || {
+                if let Err(e) = compress_file(&rotated) {
+                    error!("Failed to compress archive file {}: {}", rotated.display(), e);
+                }
+            });
+        }
+    }
+
+    /// Delete rotated files older than the retention period
+    ///
+    /// The directories the path template can render to are scanned, so files
+    /// left by earlier runs or by hosts not seen since are deleted too.
+    fn enforce_retention(&self) {
+        let Some(retention) = self.config.retention_secs.map(Duration::from_secs) else {
+            return;
+        };
+
+        for path in self.layout.rotated_files() {
+            let expired = fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                .is_some_and(|age| age >= retention);
+            if !expired {
+                continue;
+            }
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    info!("Deleted expired archive file {}", path.display());
+                    self.stats.deleted.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(e) => {
+                    error!("Failed to delete archive file {}: {}", path.display(), e);
+                    self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+impl Output for FileOutput {
+    fn offer(&self, message: &Arc<OutputMessage>) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !filter.matches(&message.gelf_message, message.received_at))
+        {
+            self.stats.filtered.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        if self.sender.try_send(message.clone()).is_err() {
+            self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl StatsProvider for FileOutput {
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "path": self.config.path,
+            "written": self.stats.written.load(Ordering::Relaxed),
+            "dropped": self.stats.dropped.load(Ordering::Relaxed),
+            "filtered": self.stats.filtered.load(Ordering::Relaxed),
+            "rotations": self.stats.rotations.load(Ordering::Relaxed),
+            "deleted": self.stats.deleted.load(Ordering::Relaxed),
+            "errors": self.stats.errors.load(Ordering::Relaxed),
+            "open_files": self.stats.open_files.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Name for a file being rotated that no earlier rotation of the same path used
+fn rotated_path(path: &Path) -> PathBuf {
+    let base = format!("{}.{}", path.display(), Utc::now().format("%Y%m%dT%H%M%S%.3f"));
+    let taken = |candidate: &str| Path::new(candidate).exists() || Path::new(&format!("{}.gz", candidate)).exists();
+    let mut candidate = base.clone();
+    let mut counter = 1;
+    while taken(&candidate) {
+        candidate = format!("{}-{}", base, counter);
+        counter += 1;
+    }
+    PathBuf::from(candidate)
+}
+
+/// Regex matching what a path template component renders to, followed by `suffix`
+fn template_regex(template: &str, suffix: &str) -> Regex {
+    let mut pattern = String::from("^");
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        pattern.push_str(&regex::escape(&rest[..start]));
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        // Placeholder values are sanitized, so they never contain a path separator
+        pattern.push_str(".+");
+        rest = &rest[start + end + 1..];
+    }
+
+    pattern.push_str(&regex::escape(rest));
+    pattern.push_str(suffix);
+    pattern.push('$');
+    Regex::new(&pattern).expect("escaped template is a valid regex")
+}
+
+fn compress_file(path: &Path) -> std::io::Result<()> {
+    let compressed_path = PathBuf::from(format!("{}.gz", path.display()));
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(&compressed_path)?, Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)?;
+    debug!("Compressed archive file to {}", compressed_path.display());
+    Ok(())
+}
+
+/// Keep placeholder values from escaping the archive directory
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    match cleaned.trim_matches('.') {
+        "" => "unknown".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// Substitute `{placeholder}`s in a path template for one message
+fn render_path(template: &str, message: &OutputMessage) -> String {
+    let received = DateTime::<Utc>::from_timestamp(message.received_at as i64, 0).unwrap_or_default();
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = &rest[start + 1..start + end];
+        let value = match name {
+            "date" => received.format("%Y-%m-%d").to_string(),
+            "hour" => received.format("%H").to_string(),
+            field => field_value(&message.gelf_message, message.received_at, field)
+                .map(|value| sanitize(&value.as_text()))
+                .unwrap_or_else(|| "unknown".to_string()),
+        };
+        rendered.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layout_splits_the_fixed_root_from_templated_levels() {
+        let layout = ArchiveLayout::new("/var/log/gelf/{host}/{date}.ndjson");
+        assert_eq!(layout.root, PathBuf::from("/var/log/gelf"));
+        assert_eq!(layout.dirs.len(), 1);
+        assert!(layout.dirs[0].is_match("web-01"));
+
+        assert!(layout.rotated.is_match("2024-05-01.ndjson.20240501T120000.123"));
+        assert!(layout.rotated.is_match("2024-05-01.ndjson.20240501T120000.123-2.gz"));
+        assert!(!layout.rotated.is_match("2024-05-01.ndjson"));
+        assert!(!layout.rotated.is_match("2024-05-01.txt.20240501T120000.123"));
+
+        let relative = ArchiveLayout::new("archive-{host}.ndjson");
+        assert_eq!(relative.root, PathBuf::from("."));
+        assert!(relative.dirs.is_empty());
+        assert!(relative.rotated.is_match("archive-db.ndjson.20240501T120000.123"));
+    }
+
+    #[test]
+    fn template_literals_are_escaped() {
+        let pattern = template_regex("a.b+{x}", "");
+        assert!(pattern.is_match("a.b+anything"));
+        assert!(!pattern.is_match("aXbb+anything"));
+        assert!(template_regex("plain{unclosed", "").is_match("plain{unclosed"));
+    }
+
+    #[test]
+    fn rotated_files_are_found_in_every_templated_directory() {
+        let root = std::env::temp_dir().join(format!("gelf-layout-{}", std::process::id()));
+        for (dir, name) in [
+            ("web", "app.ndjson.20240501T120000.123"),
+            ("db", "app.ndjson.20240501T120000.123.gz"),
+            ("db", "app.ndjson"),
+            ("db", "notes.txt"),
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            File::create(root.join(dir).join(name)).unwrap();
+        }
+
+        let layout = ArchiveLayout::new(&format!("{}/{{host}}/app.ndjson", root.display()));
+        let mut found = layout.rotated_files();
+        found.sort();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            found,
+            vec![
+                root.join("db/app.ndjson.20240501T120000.123.gz"),
+                root.join("web/app.ndjson.20240501T120000.123"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rotation_never_reuses_a_name() {
+        let root = std::env::temp_dir().join(format!("gelf-rotate-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("app.ndjson");
+
+        let mut names = Vec::new();
+        for _ in 0..3 {
+            let rotated = rotated_path(&path);
+            File::create(&rotated).unwrap();
+            names.push(rotated);
+        }
+        let layout = ArchiveLayout::new(&path.display().to_string());
+        let found = layout.rotated_files().len();
+        fs::remove_dir_all(&root).unwrap();
+
+        names.dedup();
+        assert_eq!(names.len(), 3);
+        assert_eq!(found, 3);
+    }
+}
@@ -0,0 +1,477 @@
+use super::{Output, OutputMessage};
+use crate::config::ConfigError;
+use crate::query::Query;
+use crate::storage::StatsProvider;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+/// GELF chunked UDP magic bytes
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+/// Size of the chunk header: magic, message id, sequence number and count
+const CHUNK_HEADER_SIZE: usize = 12;
+/// Maximum number of chunks a GELF message may be split into
+const MAX_CHUNKS: usize = 128;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+fn default_queue_size() -> usize {
+    10_000
+}
+
+fn default_chunk_size() -> usize {
+    1420
+}
+
+/// Transport used to reach the upstream server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardProtocol {
+    #[default]
+    Udp,
+    Tcp,
+}
+
+/// One upstream GELF server
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForwardConfig {
+    /// `host:port` of the upstream server
+    pub address: String,
+    #[serde(default)]
+    pub protocol: ForwardProtocol,
+    /// Gzip-compress UDP payloads
+    #[serde(default)]
+    pub compress: bool,
+    /// Only forward messages matching this query, e.g. `level:<=4`
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Messages buffered while the upstream is slow or unreachable
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    /// Maximum UDP datagram size before chunking
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: usize,
+}
+
+/// A queued message with a sequence number local to its forwarder
+struct Queued {
+    sequence: u64,
+    message: Arc<OutputMessage>,
+}
+
+/// Relays messages to an upstream GELF server through a bounded retry queue
+pub struct Forwarder {
+    config: ForwardConfig,
+    filter: Option<Query>,
+    queue: Mutex<VecDeque<Queued>>,
+    next_sequence: AtomicU64,
+    notify: Notify,
+    forwarded: AtomicU64,
+    dropped: AtomicU64,
+    filtered: AtomicU64,
+    send_errors: AtomicU64,
+}
+
+impl Forwarder {
+    pub fn new(config: ForwardConfig) -> Result<Self, ConfigError> {
+        let filter = config
+            .filter
+            .as_deref()
+            .map(Query::parse)
+            .transpose()
+            .map_err(|e| ConfigError::Invalid(format!("forward filter for {}: {}", config.address, e)))?;
+
+        if config.chunk_size <= CHUNK_HEADER_SIZE {
+            return Err(ConfigError::Invalid(format!(
+                "forward chunk_size for {} must be larger than {} bytes",
+                config.address, CHUNK_HEADER_SIZE
+            )));
+        }
+
+        Ok(Self {
+            config,
+            filter,
+            queue: Mutex::new(VecDeque::new()),
+            next_sequence: AtomicU64::new(0),
+            notify: Notify::new(),
+            forwarded: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            filtered: AtomicU64::new(0),
+            send_errors: AtomicU64::new(0),
+        })
+    }
+
+    /// Start the send loop; messages arrive through `offer`
+    pub fn spawn(self: Arc<Self>) {
+        tokio::spawn(async move { self.send_loop().await });
+    }
+
+    fn enqueue(&self, message: Arc<OutputMessage>) {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if queue.len() >= self.config.queue_size {
+                // Prefer fresh messages over stale ones when the upstream is down
+                queue.pop_front();
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.push_back(Queued { sequence, message });
+        }
+        self.notify.notify_one();
+    }
+
+    async fn send_loop(&self) {
+        let mut connection: Option<Connection> = None;
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            let next = self
+                .queue
+                .lock()
+                .unwrap()
+                .front()
+                .map(|queued| (queued.sequence, queued.message.clone()));
+            let Some((sequence, message)) = next else {
+                self.notify.notified().await;
+                continue;
+            };
+
+            let payload = serde_json::to_vec(&message.gelf_message.to_gelf_payload(message.received_at))
+                .unwrap_or_default();
+
+            if connection.is_none() {
+                match Connection::open(&self.config).await {
+                    Ok(opened) => {
+                        info!("Connected to upstream {}", self.config.address);
+                        connection = Some(opened);
+                        backoff = INITIAL_BACKOFF;
+                    }
+                    Err(e) => {
+                        error!("Failed to connect to upstream {}: {}", self.config.address, e);
+                        self.send_errors.fetch_add(1, Ordering::Relaxed);
+                        tokio::time::sleep(backoff).await;
+                        backoff = next_backoff(backoff);
+                        continue;
+                    }
+                }
+            }
+
+            let result = match connection.as_mut() {
+                Some(open) => open.send(&self.config, &payload).await,
+                None => continue,
+            };
+
+            match result {
+                Ok(()) => {
+                    self.pop_sent(sequence);
+                    self.forwarded.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(SendError::TooLarge) => {
+                    warn!("Message of {} bytes too large to forward to {}, dropping", payload.len(), self.config.address);
+                    self.pop_sent(sequence);
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(SendError::Io(e)) => {
+                    warn!("Failed to forward to {}: {}, reconnecting in {:?}", self.config.address, e, backoff);
+                    self.send_errors.fetch_add(1, Ordering::Relaxed);
+                    connection = None;
+                    tokio::time::sleep(backoff).await;
+                    backoff = next_backoff(backoff);
+                }
+            }
+        }
+    }
+
+    /// Remove a delivered message, unless it was already pushed out by overflow
+    fn pop_sent(&self, sequence: u64) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.front().is_some_and(|front| front.sequence == sequence) {
+            queue.pop_front();
+        }
+    }
+}
+
+impl Output for Forwarder {
+    fn offer(&self, message: &Arc<OutputMessage>) {
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !filter.matches(&message.gelf_message, message.received_at))
+        {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.enqueue(message.clone());
+    }
+}
+
+impl StatsProvider for Forwarder {
+    fn stats(&self) -> serde_json::Value {
+        let (queued, lag_seconds) = {
+            let queue = self.queue.lock().unwrap();
+            let lag = queue
+                .front()
+                .map_or(0.0, |oldest| (now() - oldest.message.received_at).max(0.0));
+            (queue.len(), lag)
+        };
+
+        serde_json::json!({
+            "address": self.config.address,
+            "protocol": self.config.protocol,
+            "forwarded": self.forwarded.load(Ordering::Relaxed),
+            "dropped": self.dropped.load(Ordering::Relaxed),
+            "filtered": self.filtered.load(Ordering::Relaxed),
+            "send_errors": self.send_errors.load(Ordering::Relaxed),
+            "queued": queued,
+            "lag_seconds": lag_seconds
+        })
+    }
+}
+
+enum SendError {
+    TooLarge,
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for SendError {
+    fn from(e: std::io::Error) -> Self {
+        SendError::Io(e)
+    }
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    async fn open(config: &ForwardConfig) -> std::io::Result<Self> {
+        match config.protocol {
+            ForwardProtocol::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(&config.address).await?;
+                Ok(Connection::Udp(socket))
+            }
+            ForwardProtocol::Tcp => Ok(Connection::Tcp(TcpStream::connect(&config.address).await?)),
+        }
+    }
+
+    async fn send(&mut self, config: &ForwardConfig, payload: &[u8]) -> Result<(), SendError> {
+        match self {
+            Connection::Udp(socket) => {
+                let data = if config.compress { gzip(payload)? } else { payload.to_vec() };
+                for datagram in chunk(&data, config.chunk_size)? {
+                    socket.send(&datagram).await?;
+                }
+                Ok(())
+            }
+            Connection::Tcp(stream) => {
+                // GELF TCP frames are terminated by a null byte and cannot be compressed
+                stream.write_all(payload).await?;
+                stream.write_all(&[0]).await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Wait before the next attempt, doubling up to `MAX_BACKOFF`
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Split a payload into GELF UDP chunks if it does not fit in one datagram
+fn chunk(data: &[u8], chunk_size: usize) -> Result<Vec<Vec<u8>>, SendError> {
+    if data.len() <= chunk_size {
+        return Ok(vec![data.to_vec()]);
+    }
+
+    let body_size = chunk_size - CHUNK_HEADER_SIZE;
+    let count = data.len().div_ceil(body_size);
+    if count > MAX_CHUNKS {
+        return Err(SendError::TooLarge);
+    }
+
+    let message_id = message_id();
+    Ok(data
+        .chunks(body_size)
+        .enumerate()
+        .map(|(sequence, body)| {
+            let mut datagram = Vec::with_capacity(CHUNK_HEADER_SIZE + body.len());
+            datagram.extend_from_slice(&CHUNK_MAGIC);
+            datagram.extend_from_slice(&message_id);
+            datagram.push(sequence as u8);
+            datagram.push(count as u8);
+            datagram.extend_from_slice(body);
+            datagram
+        })
+        .collect())
+}
+
+/// Unique-enough 8-byte id for correlating the chunks of one message
+fn message_id() -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    (nanos ^ COUNTER.fetch_add(1, Ordering::Relaxed).rotate_left(48)).to_be_bytes()
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn forwarder(config: serde_json::Value) -> Arc<Forwarder> {
+        Arc::new(Forwarder::new(serde_json::from_value(config).unwrap()).unwrap())
+    }
+
+    fn message(text: &str) -> Arc<OutputMessage> {
+        Arc::new(OutputMessage {
+            gelf_message: serde_json::from_value(json!({ "host": "web-1", "short_message": text })).unwrap(),
+            received_at: now(),
+        })
+    }
+
+    fn queued(forwarder: &Forwarder) -> Vec<u64> {
+        forwarder.queue.lock().unwrap().iter().map(|queued| queued.sequence).collect()
+    }
+
+    /// Poll until the forwarder's counters satisfy `done`, failing after five seconds
+    async fn wait_for(forwarder: &Forwarder, done: impl Fn(&serde_json::Value) -> bool) -> serde_json::Value {
+        for _ in 0..500 {
+            let stats = forwarder.stats();
+            if done(&stats) {
+                return stats;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("forwarder did not get there: {}", forwarder.stats());
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_messages() {
+        let forwarder = forwarder(json!({ "address": "127.0.0.1:1", "queue_size": 3 }));
+        for i in 0..5 {
+            forwarder.enqueue(message(&format!("message {}", i)));
+        }
+        assert_eq!(queued(&forwarder), [2, 3, 4]);
+        assert_eq!(forwarder.stats()["dropped"], 2);
+        assert_eq!(forwarder.stats()["queued"], 3);
+
+        // A send that finishes after its message was pushed out must not remove a newer one
+        forwarder.pop_sent(0);
+        assert_eq!(queued(&forwarder), [2, 3, 4]);
+        forwarder.pop_sent(2);
+        assert_eq!(queued(&forwarder), [3, 4]);
+    }
+
+    #[test]
+    fn filtered_messages_are_counted_and_not_queued() {
+        let forwarder = forwarder(json!({ "address": "127.0.0.1:1", "filter": "level:<=3" }));
+        forwarder.offer(&message("no level"));
+        assert!(queued(&forwarder).is_empty());
+        assert_eq!(forwarder.stats()["filtered"], 1);
+    }
+
+    #[test]
+    fn large_payloads_are_split_into_gelf_chunks() {
+        let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        assert!(matches!(chunk(&data, 1000).as_deref(), Ok([single]) if single == &data));
+
+        let chunks = chunk(&data, 100).ok().unwrap();
+        assert_eq!(chunks.len(), 12);
+        for (sequence, datagram) in chunks.iter().enumerate() {
+            assert!(datagram.len() <= 100);
+            assert_eq!(datagram[..2], CHUNK_MAGIC);
+            assert_eq!(datagram[2..10], chunks[0][2..10]);
+            assert_eq!((datagram[10], datagram[11]), (sequence as u8, 12));
+        }
+        let body: Vec<u8> = chunks.iter().flat_map(|datagram| datagram[CHUNK_HEADER_SIZE..].to_vec()).collect();
+        assert_eq!(body, data);
+
+        let too_many = vec![0; MAX_CHUNKS * 88 + 1];
+        assert!(matches!(chunk(&too_many, 100), Err(SendError::TooLarge)));
+    }
+
+    #[tokio::test]
+    async fn messages_too_large_to_chunk_are_dropped() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let forwarder = forwarder(json!({
+            "address": upstream.local_addr().unwrap().to_string(),
+            "chunk_size": 20
+        }));
+        forwarder.clone().spawn();
+        forwarder.offer(&message(&"x".repeat(2000)));
+        forwarder.offer(&message("small"));
+
+        let stats = wait_for(&forwarder, |stats| stats["forwarded"] == 1).await;
+        assert_eq!((stats["dropped"].clone(), stats["queued"].clone()), (json!(1), json!(0)));
+        let mut datagram = [0; 64];
+        let received = upstream.recv(&mut datagram).await.unwrap();
+        assert_eq!(datagram[..2], CHUNK_MAGIC);
+        assert!(received <= 20);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut waits = Vec::new();
+        for _ in 0..9 {
+            waits.push(backoff.as_secs_f64());
+            backoff = next_backoff(backoff);
+        }
+        assert_eq!(waits, [0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 30.0, 30.0, 30.0]);
+    }
+
+    #[tokio::test]
+    async fn tcp_reconnects_once_the_upstream_is_back() {
+        let address = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let forwarder = forwarder(json!({ "address": address.to_string(), "protocol": "tcp" }));
+        forwarder.clone().spawn();
+        forwarder.offer(&message("kept while the upstream is down"));
+
+        wait_for(&forwarder, |stats| stats["send_errors"].as_u64() >= Some(1)).await;
+        assert_eq!(forwarder.stats()["queued"], 1);
+
+        let listener = TcpListener::bind(address).await.unwrap();
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut frame = Vec::new();
+        while frame.last() != Some(&0) {
+            let mut buffer = [0; 512];
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert!(read > 0, "connection closed before a whole frame arrived");
+            frame.extend_from_slice(&buffer[..read]);
+        }
+        let payload: serde_json::Value = serde_json::from_slice(&frame[..frame.len() - 1]).unwrap();
+        assert_eq!(payload["short_message"], "kept while the upstream is down");
+        wait_for(&forwarder, |stats| stats["forwarded"] == 1 && stats["queued"] == 0).await;
+    }
+}
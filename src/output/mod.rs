@@ -0,0 +1,114 @@
+pub mod file;
+pub mod forward;
+
+use crate::config::ConfigError;
+use crate::gelf::GelfMessage;
+use crate::storage::{InMemoryMessageStore, StatsProvider};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+pub use file::{FileOutput, FileOutputConfig};
+pub use forward::{ForwardConfig, ForwardProtocol, Forwarder};
+
+/// Configuration of all outputs
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputsConfig {
+    /// Upstream GELF servers to relay messages to
+    pub forward: Vec<ForwardConfig>,
+    /// Rotating NDJSON archives on local disk
+    pub file: Vec<FileOutputConfig>,
+}
+
+/// An accepted message on its way to the outputs
+#[derive(Debug, Serialize)]
+pub struct OutputMessage {
+    #[serde(flatten)]
+    pub gelf_message: GelfMessage,
+    pub received_at: f64,
+}
+
+/// Destination of accepted messages
+trait Output: Send + Sync {
+    /// Take a message without waiting; an output that cannot keep up drops and counts it
+    fn offer(&self, message: &Arc<OutputMessage>);
+}
+
+/// Hands accepted messages to the outputs before they are stored
+///
+/// Outputs are fed here rather than from the store's broadcast, so they see
+/// every message that passed the pipeline regardless of what the buffer does
+/// with it, and each output buffers on its own.
+#[derive(Clone, Default)]
+pub struct Outputs {
+    outputs: Arc<Vec<Arc<dyn Output>>>,
+}
+
+impl Outputs {
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    pub fn send(&self, gelf_message: &GelfMessage) {
+        if self.outputs.is_empty() {
+            return;
+        }
+        let message = Arc::new(OutputMessage {
+            gelf_message: gelf_message.clone(),
+            received_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64(),
+        });
+        for output in self.outputs.iter() {
+            output.offer(&message);
+        }
+    }
+}
+
+/// Statistics of all outputs of one kind, reported as an array
+struct OutputStatsList(Vec<Arc<dyn StatsProvider>>);
+
+impl StatsProvider for OutputStatsList {
+    fn stats(&self) -> serde_json::Value {
+        serde_json::Value::Array(self.0.iter().map(|output| output.stats()).collect())
+    }
+}
+
+/// Start a task for every configured output, fed through the returned `Outputs`
+///
+/// Output statistics are reported by `store`.
+pub fn spawn_outputs(store: &InMemoryMessageStore, config: &OutputsConfig) -> Result<Outputs, ConfigError> {
+    let mut outputs: Vec<Arc<dyn Output>> = Vec::new();
+    let mut forwarders: Vec<Arc<dyn StatsProvider>> = Vec::new();
+    for forward_config in &config.forward {
+        let forwarder = Arc::new(Forwarder::new(forward_config.clone())?);
+        info!("Forwarding messages to {} via {:?}", forward_config.address, forward_config.protocol);
+        forwarder.clone().spawn();
+        outputs.push(forwarder.clone());
+        forwarders.push(forwarder);
+    }
+
+    if !forwarders.is_empty() {
+        store.register_stats("forward", Arc::new(OutputStatsList(forwarders)));
+    }
+
+    let mut file_outputs: Vec<Arc<dyn StatsProvider>> = Vec::new();
+    for file_config in &config.file {
+        let output = Arc::new(FileOutput::new(file_config.clone())?);
+        info!("Archiving messages to {}", file_config.path);
+        output.clone().spawn();
+        outputs.push(output.clone());
+        file_outputs.push(output);
+    }
+
+    if !file_outputs.is_empty() {
+        store.register_stats("file_outputs", Arc::new(OutputStatsList(file_outputs)));
+    }
+
+    Ok(Outputs {
+        outputs: Arc::new(outputs),
+    })
+}
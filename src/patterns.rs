@@ -0,0 +1,426 @@
+use crate::gelf::GelfMessage;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+/// Fraction of constant tokens that must agree to join a pattern
+const SIMILARITY_THRESHOLD: f64 = 0.5;
+const MAX_EXAMPLES: usize = 3;
+const MAX_EXAMPLE_LENGTH: usize = 200;
+const WILDCARD: &str = "<*>";
+
+/// A group of messages sharing a template
+#[derive(Debug, Clone, Serialize)]
+pub struct PatternInfo {
+    pub id: u64,
+    /// `short_message` with variable parts replaced by placeholders
+    pub template: String,
+    /// Number of buffered messages with this pattern
+    pub count: usize,
+    /// Number of messages with this pattern since it was first seen
+    pub total_seen: u64,
+    pub first_seen: f64,
+    pub last_seen: f64,
+    pub examples: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Cluster {
+    tokens: Vec<String>,
+    info: PatternInfo,
+}
+
+fn is_ipv4(value: &str) -> bool {
+    let address = match value.rsplit_once(':') {
+        Some((address, port)) if port.chars().all(|c| c.is_ascii_digit()) => address,
+        _ => value,
+    };
+    address.split('.').count() == 4 && address.split('.').all(|octet| octet.parse::<u8>().is_ok())
+}
+
+fn is_ipv6(value: &str) -> bool {
+    value.matches(':').count() >= 2 && value.chars().all(|c| c.is_ascii_hexdigit() || c == ':')
+}
+
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+        && groups.iter().all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Placeholder for a variable value, if it looks like one
+fn mask_value(value: &str) -> Option<&'static str> {
+    let trimmed = value.trim_matches(|c: char| matches!(c, ',' | ';' | '.' | ')' | '(' | '[' | ']' | '"' | '\''));
+    if !trimmed.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    if is_ipv4(trimmed) || is_ipv6(trimmed) {
+        Some("<IP>")
+    } else if is_uuid(trimmed) {
+        Some("<UUID>")
+    } else if trimmed.parse::<f64>().is_ok() {
+        Some("<NUM>")
+    } else if trimmed.len() >= 8 && trimmed.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some("<HEX>")
+    } else {
+        Some(WILDCARD)
+    }
+}
+
+/// Mask a token, keeping the key of `key=value` pairs
+fn mask_token(token: &str) -> Cow<'_, str> {
+    if let Some((key, value)) = token.split_once('=')
+        && let Some(mask) = mask_value(value)
+    {
+        return Cow::Owned(format!("{}={}", key, mask));
+    }
+    match mask_value(token) {
+        Some(mask) => Cow::Borrowed(mask),
+        None => Cow::Borrowed(token),
+    }
+}
+
+fn template_tokens(text: &str) -> Vec<String> {
+    text.split_whitespace().map(|token| mask_token(token).into_owned()).collect()
+}
+
+/// Messages are only compared with patterns of the same token count and first token
+fn group_key(tokens: &[String]) -> (usize, String) {
+    (tokens.len(), tokens.first().cloned().unwrap_or_default())
+}
+
+/// Fraction of positions where the template agrees, a wildcard agreeing with any token
+fn similarity(template: &[String], tokens: &[String]) -> f64 {
+    if tokens.is_empty() {
+        return 1.0;
+    }
+    let equal = template
+        .iter()
+        .zip(tokens)
+        .filter(|(a, b)| a.as_str() == WILDCARD || a == b)
+        .count();
+    equal as f64 / tokens.len() as f64
+}
+
+fn wildcards(template: &[String]) -> usize {
+    template.iter().filter(|token| token.as_str() == WILDCARD).count()
+}
+
+fn example_text(text: &str) -> String {
+    match text.char_indices().nth(MAX_EXAMPLE_LENGTH) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Online template mining over `short_message`, in the style of Drain
+///
+/// Messages are grouped by token count and first token; within a group a
+/// message joins the most similar pattern, turning the positions where
+/// they differ into wildcards, or starts a new pattern.
+#[derive(Debug, Default)]
+pub struct PatternMiner {
+    clusters: HashMap<u64, Cluster>,
+    groups: HashMap<(usize, String), Vec<u64>>,
+    next_id: u64,
+    /// Distance between the ids this miner hands out
+    id_step: u64,
+}
+
+impl PatternMiner {
+    pub fn new() -> Self {
+        Self::with_ids(1, 1)
+    }
+
+    /// Miner numbering its patterns `first`, `first + step`, ..., so several miners never share an id
+    pub fn with_ids(first: u64, step: u64) -> Self {
+        Self {
+            next_id: first,
+            id_step: step,
+            ..Self::default()
+        }
+    }
+
+    /// Assign a message to a pattern, returning the pattern id
+    pub fn insert(&mut self, message: &GelfMessage, received_at: f64) -> Option<u64> {
+        let text = message.short_message.as_deref()?;
+        Some(self.insert_tokens(text, template_tokens(text), received_at))
+    }
+
+    fn insert_tokens(&mut self, text: &str, tokens: Vec<String>, received_at: f64) -> u64 {
+        let group = self.groups.entry(group_key(&tokens)).or_default();
+
+        // On a tie the more specific pattern wins, so general ones do not swallow their neighbours
+        let best = group
+            .iter()
+            .map(|id| (*id, similarity(&self.clusters[id].tokens, &tokens)))
+            .filter(|(_, score)| *score >= SIMILARITY_THRESHOLD)
+            .max_by(|a, b| {
+                a.1.total_cmp(&b.1)
+                    .then(wildcards(&self.clusters[&b.0].tokens).cmp(&wildcards(&self.clusters[&a.0].tokens)))
+            })
+            .map(|(id, _)| id);
+
+        let id = match best {
+            Some(id) => {
+                let cluster = self.clusters.get_mut(&id).unwrap();
+                let mut changed = false;
+                for (existing, token) in cluster.tokens.iter_mut().zip(&tokens) {
+                    if existing != token && existing != WILDCARD {
+                        *existing = WILDCARD.to_string();
+                        changed = true;
+                    }
+                }
+                if changed {
+                    cluster.info.template = cluster.tokens.join(" ");
+                }
+                id
+            }
+            None => {
+                let id = self.next_id;
+                self.next_id += self.id_step;
+                group.push(id);
+                self.clusters.insert(
+                    id,
+                    Cluster {
+                        info: PatternInfo {
+                            id,
+                            template: tokens.join(" "),
+                            count: 0,
+                            total_seen: 0,
+                            first_seen: received_at,
+                            last_seen: received_at,
+                            examples: Vec::new(),
+                        },
+                        tokens,
+                    },
+                );
+                id
+            }
+        };
+
+        let info = &mut self.clusters.get_mut(&id).unwrap().info;
+        info.count += 1;
+        info.total_seen += 1;
+        info.last_seen = received_at;
+        if info.examples.len() < MAX_EXAMPLES {
+            let example = example_text(text);
+            if !info.examples.contains(&example) {
+                info.examples.push(example);
+            }
+        }
+        id
+    }
+
+    /// Forget a message that left the buffer; patterns no longer present are dropped
+    pub fn remove(&mut self, id: u64) {
+        let Some(cluster) = self.clusters.get_mut(&id) else {
+            return;
+        };
+
+        cluster.info.count -= 1;
+        if cluster.info.count == 0 {
+            let cluster = self.clusters.remove(&id).unwrap();
+            let group_key = group_key(&cluster.tokens);
+            if let Some(group) = self.groups.get_mut(&group_key) {
+                group.retain(|member| *member != id);
+                if group.is_empty() {
+                    self.groups.remove(&group_key);
+                }
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.clusters.clear();
+        self.groups.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.clusters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clusters.is_empty()
+    }
+
+    /// Patterns with the most buffered messages first
+    pub fn list(&self, limit: Option<usize>) -> Vec<PatternInfo> {
+        let mut patterns: Vec<PatternInfo> = self.clusters.values().map(|cluster| cluster.info.clone()).collect();
+        patterns.sort_by(|a, b| b.count.cmp(&a.count).then(a.id.cmp(&b.id)));
+        patterns.truncate(limit.unwrap_or(patterns.len()));
+        patterns
+    }
+}
+
+/// Pattern miners split by token count and first token, which no pattern spans
+///
+/// Each stripe has its own lock, so messages of different groups are mined
+/// concurrently, while the patterns and their ids are the same as with a
+/// single miner. Tokenizing happens before a stripe is locked.
+#[derive(Debug)]
+pub struct PatternStripes {
+    stripes: Vec<Mutex<PatternMiner>>,
+}
+
+impl PatternStripes {
+    pub fn new(stripe_count: usize) -> Self {
+        let stripe_count = stripe_count.max(1);
+        Self {
+            stripes: (0..stripe_count)
+                .map(|stripe| Mutex::new(PatternMiner::with_ids(stripe as u64 + 1, stripe_count as u64)))
+                .collect(),
+        }
+    }
+
+    /// Assign a message to a pattern, returning the pattern id
+    pub fn insert(&self, message: &GelfMessage, received_at: f64) -> Option<u64> {
+        let text = message.short_message.as_deref()?;
+        let tokens = template_tokens(text);
+        let mut hasher = DefaultHasher::new();
+        group_key(&tokens).hash(&mut hasher);
+        let stripe = &self.stripes[(hasher.finish() % self.stripes.len() as u64) as usize];
+        Some(stripe.lock().unwrap().insert_tokens(text, tokens, received_at))
+    }
+
+    /// Forget a message that left the buffer; the id tells which stripe mined it
+    pub fn remove(&self, id: u64) {
+        let stripe = ((id - 1) % self.stripes.len() as u64) as usize;
+        self.stripes[stripe].lock().unwrap().remove(id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.stripes.iter().map(|stripe| stripe.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Patterns with the most buffered messages first
+    pub fn list(&self, limit: Option<usize>) -> Vec<PatternInfo> {
+        let mut patterns: Vec<PatternInfo> = self
+            .stripes
+            .iter()
+            .flat_map(|stripe| stripe.lock().unwrap().list(limit))
+            .collect();
+        patterns.sort_by(|a, b| b.count.cmp(&a.count).then(a.id.cmp(&b.id)));
+        patterns.truncate(limit.unwrap_or(patterns.len()));
+        patterns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> GelfMessage {
+        serde_json::from_value(serde_json::json!({ "short_message": text })).unwrap()
+    }
+
+    #[test]
+    fn variable_tokens_are_masked_by_kind() {
+        let tokens = template_tokens("GET /items/7 from 10.0.0.1:443 id=550e8400-e29b-41d4-a716-446655440000 in 1.5 ms, hash deadbeef42");
+        assert_eq!(
+            tokens.join(" "),
+            "GET <*> from <IP> id=<UUID> in <NUM> ms, hash <HEX>"
+        );
+    }
+
+    #[test]
+    fn similar_messages_merge_and_differing_positions_become_wildcards() {
+        let mut miner = PatternMiner::new();
+        let first = miner.insert(&message("user alice logged in from web"), 1.0);
+        let second = miner.insert(&message("user bob logged in from web"), 2.0);
+        let third = miner.insert(&message("user carol logged out from app"), 3.0);
+
+        assert_eq!(first, second);
+        assert_eq!(second, third);
+        let patterns = miner.list(None);
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].template, "user <*> logged <*> from <*>");
+        assert_eq!((patterns[0].count, patterns[0].first_seen, patterns[0].last_seen), (3, 1.0, 3.0));
+    }
+
+    #[test]
+    fn wildcards_count_as_agreeing_positions() {
+        let mut miner = PatternMiner::new();
+        for text in ["conn a to b c", "conn x to b c", "conn y to z c", "conn q to r s"] {
+            miner.insert(&message(text), 1.0);
+        }
+        assert_eq!(miner.list(None).len(), 1);
+        assert_eq!(miner.list(None)[0].template, "conn <*> to <*> <*>");
+
+        // Only two of five tokens are constant, yet new lines keep joining
+        for text in ["conn m to n o", "conn j to k l"] {
+            miner.insert(&message(text), 2.0);
+        }
+        let patterns = miner.list(None);
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].count, 6);
+    }
+
+    #[test]
+    fn dissimilar_or_differently_shaped_messages_start_new_patterns() {
+        let mut miner = PatternMiner::new();
+        let login = miner.insert(&message("user alice logged in"), 1.0);
+        // Fewer than half of the tokens agree
+        let other = miner.insert(&message("user cache flushed completely"), 1.0);
+        // A different token count or first token is never compared
+        let longer = miner.insert(&message("user alice logged in twice"), 1.0);
+        let first_token = miner.insert(&message("admin alice logged in"), 1.0);
+
+        let ids = [login, other, longer, first_token];
+        assert!(ids.iter().all(Option::is_some));
+        assert_eq!(ids.iter().collect::<std::collections::HashSet<_>>().len(), 4);
+        let empty: GelfMessage = serde_json::from_value(serde_json::json!({ "host": "a" })).unwrap();
+        assert_eq!(miner.insert(&empty, 1.0), None);
+    }
+
+    #[test]
+    fn patterns_are_dropped_when_their_last_message_leaves() {
+        let mut miner = PatternMiner::new();
+        let id = miner.insert(&message("disk 1 full"), 1.0).unwrap();
+        miner.insert(&message("disk 2 full"), 2.0);
+
+        miner.remove(id);
+        assert_eq!(miner.list(None)[0].count, 1);
+        assert_eq!(miner.list(None)[0].total_seen, 2);
+        miner.remove(id);
+        assert!(miner.is_empty());
+        assert_ne!(miner.insert(&message("disk 3 full"), 3.0), Some(id));
+    }
+
+    #[test]
+    fn examples_are_distinct_and_limited() {
+        let mut miner = PatternMiner::new();
+        for text in ["job 1 done", "job 1 done", "job 2 done", "job 3 done", "job 4 done"] {
+            miner.insert(&message(text), 1.0);
+        }
+        assert_eq!(miner.list(None)[0].examples, ["job 1 done", "job 2 done", "job 3 done"]);
+    }
+
+    #[test]
+    fn stripes_hand_out_unique_ids_and_list_like_one_miner() {
+        let stripes = PatternStripes::new(4);
+        let texts = ["user 1 logged in", "cache warmed", "disk 1 full", "user 2 logged in", "job 7 done"];
+        let ids: Vec<u64> = texts
+            .iter()
+            .map(|text| stripes.insert(&message(text), 1.0).unwrap())
+            .collect();
+
+        assert_eq!(ids[0], ids[3]);
+        assert_eq!(ids.iter().collect::<std::collections::HashSet<_>>().len(), 4);
+        let listed = stripes.list(None);
+        assert_eq!(listed.len(), 4);
+        assert_eq!((listed[0].id, listed[0].count), (ids[0], 2));
+        assert_eq!(stripes.list(Some(2)).len(), 2);
+
+        stripes.remove(ids[1]);
+        assert_eq!(stripes.len(), 3);
+    }
+}
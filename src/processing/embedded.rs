@@ -0,0 +1,310 @@
+use super::{Action, Processor};
+use crate::gelf::GelfMessage;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Payload formats recognised inside text fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddedFormat {
+    Json,
+    Logfmt,
+}
+
+fn default_fields() -> Vec<String> {
+    vec!["short_message".to_string(), "full_message".to_string()]
+}
+
+fn default_formats() -> Vec<EmbeddedFormat> {
+    vec![EmbeddedFormat::Json, EmbeddedFormat::Logfmt]
+}
+
+fn default_separator() -> String {
+    "_".to_string()
+}
+
+fn default_max_depth() -> usize {
+    3
+}
+
+/// Settings of the `embedded` processor
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmbeddedConfig {
+    /// Text fields to look for payloads in
+    #[serde(default = "default_fields")]
+    pub fields: Vec<String>,
+    /// Formats to try, in order
+    #[serde(default = "default_formats")]
+    pub formats: Vec<EmbeddedFormat>,
+    /// Joins the keys of nested JSON objects
+    #[serde(default = "default_separator")]
+    pub separator: String,
+    /// Nesting levels flattened into separate fields; deeper objects are kept as JSON text
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    /// Prepended to every lifted key
+    #[serde(default)]
+    pub prefix: String,
+    /// Replace fields the message already has
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// Parse the JSON object in a text, which may follow a plain-text prefix
+fn parse_json(text: &str) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    match serde_json::from_str(&text[start..=end]) {
+        Ok(serde_json::Value::Object(object)) => Some(object),
+        _ => None,
+    }
+}
+
+fn is_logfmt_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Numbers and booleans are typed so they can be compared in queries
+fn logfmt_value(value: String) -> serde_json::Value {
+    if let Ok(number) = value.parse::<i64>() {
+        return number.into();
+    }
+    if let Some(number) = value.parse::<f64>().ok().and_then(serde_json::Number::from_f64) {
+        return serde_json::Value::Number(number);
+    }
+    match value.as_str() {
+        "true" => true.into(),
+        "false" => false.into(),
+        _ => value.into(),
+    }
+}
+
+/// Collect the `key=value` and `key="quoted value"` pairs of a logfmt line
+///
+/// Words that are not pairs, like a leading message, are skipped.
+fn parse_logfmt(text: &str) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let mut pairs = serde_json::Map::new();
+    let mut chars = text.chars().peekable();
+
+    while chars.peek().is_some() {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() || c == '=' {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        if chars.peek() != Some(&'=') {
+            continue;
+        }
+        chars.next();
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+
+        if is_logfmt_key(&key) {
+            pairs.insert(key, logfmt_value(value));
+        }
+    }
+
+    (!pairs.is_empty()).then_some(pairs)
+}
+
+/// Characters allowed in lifted field names; others become `_`
+fn sanitize_key(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '_' | '-' | '.') { c } else { '_' })
+        .collect()
+}
+
+/// Lifts the keys of JSON or logfmt payloads inside text fields into additional fields
+pub struct EmbeddedPayloads {
+    config: EmbeddedConfig,
+    parsed_json: AtomicU64,
+    parsed_logfmt: AtomicU64,
+    fields_added: AtomicU64,
+}
+
+impl EmbeddedPayloads {
+    pub fn new(config: EmbeddedConfig) -> Self {
+        Self {
+            config,
+            parsed_json: AtomicU64::new(0),
+            parsed_logfmt: AtomicU64::new(0),
+            fields_added: AtomicU64::new(0),
+        }
+    }
+
+    fn parse(&self, text: &str) -> Option<serde_json::Map<String, serde_json::Value>> {
+        self.config.formats.iter().find_map(|format| {
+            let parsed = match format {
+                EmbeddedFormat::Json => parse_json(text),
+                EmbeddedFormat::Logfmt => parse_logfmt(text),
+            }?;
+            let counter = match format {
+                EmbeddedFormat::Json => &self.parsed_json,
+                EmbeddedFormat::Logfmt => &self.parsed_logfmt,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            Some(parsed)
+        })
+    }
+
+    /// Flatten nested objects into `parent<separator>child` names
+    fn flatten(
+        &self,
+        object: serde_json::Map<String, serde_json::Value>,
+        prefix: &str,
+        depth: usize,
+        out: &mut Vec<(String, serde_json::Value)>,
+    ) {
+        for (key, value) in object {
+            let name = format!("{}{}", prefix, sanitize_key(&key));
+            match value {
+                serde_json::Value::Object(nested) if depth < self.config.max_depth => {
+                    let nested_prefix = format!("{}{}", name, self.config.separator);
+                    self.flatten(nested, &nested_prefix, depth + 1, out);
+                }
+                serde_json::Value::Object(nested) => {
+                    out.push((name, serde_json::Value::Object(nested).to_string().into()));
+                }
+                value => out.push((name, value)),
+            }
+        }
+    }
+}
+
+impl Processor for EmbeddedPayloads {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        let mut lifted = Vec::new();
+        for field in &self.config.fields {
+            let Some(serde_json::Value::String(text)) = message.get_field(field) else {
+                continue;
+            };
+            if let Some(object) = self.parse(&text) {
+                self.flatten(object, &self.config.prefix, 1, &mut lifted);
+            }
+        }
+
+        for (name, value) in lifted {
+            let key = GelfMessage::additional_field_key(&name);
+            if self.config.overwrite || !message.additional_fields.contains_key(&key) {
+                message.additional_fields.insert(key, value);
+                self.fields_added.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Action::Keep
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "parsed_json": self.parsed_json.load(Ordering::Relaxed),
+            "parsed_logfmt": self.parsed_logfmt.load(Ordering::Relaxed),
+            "fields_added": self.fields_added.load(Ordering::Relaxed)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> EmbeddedConfig {
+        serde_json::from_value(json!({})).unwrap()
+    }
+
+    fn lift(config: EmbeddedConfig, message: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        let mut message: GelfMessage = serde_json::from_value(message).unwrap();
+        EmbeddedPayloads::new(config).process(&mut message, &mut String::new());
+        message.additional_fields
+    }
+
+    #[test]
+    fn json_after_a_text_prefix_is_parsed() {
+        let parsed = parse_json(r#"request done {"status": 200, "user": {"id": 7}}"#).unwrap();
+        assert_eq!(serde_json::Value::Object(parsed), json!({ "status": 200, "user": { "id": 7 } }));
+        assert!(parse_json("no payload").is_none());
+        assert!(parse_json("} backwards {").is_none());
+        assert!(parse_json("[1, 2]").is_none());
+    }
+
+    #[test]
+    fn logfmt_pairs_are_typed_and_quoted_values_unescaped() {
+        let parsed = parse_logfmt(r#"request done status=200 took=1.5 cached=true path="/a b" note="say \"hi\"" bare"#).unwrap();
+        assert_eq!(
+            serde_json::Value::Object(parsed),
+            json!({ "status": 200, "took": 1.5, "cached": true, "path": "/a b", "note": "say \"hi\"" })
+        );
+        assert!(parse_logfmt("just words here").is_none());
+        assert!(parse_logfmt("=value").is_none());
+    }
+
+    #[test]
+    fn nested_objects_are_flattened_up_to_max_depth() {
+        let mut config = config();
+        config.max_depth = 2;
+        let fields = lift(
+            config,
+            json!({ "short_message": r#"{"http": {"status": 500, "client": {"ip": "10.0.0.1"}}}"# }),
+        );
+
+        assert_eq!(fields["_http_status"], 500);
+        assert_eq!(fields["_http_client"], r#"{"ip":"10.0.0.1"}"#);
+    }
+
+    #[test]
+    fn keys_are_prefixed_and_sanitized_and_existing_fields_kept() {
+        let mut config = config();
+        config.prefix = "app_".to_string();
+        let fields = lift(
+            config,
+            json!({ "short_message": r#"{"user name": "alice", "id": 7}"#, "_app_id": 1 }),
+        );
+
+        assert_eq!(fields["_app_user_name"], "alice");
+        assert_eq!(fields["_app_id"], 1);
+    }
+
+    #[test]
+    fn formats_are_tried_in_order() {
+        let text = r#"level=warn payload={"a": 1}"#;
+        let json_first = lift(config(), json!({ "short_message": text }));
+        assert_eq!(json_first["_a"], 1);
+        assert!(!json_first.contains_key("_level"));
+
+        let mut logfmt_first = config();
+        logfmt_first.formats = vec![EmbeddedFormat::Logfmt, EmbeddedFormat::Json];
+        let fields = lift(logfmt_first, json!({ "short_message": text }));
+        assert_eq!(fields["_level"], "warn");
+    }
+}
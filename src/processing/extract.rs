@@ -0,0 +1,439 @@
+use super::{Action, Processor};
+use crate::config::ConfigError;
+use crate::gelf::GelfMessage;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Limit on `%{...}` nesting, which also catches patterns that refer to themselves
+const MAX_GROK_DEPTH: usize = 20;
+
+/// Bundled grok patterns, a subset of the Logstash library
+///
+/// The `regex` crate has no look-around, so some patterns are simplified.
+const GROK_LIBRARY: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    ("INT", r"(?:[+-]?(?:[0-9]+))"),
+    ("BASE10NUM", r"(?:[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+))"),
+    ("NUMBER", r"(?:%{BASE10NUM})"),
+    ("BASE16NUM", r"(?:0[xX])?[0-9a-fA-F]+"),
+    ("POSINT", r"\b(?:[1-9][0-9]*)\b"),
+    ("NONNEGINT", r"\b(?:[0-9]+)\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    ("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*""#),
+    ("UUID", r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}"),
+    ("IPV4", r"(?:(?:25[0-5]|2[0-4][0-9]|1?[0-9]{1,2})\.){3}(?:25[0-5]|2[0-4][0-9]|1?[0-9]{1,2})"),
+    ("IPV6", r"(?:[0-9A-Fa-f]{0,4}:){2,7}[0-9A-Fa-f]{0,4}"),
+    ("IP", r"(?:%{IPV6}|%{IPV4})"),
+    ("HOSTNAME", r"\b(?:[0-9A-Za-z][0-9A-Za-z-]{0,62})(?:\.(?:[0-9A-Za-z][0-9A-Za-z-]{0,62}))*\.?\b"),
+    ("IPORHOST", r"(?:%{IP}|%{HOSTNAME})"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    ("UNIXPATH", r"(?:/[\w_%!$@:.,+~-]*)+"),
+    ("WINPATH", r"(?:[A-Za-z]+:|\\)(?:\\[^\\?*]*)+"),
+    ("PATH", r"(?:%{UNIXPATH}|%{WINPATH})"),
+    ("URIPATH", r"(?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_\-]*)+"),
+    ("URIPARAM", r"\?[A-Za-z0-9$.+!*'|(){},~@#%&/=:;_?\-\[\]<>]*"),
+    ("URIPATHPARAM", r"%{URIPATH}(?:%{URIPARAM})?"),
+    ("URI", r"[A-Za-z][A-Za-z0-9+\-.]*://\S+"),
+    ("EMAILADDRESS", r"[a-zA-Z0-9_.+-]+@[a-zA-Z0-9-]+(?:\.[a-zA-Z0-9-]+)+"),
+    ("MONTH", r"\b(?:[Jj]an(?:uary)?|[Ff]eb(?:ruary)?|[Mm]ar(?:ch)?|[Aa]pr(?:il)?|[Mm]ay|[Jj]un(?:e)?|[Jj]ul(?:y)?|[Aa]ug(?:ust)?|[Ss]ep(?:tember)?|[Oo]ct(?:ober)?|[Nn]ov(?:ember)?|[Dd]ec(?:ember)?)\b"),
+    ("MONTHNUM", r"(?:0?[1-9]|1[0-2])"),
+    ("MONTHDAY", r"(?:(?:0[1-9])|(?:[12][0-9])|(?:3[01])|[1-9])"),
+    ("DAY", r"(?:Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?)"),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"(?:2[0123]|[01]?[0-9])"),
+    ("MINUTE", r"(?:[0-5][0-9])"),
+    ("SECOND", r"(?:(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?)"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("ISO8601_TIMEZONE", r"(?:Z|[+-]%{HOUR}(?::?%{MINUTE}))"),
+    ("TIMESTAMP_ISO8601", r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?"),
+    ("DATE_US", r"%{MONTHNUM}[/-]%{MONTHDAY}[/-]%{YEAR}"),
+    ("DATE_EU", r"%{MONTHDAY}[./-]%{MONTHNUM}[./-]%{YEAR}"),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    ("HTTPDATE", r"%{MONTHDAY}/%{MONTH}/%{YEAR}:%{TIME} %{INT}"),
+    ("LOGLEVEL", r"(?:[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn?(?:ing)?|WARN?(?:ING)?|[Ee]rr?(?:or)?|ERR?(?:OR)?|[Cc]rit?(?:ical)?|CRIT?(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?)"),
+    ("COMMONAPACHELOG", r#"%{IPORHOST:clientip} %{USER:ident} %{USER:auth} \[%{HTTPDATE:timestamp}\] "(?:%{WORD:verb} %{NOTSPACE:request}(?: HTTP/%{NUMBER:httpversion})?|%{DATA:rawrequest})" %{NUMBER:response:int} (?:%{NUMBER:bytes:int}|-)"#),
+];
+
+/// Type a captured value is converted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Conversion {
+    String,
+    Int,
+    Float,
+}
+
+impl Conversion {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "string" => Some(Conversion::String),
+            "int" => Some(Conversion::Int),
+            "float" => Some(Conversion::Float),
+            _ => None,
+        }
+    }
+
+    /// Convert a captured value, or `None` if it is not a valid number
+    fn apply(self, value: &str) -> Option<serde_json::Value> {
+        match self {
+            Conversion::String => Some(value.into()),
+            Conversion::Int => value.trim().parse::<i64>().ok().map(Into::into),
+            Conversion::Float => value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(serde_json::Value::Number),
+        }
+    }
+}
+
+fn default_fields() -> Vec<String> {
+    vec!["short_message".to_string()]
+}
+
+/// A regex or grok pattern whose captures become additional fields
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExtractRuleConfig {
+    pub name: String,
+    /// Fields to match against, in order; the first that matches is used
+    #[serde(default = "default_fields")]
+    pub fields: Vec<String>,
+    /// Regular expression with named captures, e.g. `user=(?P<user>\w+)`
+    #[serde(default)]
+    pub regex: Option<String>,
+    /// Grok pattern, e.g. `%{IP:client} %{NUMBER:duration:float}`
+    #[serde(default)]
+    pub grok: Option<String>,
+    /// Type conversion per capture name
+    #[serde(default)]
+    pub convert: BTreeMap<String, Conversion>,
+    /// Replace fields the message already has
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+/// Expands `%{PATTERN:field:type}` references into a plain regex
+struct GrokCompiler<'a> {
+    patterns: HashMap<&'a str, &'a str>,
+    /// Generated group name to field name and conversion
+    captures: Vec<(String, String, Option<Conversion>)>,
+}
+
+impl<'a> GrokCompiler<'a> {
+    fn expand(&mut self, pattern: &str, depth: usize) -> Result<String, String> {
+        if depth > MAX_GROK_DEPTH {
+            return Err("grok patterns nest too deeply or refer to themselves".to_string());
+        }
+
+        let mut expanded = String::with_capacity(pattern.len());
+        let mut rest = pattern;
+        while let Some(start) = rest.find("%{") {
+            expanded.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unterminated %{{ in grok pattern '{}'", pattern))?;
+            let reference = &rest[start + 2..start + end];
+            let mut parts = reference.splitn(3, ':');
+            let name = parts.next().unwrap_or_default();
+            let body = self
+                .patterns
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("unknown grok pattern '{}'", name))?;
+            let inner = self.expand(body, depth + 1)?;
+
+            match parts.next().filter(|field| !field.is_empty()) {
+                Some(field) => {
+                    let conversion = match parts.next() {
+                        Some(kind) => Some(
+                            Conversion::parse(kind)
+                                .ok_or_else(|| format!("unknown conversion '{}' for {}", kind, field))?,
+                        ),
+                        None => None,
+                    };
+                    let group = format!("g{}", self.captures.len());
+                    expanded.push_str(&format!("(?P<{}>{})", group, inner));
+                    self.captures.push((group, field.to_string(), conversion));
+                }
+                None => {
+                    expanded.push_str("(?:");
+                    expanded.push_str(&inner);
+                    expanded.push(')');
+                }
+            }
+            rest = &rest[start + end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
+
+struct ExtractRule {
+    config: ExtractRuleConfig,
+    regex: Regex,
+    /// Capture group name to field name and conversion
+    captures: Vec<(String, String, Option<Conversion>)>,
+    matched: AtomicU64,
+    missed: AtomicU64,
+    conversion_errors: AtomicU64,
+}
+
+impl ExtractRule {
+    fn new(config: ExtractRuleConfig, custom_patterns: &BTreeMap<String, String>) -> Result<Self, ConfigError> {
+        let invalid = |message: String| ConfigError::Invalid(format!("extract rule '{}': {}", config.name, message));
+
+        let (source, captures) = match (&config.regex, &config.grok) {
+            (Some(regex), None) => {
+                let names = Regex::new(regex)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .capture_names()
+                    .flatten()
+                    .map(|name| (name.to_string(), name.to_string(), None))
+                    .collect();
+                (regex.clone(), names)
+            }
+            (None, Some(grok)) => {
+                let mut compiler = GrokCompiler {
+                    patterns: GROK_LIBRARY.iter().copied().collect(),
+                    captures: Vec::new(),
+                };
+                compiler
+                    .patterns
+                    .extend(custom_patterns.iter().map(|(name, body)| (name.as_str(), body.as_str())));
+                let source = compiler.expand(grok, 0).map_err(invalid)?;
+                (source, compiler.captures)
+            }
+            _ => return Err(invalid("needs exactly one of 'regex' or 'grok'".to_string())),
+        };
+
+        let regex = Regex::new(&source).map_err(|e| invalid(e.to_string()))?;
+        Ok(Self {
+            config,
+            regex,
+            captures,
+            matched: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+            conversion_errors: AtomicU64::new(0),
+        })
+    }
+
+    fn apply(&self, message: &mut GelfMessage) {
+        let found = self.config.fields.iter().find_map(|field| {
+            let value = message.get_field(field)?;
+            let text = value.as_str()?;
+            let captures = self.regex.captures(text)?;
+            Some(
+                self.captures
+                    .iter()
+                    .filter_map(|(group, field, conversion)| {
+                        let captured = captures.name(group)?.as_str();
+                        Some((field.clone(), captured.to_string(), *conversion))
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        });
+
+        let Some(values) = found else {
+            self.missed.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        self.matched.fetch_add(1, Ordering::Relaxed);
+
+        for (field, captured, conversion) in values {
+            let conversion = conversion
+                .or_else(|| self.config.convert.get(&field).copied())
+                .unwrap_or(Conversion::String);
+            let value = match conversion.apply(&captured) {
+                Some(value) => value,
+                None => {
+                    self.conversion_errors.fetch_add(1, Ordering::Relaxed);
+                    captured.into()
+                }
+            };
+
+            let key = GelfMessage::additional_field_key(&field);
+            if self.config.overwrite || !message.additional_fields.contains_key(&key) {
+                message.additional_fields.insert(key, value);
+            }
+        }
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.config.name,
+            "matched": self.matched.load(Ordering::Relaxed),
+            "missed": self.missed.load(Ordering::Relaxed),
+            "conversion_errors": self.conversion_errors.load(Ordering::Relaxed)
+        })
+    }
+}
+
+/// Extracts additional fields from text fields with regexes or grok patterns
+///
+/// Every rule is applied to every message; captures are written as
+/// `_`-prefixed additional fields.
+pub struct ExtractFields {
+    rules: Vec<ExtractRule>,
+}
+
+impl ExtractFields {
+    pub fn new(rules: &[ExtractRuleConfig], custom_patterns: &BTreeMap<String, String>) -> Result<Self, ConfigError> {
+        let rules = rules
+            .iter()
+            .map(|rule| ExtractRule::new(rule.clone(), custom_patterns))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { rules })
+    }
+}
+
+impl Processor for ExtractFields {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        for rule in &self.rules {
+            rule.apply(message);
+        }
+        Action::Keep
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "rules": self.rules.iter().map(ExtractRule::stats).collect::<Vec<_>>()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(name: &str) -> ExtractRuleConfig {
+        ExtractRuleConfig {
+            name: name.to_string(),
+            fields: default_fields(),
+            regex: None,
+            grok: None,
+            convert: BTreeMap::new(),
+            overwrite: false,
+        }
+    }
+
+    fn extract(rules: Vec<ExtractRuleConfig>, message: serde_json::Value) -> GelfMessage {
+        let extractor = ExtractFields::new(&rules, &BTreeMap::new()).unwrap();
+        let mut message: GelfMessage = serde_json::from_value(message).unwrap();
+        extractor.process(&mut message, &mut String::new());
+        message
+    }
+
+    #[test]
+    fn regex_captures_become_fields_with_conversions() {
+        let rule = ExtractRuleConfig {
+            regex: Some(r"user=(?P<user>\w+) took (?P<took>\d+)ms".to_string()),
+            convert: BTreeMap::from([("took".to_string(), Conversion::Int)]),
+            ..rule("timing")
+        };
+        let message = extract(vec![rule], json!({ "short_message": "login user=alice took 42ms" }));
+
+        assert_eq!(message.additional_fields["_user"], "alice");
+        assert_eq!(message.additional_fields["_took"], 42);
+    }
+
+    #[test]
+    fn grok_patterns_expand_with_inline_types() {
+        let rule = ExtractRuleConfig {
+            grok: Some("%{IP:client} %{WORD:method} %{URIPATHPARAM:path} %{NUMBER:duration:float}".to_string()),
+            ..rule("access")
+        };
+        let message = extract(vec![rule], json!({ "short_message": "10.1.2.3 GET /items?id=7 0.25" }));
+
+        assert_eq!(message.additional_fields["_client"], "10.1.2.3");
+        assert_eq!(message.additional_fields["_method"], "GET");
+        assert_eq!(message.additional_fields["_path"], "/items?id=7");
+        assert_eq!(message.additional_fields["_duration"], 0.25);
+    }
+
+    #[test]
+    fn bundled_apache_pattern_parses_a_common_log_line() {
+        let rule = ExtractRuleConfig {
+            grok: Some("%{COMMONAPACHELOG}".to_string()),
+            ..rule("apache")
+        };
+        let line = r#"127.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /apache_pb.gif HTTP/1.0" 200 2326"#;
+        let message = extract(vec![rule], json!({ "short_message": line }));
+
+        assert_eq!(message.additional_fields["_clientip"], "127.0.0.1");
+        assert_eq!(message.additional_fields["_auth"], "frank");
+        assert_eq!(message.additional_fields["_request"], "/apache_pb.gif");
+        assert_eq!(message.additional_fields["_response"], 200);
+        assert_eq!(message.additional_fields["_bytes"], 2326);
+    }
+
+    #[test]
+    fn existing_fields_are_kept_unless_overwrite_is_set() {
+        let regex = Some(r"user=(?P<user>\w+)".to_string());
+        let message = json!({ "short_message": "user=alice", "_user": "root" });
+
+        let kept = extract(vec![ExtractRuleConfig { regex: regex.clone(), ..rule("keep") }], message.clone());
+        assert_eq!(kept.additional_fields["_user"], "root");
+
+        let replaced = extract(vec![ExtractRuleConfig { regex, overwrite: true, ..rule("replace") }], message);
+        assert_eq!(replaced.additional_fields["_user"], "alice");
+    }
+
+    #[test]
+    fn failed_conversions_keep_the_text_and_are_counted() {
+        let extractor = ExtractFields::new(
+            &[ExtractRuleConfig {
+                regex: Some(r"code=(?P<code>\S+)".to_string()),
+                convert: BTreeMap::from([("code".to_string(), Conversion::Int)]),
+                ..rule("code")
+            }],
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        let mut message: GelfMessage = serde_json::from_value(json!({ "short_message": "code=E42" })).unwrap();
+        extractor.process(&mut message, &mut String::new());
+
+        assert_eq!(message.additional_fields["_code"], "E42");
+        assert_eq!(extractor.stats().unwrap()["rules"][0]["conversion_errors"], 1);
+    }
+
+    #[test]
+    fn the_first_matching_field_is_used() {
+        let rule = ExtractRuleConfig {
+            fields: vec!["short_message".to_string(), "full_message".to_string()],
+            regex: Some(r"order (?P<order>\d+)".to_string()),
+            ..rule("order")
+        };
+        let message = extract(
+            vec![rule],
+            json!({ "short_message": "payment failed", "full_message": "order 7 was declined" }),
+        );
+        assert_eq!(message.additional_fields["_order"], "7");
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let custom = BTreeMap::from([("LOOP".to_string(), "%{LOOP}".to_string())]);
+        let cases = [
+            ExtractRuleConfig { grok: Some("%{NOPE:x}".to_string()), ..rule("unknown") },
+            ExtractRuleConfig { grok: Some("%{LOOP:x}".to_string()), ..rule("recursive") },
+            ExtractRuleConfig { grok: Some("%{INT:x:hex}".to_string()), ..rule("conversion") },
+            ExtractRuleConfig { grok: Some("%{INT:x".to_string()), ..rule("unterminated") },
+            ExtractRuleConfig { regex: Some("(".to_string()), ..rule("regex") },
+            rule("neither"),
+        ];
+        for case in cases {
+            let name = case.name.clone();
+            assert!(ExtractFields::new(&[case], &custom).is_err(), "{}", name);
+        }
+    }
+}
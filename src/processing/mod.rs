@@ -0,0 +1,194 @@
+pub mod embedded;
+pub mod extract;
+pub mod redact;
+pub mod sample;
+pub mod transform;
+
+use crate::config::ConfigError;
+use crate::gelf::GelfMessage;
+use crate::query::Query;
+use crate::storage::StatsProvider;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+pub use embedded::{EmbeddedConfig, EmbeddedFormat, EmbeddedPayloads};
+pub use extract::{ExtractFields, ExtractRuleConfig};
+pub use redact::{BuiltinDetector, RedactConfig, RedactValues};
+pub use sample::{SampleMessages, SampleRuleConfig};
+pub use transform::{AddFields, DropMessages, RemoveFields, RenameFields, SetFields};
+
+/// What happens to a message after a processor ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Keep,
+    Drop,
+}
+
+/// A step between parsing a message and storing it
+pub trait Processor: Send + Sync {
+    /// Modify the message in place, or ask for it to be dropped
+    fn process(&self, message: &mut GelfMessage, raw_message: &mut String) -> Action;
+
+    /// Processor-specific counters reported in `/stats`
+    fn stats(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// One entry of the `processors` list in the config file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ProcessorConfig {
+    /// Rename fields, from old name to new name
+    Rename { fields: BTreeMap<String, String> },
+    /// Remove fields
+    Remove { fields: Vec<String> },
+    /// Add fields with static values
+    Add {
+        fields: serde_json::Map<String, serde_json::Value>,
+        /// Replace fields the message already has
+        #[serde(default)]
+        overwrite: bool,
+    },
+    /// Set fields on messages matching a query
+    Set {
+        filter: String,
+        fields: serde_json::Map<String, serde_json::Value>,
+    },
+    /// Drop messages matching a query
+    Drop { filter: String },
+    /// Extract additional fields with regexes or grok patterns
+    Extract {
+        rules: Vec<ExtractRuleConfig>,
+        /// Custom grok patterns, by name
+        #[serde(default)]
+        patterns: BTreeMap<String, String>,
+    },
+    /// Lift the keys of JSON or logfmt payloads in text fields into additional fields
+    Embedded(EmbeddedConfig),
+    /// Mask sensitive values such as emails, card numbers and tokens
+    Redact(RedactConfig),
+    /// Keep a fraction of the messages matching each rule
+    Sample { rules: Vec<SampleRuleConfig> },
+}
+
+fn parse_filter(kind: &str, filter: &str) -> Result<Query, ConfigError> {
+    Query::parse(filter).map_err(|e| ConfigError::Invalid(format!("{} processor filter: {}", kind, e)))
+}
+
+impl ProcessorConfig {
+    /// Name of the processor type, as used in the config file
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProcessorConfig::Rename { .. } => "rename",
+            ProcessorConfig::Remove { .. } => "remove",
+            ProcessorConfig::Add { .. } => "add",
+            ProcessorConfig::Set { .. } => "set",
+            ProcessorConfig::Drop { .. } => "drop",
+            ProcessorConfig::Extract { .. } => "extract",
+            ProcessorConfig::Embedded(_) => "embedded",
+            ProcessorConfig::Redact(_) => "redact",
+            ProcessorConfig::Sample { .. } => "sample",
+        }
+    }
+
+    pub fn build(&self) -> Result<Box<dyn Processor>, ConfigError> {
+        Ok(match self {
+            ProcessorConfig::Rename { fields } => Box::new(RenameFields::new(fields.clone())),
+            ProcessorConfig::Remove { fields } => Box::new(RemoveFields::new(fields.clone())),
+            ProcessorConfig::Add { fields, overwrite } => Box::new(AddFields::new(fields.clone(), *overwrite)),
+            ProcessorConfig::Set { filter, fields } => {
+                Box::new(SetFields::new(parse_filter(self.kind(), filter)?, fields.clone()))
+            }
+            ProcessorConfig::Drop { filter } => Box::new(DropMessages::new(parse_filter(self.kind(), filter)?)),
+            ProcessorConfig::Extract { rules, patterns } => Box::new(ExtractFields::new(rules, patterns)?),
+            ProcessorConfig::Embedded(config) => Box::new(EmbeddedPayloads::new(config.clone())),
+            ProcessorConfig::Redact(config) => Box::new(RedactValues::new(config)?),
+            ProcessorConfig::Sample { rules } => Box::new(SampleMessages::new(rules)?),
+        })
+    }
+}
+
+struct Stage {
+    kind: String,
+    processor: Box<dyn Processor>,
+    processed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Ordered processors every input runs its messages through before storing them
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(configs: &[ProcessorConfig]) -> Result<Self, ConfigError> {
+        let mut pipeline = Self::new();
+        for config in configs {
+            pipeline.add(config.kind(), config.build()?);
+        }
+        Ok(pipeline)
+    }
+
+    /// Append a processor; `kind` identifies it in the statistics
+    pub fn add(&mut self, kind: impl Into<String>, processor: Box<dyn Processor>) {
+        self.stages.push(Stage {
+            kind: kind.into(),
+            processor,
+            processed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Run a message through every processor, stopping at the first that drops it
+    pub fn run(&self, message: &mut GelfMessage, raw_message: &mut String) -> Action {
+        for stage in &self.stages {
+            stage.processed.fetch_add(1, Ordering::Relaxed);
+            if stage.processor.process(message, raw_message) == Action::Drop {
+                debug!("Message dropped by {} processor", stage.kind);
+                stage.dropped.fetch_add(1, Ordering::Relaxed);
+                return Action::Drop;
+            }
+        }
+        Action::Keep
+    }
+}
+
+impl StatsProvider for Pipeline {
+    fn stats(&self) -> serde_json::Value {
+        self.stages
+            .iter()
+            .map(|stage| {
+                let mut stats = serde_json::json!({
+                    "type": stage.kind,
+                    "processed": stage.processed.load(Ordering::Relaxed),
+                    "dropped": stage.dropped.load(Ordering::Relaxed)
+                });
+                if let Some(extra) = stage.processor.stats() {
+                    stats["stats"] = extra;
+                }
+                stats
+            })
+            .collect()
+    }
+}
+
+/// Time used when evaluating queries on messages that are not stored yet
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
@@ -0,0 +1,479 @@
+use super::{Action, Processor};
+use crate::config::ConfigError;
+use crate::gelf::GelfMessage;
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Check on the characters before and after a candidate
+type NeighbourCheck = fn(Option<char>, Option<char>) -> bool;
+
+/// Bundled detectors for sensitive values
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinDetector {
+    Jwt,
+    Bearer,
+    AwsKey,
+    Email,
+    CreditCard,
+    Ipv4,
+    Ipv6,
+}
+
+impl BuiltinDetector {
+    const ALL: [BuiltinDetector; 7] = [
+        BuiltinDetector::Jwt,
+        BuiltinDetector::Bearer,
+        BuiltinDetector::AwsKey,
+        BuiltinDetector::Email,
+        BuiltinDetector::CreditCard,
+        BuiltinDetector::Ipv4,
+        BuiltinDetector::Ipv6,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            BuiltinDetector::Jwt => "jwt",
+            BuiltinDetector::Bearer => "bearer",
+            BuiltinDetector::AwsKey => "aws_key",
+            BuiltinDetector::Email => "email",
+            BuiltinDetector::CreditCard => "credit_card",
+            BuiltinDetector::Ipv4 => "ipv4",
+            BuiltinDetector::Ipv6 => "ipv6",
+        }
+    }
+
+    /// Candidate pattern; a group named `value` limits the mask to that part
+    fn pattern(self) -> &'static str {
+        match self {
+            BuiltinDetector::Jwt => r"\beyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*",
+            BuiltinDetector::Bearer => r"(?i)\bbearer\s+(?P<value>[A-Za-z0-9._~+/-]{8,}=*)",
+            BuiltinDetector::AwsKey => {
+                r#"\b(?:AKIA|ASIA|AGPA|AIDA|AROA|ANPA|ANVA|AIPA)[0-9A-Z]{16}\b|(?i:aws_?secret_?access_?key)["']?\s*[:=]\s*["']?(?P<value>[A-Za-z0-9/+=]{40})"#
+            }
+            BuiltinDetector::Email => r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+            BuiltinDetector::CreditCard => r"\b\d(?:[ -]?\d){12,18}\b",
+            BuiltinDetector::Ipv4 => r"\b(?:\d{1,3}\.){3}\d{1,3}\b",
+            BuiltinDetector::Ipv6 => r"(?i)(?:[0-9a-f]{0,4}:){2,7}[0-9a-f]{0,4}(?:%[0-9a-z]+)?",
+        }
+    }
+
+    /// Check on a candidate that the pattern alone cannot express
+    fn validator(self) -> Option<fn(&str) -> bool> {
+        match self {
+            BuiltinDetector::CreditCard => Some(card_number_valid),
+            BuiltinDetector::Ipv4 => Some(|value| value.parse::<Ipv4Addr>().is_ok()),
+            BuiltinDetector::Ipv6 => Some(|value| {
+                let address = value.split('%').next().unwrap_or(value);
+                address.chars().any(|c| c.is_ascii_hexdigit()) && address.parse::<Ipv6Addr>().is_ok()
+            }),
+            _ => None,
+        }
+    }
+
+    /// Check on the characters around a candidate, for boundaries `\b` cannot express
+    ///
+    /// The characters are checked after matching instead of being part of the
+    /// pattern, so that adjacent candidates can share a separator.
+    fn neighbours(self) -> Option<NeighbourCheck> {
+        match self {
+            BuiltinDetector::Ipv6 => Some(|before, after| {
+                let address = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == ':';
+                !before.is_some_and(|c| address(c) || c == '.') && !after.is_some_and(address)
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Issuer prefixes as `(first, last, lengths)`; the prefix length is the number of digits of `first`
+const CARD_RANGES: [(u32, u32, &[usize]); 13] = [
+    (4, 4, &[13, 16, 19]),
+    (51, 55, &[16]),
+    (2221, 2720, &[16]),
+    (34, 34, &[15]),
+    (37, 37, &[15]),
+    (6011, 6011, &[16, 17, 18, 19]),
+    (644, 649, &[16, 17, 18, 19]),
+    (65, 65, &[16, 17, 18, 19]),
+    (300, 305, &[14, 15, 16, 17, 18, 19]),
+    (36, 36, &[14, 15, 16, 17, 18, 19]),
+    (38, 39, &[14, 15, 16, 17, 18, 19]),
+    (3528, 3589, &[16, 17, 18, 19]),
+    (62, 62, &[16, 17, 18, 19]),
+];
+
+/// Whether a candidate has the prefix and length of a known card network and passes the Luhn check
+///
+/// The prefixes keep numbers like millisecond timestamps from being masked.
+fn card_number_valid(value: &str) -> bool {
+    let digits: Vec<u32> = value.chars().filter_map(|c| c.to_digit(10)).collect();
+    let known = CARD_RANGES.iter().any(|&(first, last, lengths)| {
+        let width = first.to_string().len();
+        let prefix = digits.iter().take(width).fold(0, |prefix, digit| prefix * 10 + digit);
+        digits.len() >= width && (first..=last).contains(&prefix) && lengths.contains(&digits.len())
+    });
+    known && luhn_valid(value)
+}
+
+fn luhn_valid(value: &str) -> bool {
+    let digits: Vec<u32> = value.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &digit)| match i % 2 {
+            0 => digit,
+            _ if digit * 2 > 9 => digit * 2 - 9,
+            _ => digit * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+fn default_detectors() -> Vec<BuiltinDetector> {
+    BuiltinDetector::ALL.to_vec()
+}
+
+fn default_replacement() -> String {
+    "[REDACTED:{{detector}}]".to_string()
+}
+
+/// Settings of the `redact` processor
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedactConfig {
+    /// Bundled detectors to apply, in order
+    #[serde(default = "default_detectors")]
+    pub detectors: Vec<BuiltinDetector>,
+    /// Additional detectors, by name, applied after the bundled ones
+    #[serde(default)]
+    pub custom: BTreeMap<String, String>,
+    /// Fields left as they are
+    #[serde(default)]
+    pub skip_fields: Vec<String>,
+    /// Text a match is replaced with; `{{detector}}` is replaced with the detector name
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+}
+
+struct Detector {
+    name: String,
+    regex: Regex,
+    validator: Option<fn(&str) -> bool>,
+    neighbours: Option<NeighbourCheck>,
+    replacement: String,
+    redactions: AtomicU64,
+}
+
+impl Detector {
+    fn redact<'t>(&self, text: &'t str, count: bool) -> Cow<'t, str> {
+        self.regex.replace_all(text, |captures: &Captures| {
+            let whole = captures.get(0).unwrap();
+            let value = captures.name("value").unwrap_or(whole);
+            let before = text[..whole.start()].chars().next_back();
+            let after = text[whole.end()..].chars().next();
+            if self.validator.is_some_and(|valid| !valid(value.as_str()))
+                || self.neighbours.is_some_and(|isolated| !isolated(before, after))
+            {
+                return whole.as_str().to_string();
+            }
+            if count {
+                self.redactions.fetch_add(1, Ordering::Relaxed);
+            }
+            format!(
+                "{}{}{}",
+                &whole.as_str()[..value.start() - whole.start()],
+                self.replacement,
+                &whole.as_str()[value.end() - whole.start()..]
+            )
+        })
+    }
+}
+
+/// Masks sensitive values in text fields and the raw message
+///
+/// Every detector is applied to `short_message`, `full_message` and the text
+/// values of additional fields, nested ones included, except the skipped
+/// fields. The raw message is redacted value by value after decoding its
+/// JSON, so escaped text cannot hide a match.
+pub struct RedactValues {
+    detectors: Vec<Detector>,
+    skip_fields: Vec<String>,
+    messages: AtomicU64,
+}
+
+impl RedactValues {
+    pub fn new(config: &RedactConfig) -> Result<Self, ConfigError> {
+        let mut detectors = Vec::new();
+        let builtin = config
+            .detectors
+            .iter()
+            .map(|detector| {
+                (detector.name().to_string(), detector.pattern(), detector.validator(), detector.neighbours())
+            });
+        let custom = config
+            .custom
+            .iter()
+            .map(|(name, pattern)| (name.clone(), pattern.as_str(), None, None));
+
+        for (name, pattern, validator, neighbours) in builtin.chain(custom) {
+            if detectors.iter().any(|detector: &Detector| detector.name == name) {
+                return Err(ConfigError::Invalid(format!("redact detector '{}' is listed twice", name)));
+            }
+            let regex = Regex::new(pattern)
+                .map_err(|e| ConfigError::Invalid(format!("redact detector '{}': {}", name, e)))?;
+            detectors.push(Detector {
+                replacement: config.replacement.replace("{{detector}}", &name),
+                name,
+                regex,
+                validator,
+                neighbours,
+                redactions: AtomicU64::new(0),
+            });
+        }
+
+        Ok(Self {
+            detectors,
+            skip_fields: config
+                .skip_fields
+                .iter()
+                .map(|field| field.strip_prefix('_').unwrap_or(field).to_string())
+                .collect(),
+            messages: AtomicU64::new(0),
+        })
+    }
+
+    fn skipped(&self, field: &str) -> bool {
+        let field = field.strip_prefix('_').unwrap_or(field);
+        self.skip_fields.iter().any(|skipped| skipped == field)
+    }
+
+    /// Redact a text in place, returning whether anything was masked
+    ///
+    /// The raw message repeats the parsed fields, so matches in it are not counted.
+    fn redact(&self, text: &mut String, count: bool) -> bool {
+        let mut changed = false;
+        for detector in &self.detectors {
+            if let Cow::Owned(redacted) = detector.redact(text, count)
+                && redacted != *text
+            {
+                *text = redacted;
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Redact every text inside a JSON value; numbers are left alone
+    fn redact_value(&self, value: &mut serde_json::Value, count: bool) -> bool {
+        match value {
+            serde_json::Value::String(text) => self.redact(text, count),
+            serde_json::Value::Array(values) => values
+                .iter_mut()
+                .fold(false, |changed, value| self.redact_value(value, count) | changed),
+            serde_json::Value::Object(fields) => fields
+                .values_mut()
+                .fold(false, |changed, value| self.redact_value(value, count) | changed),
+            _ => false,
+        }
+    }
+
+    /// Redact the raw message, re-serializing it only when something was masked
+    fn redact_raw(&self, raw_message: &mut String) {
+        let Ok(serde_json::Value::Object(mut fields)) = serde_json::from_str(raw_message) else {
+            self.redact(raw_message, false);
+            return;
+        };
+        let mut changed = false;
+        for (key, value) in fields.iter_mut() {
+            if !self.skipped(key) {
+                changed |= self.redact_value(value, false);
+            }
+        }
+        if changed && let Ok(redacted) = serde_json::to_string(&fields) {
+            *raw_message = redacted;
+        }
+    }
+}
+
+impl Processor for RedactValues {
+    fn process(&self, message: &mut GelfMessage, raw_message: &mut String) -> Action {
+        let mut changed = false;
+
+        for (name, text) in [
+            ("short_message", message.short_message.as_mut()),
+            ("full_message", message.full_message.as_mut()),
+        ] {
+            if let Some(text) = text
+                && !self.skipped(name)
+            {
+                changed |= self.redact(text, true);
+            }
+        }
+
+        for (key, value) in message.additional_fields.iter_mut() {
+            if !self.skipped(key) {
+                changed |= self.redact_value(value, true);
+            }
+        }
+
+        if !self.skipped("raw_message") {
+            self.redact_raw(raw_message);
+        }
+
+        if changed {
+            self.messages.fetch_add(1, Ordering::Relaxed);
+        }
+        Action::Keep
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        let redactions: serde_json::Map<String, serde_json::Value> = self
+            .detectors
+            .iter()
+            .map(|detector| (detector.name.clone(), detector.redactions.load(Ordering::Relaxed).into()))
+            .collect();
+        Some(serde_json::json!({
+            "messages": self.messages.load(Ordering::Relaxed),
+            "redactions": redactions
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redactor(detectors: &[BuiltinDetector]) -> RedactValues {
+        RedactValues::new(&RedactConfig {
+            detectors: detectors.to_vec(),
+            custom: BTreeMap::new(),
+            skip_fields: Vec::new(),
+            replacement: default_replacement(),
+        })
+        .unwrap()
+    }
+
+    fn redacted(redactor: &RedactValues, text: &str) -> String {
+        let mut text = text.to_string();
+        redactor.redact(&mut text, true);
+        text
+    }
+
+    fn parse(raw: &str) -> GelfMessage {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn luhn_accepts_valid_checksums_only() {
+        assert!(luhn_valid("4111 1111 1111 1111"));
+        assert!(luhn_valid("378282246310005"));
+        assert!(!luhn_valid("4111 1111 1111 1112"));
+        assert!(!luhn_valid("123456789012"));
+    }
+
+    #[test]
+    fn card_numbers_need_a_known_prefix_and_length() {
+        assert!(card_number_valid("4111-1111-1111-1111"));
+        assert!(card_number_valid("5555555555554444"));
+        assert!(card_number_valid("2223003122003222"));
+        assert!(card_number_valid("378282246310005"));
+        assert!(card_number_valid("6011111111111117"));
+        // Luhn-valid, but a Visa number cannot have 15 digits
+        assert!(!card_number_valid("411111111111116"));
+        // Luhn-valid millisecond timestamp
+        assert!(luhn_valid("1714557600009"));
+        assert!(!card_number_valid("1714557600009"));
+    }
+
+    #[test]
+    fn epoch_milliseconds_are_not_masked() {
+        let redactor = redactor(&[BuiltinDetector::CreditCard]);
+        let masked = (1_714_557_600_000u64..1_714_557_601_000)
+            .map(|millis| millis.to_string())
+            .filter(|millis| redacted(&redactor, millis) != *millis)
+            .count();
+        assert_eq!(masked, 0);
+        assert_eq!(redacted(&redactor, "paid with 4111 1111 1111 1111"), "paid with [REDACTED:credit_card]");
+    }
+
+    #[test]
+    fn adjacent_ipv6_addresses_are_both_masked() {
+        let redactor = redactor(&[BuiltinDetector::Ipv6]);
+        assert_eq!(
+            redacted(&redactor, "fe80::1 2001:db8::2"),
+            "[REDACTED:ipv6] [REDACTED:ipv6]"
+        );
+        assert_eq!(redacted(&redactor, "peers fe80::1,::1."), "peers [REDACTED:ipv6],[REDACTED:ipv6].");
+        assert_eq!(redacted(&redactor, "at 12:30:45"), "at 12:30:45");
+        assert_eq!(redacted(&redactor, "xfe80::1"), "xfe80::1");
+    }
+
+    #[test]
+    fn nested_additional_fields_are_redacted() {
+        let redactor = redactor(&[BuiltinDetector::Email]);
+        let raw = r#"{"short_message":"login","_user":{"emails":["a@example.com",{"work":"b@example.com"}],"id":4111111111111111}}"#;
+        let mut message = parse(raw);
+        let mut raw = raw.to_string();
+        redactor.process(&mut message, &mut raw);
+
+        assert_eq!(
+            message.additional_fields["_user"],
+            json!({"emails": ["[REDACTED:email]", {"work": "[REDACTED:email]"}], "id": 4111111111111111u64})
+        );
+        assert!(!raw.contains("example.com"));
+    }
+
+    #[test]
+    fn escaped_values_in_the_raw_message_are_redacted() {
+        let redactor = redactor(&[BuiltinDetector::Email, BuiltinDetector::Bearer]);
+        let raw = r#"{"short_message":"mail a@b.com","_auth":"Bearer abc\/def\/ghi123"}"#;
+        let mut message = parse(raw);
+        let mut raw = raw.to_string();
+        redactor.process(&mut message, &mut raw);
+
+        let raw: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(raw["short_message"], "mail [REDACTED:email]");
+        assert_eq!(raw["_auth"], "Bearer [REDACTED:bearer]");
+        assert_eq!(message.short_message.as_deref(), Some("mail [REDACTED:email]"));
+    }
+
+    #[test]
+    fn unchanged_raw_messages_keep_their_text() {
+        let redactor = redactor(&[BuiltinDetector::Email]);
+        let original = r#"{ "short_message": "nothing here", "timestamp": 1714557600002 }"#;
+        let mut message = parse(original);
+        let mut raw = original.to_string();
+        redactor.process(&mut message, &mut raw);
+        assert_eq!(raw, original);
+    }
+
+    #[test]
+    fn skipped_fields_stay_readable_in_the_raw_message() {
+        let redactor = RedactValues::new(&RedactConfig {
+            detectors: vec![BuiltinDetector::Email],
+            custom: BTreeMap::new(),
+            skip_fields: vec!["contact".to_string()],
+            replacement: default_replacement(),
+        })
+        .unwrap();
+        let raw = r#"{"short_message":"from a@example.com","_contact":"support@example.com"}"#;
+        let mut message = parse(raw);
+        let mut raw = raw.to_string();
+        redactor.process(&mut message, &mut raw);
+
+        let raw: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(raw["short_message"], "from [REDACTED:email]");
+        assert_eq!(raw["_contact"], "support@example.com");
+        assert_eq!(message.additional_fields["_contact"], "support@example.com");
+    }
+}
@@ -0,0 +1,190 @@
+use super::{now, Action, Processor};
+use crate::config::ConfigError;
+use crate::gelf::GelfMessage;
+use crate::query::Query;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+fn default_keep_one_in() -> u64 {
+    1
+}
+
+/// One sampling rule of a `sample` processor
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SampleRuleConfig {
+    pub name: String,
+    /// Query selecting the messages this rule applies to
+    pub filter: String,
+    /// Keep one in this many matching messages; 1 keeps all
+    #[serde(default = "default_keep_one_in")]
+    pub keep_one_in: u64,
+    /// Field whose value decides, so messages sharing it are kept or dropped together
+    #[serde(default)]
+    pub key: Option<String>,
+}
+
+/// 64-bit FNV-1a, stable across builds so sampling decisions are reproducible
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+struct SampleRule {
+    config: SampleRuleConfig,
+    filter: Query,
+    matched: AtomicU64,
+    sampled_out: AtomicU64,
+}
+
+impl SampleRule {
+    /// Whether to keep a message, `seen` being the number of earlier matches
+    fn keep(&self, message: &GelfMessage, seen: u64) -> bool {
+        let rate = self.config.keep_one_in;
+        let key = self.config.key.as_deref().and_then(|field| message.get_field(field));
+        match key {
+            Some(serde_json::Value::String(text)) => fnv1a(&text).is_multiple_of(rate),
+            Some(value) => fnv1a(&value.to_string()).is_multiple_of(rate),
+            // Without a key every n-th message is kept
+            None => seen.is_multiple_of(rate),
+        }
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        let matched = self.matched.load(Ordering::Relaxed);
+        let sampled_out = self.sampled_out.load(Ordering::Relaxed);
+        serde_json::json!({
+            "name": self.config.name,
+            "keep_one_in": self.config.keep_one_in,
+            "matched": matched,
+            // Both counters move independently, so sampled_out may already count a newer match
+            "kept": matched.saturating_sub(sampled_out),
+            "sampled_out": sampled_out
+        })
+    }
+}
+
+/// Keeps a fraction of the messages matching each rule
+///
+/// The first rule whose filter matches decides; messages matching no rule
+/// are kept. Kept messages of rules that drop some get a `_sample_rate`
+/// field, so counts can be scaled back up.
+pub struct SampleMessages {
+    rules: Vec<SampleRule>,
+}
+
+impl SampleMessages {
+    pub fn new(rules: &[SampleRuleConfig]) -> Result<Self, ConfigError> {
+        let rules = rules
+            .iter()
+            .map(|config| {
+                if config.keep_one_in == 0 {
+                    return Err(ConfigError::Invalid(format!(
+                        "sample rule '{}': keep_one_in must be at least 1",
+                        config.name
+                    )));
+                }
+                let filter = Query::parse(&config.filter)
+                    .map_err(|e| ConfigError::Invalid(format!("sample rule '{}' filter: {}", config.name, e)))?;
+                Ok(SampleRule {
+                    config: config.clone(),
+                    filter,
+                    matched: AtomicU64::new(0),
+                    sampled_out: AtomicU64::new(0),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { rules })
+    }
+}
+
+impl Processor for SampleMessages {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        let now = now();
+        let Some(rule) = self.rules.iter().find(|rule| rule.filter.matches(message, now)) else {
+            return Action::Keep;
+        };
+
+        let seen = rule.matched.fetch_add(1, Ordering::Relaxed);
+        if rule.config.keep_one_in == 1 {
+            return Action::Keep;
+        }
+        if !rule.keep(message, seen) {
+            rule.sampled_out.fetch_add(1, Ordering::Relaxed);
+            return Action::Drop;
+        }
+        message
+            .additional_fields
+            .insert("_sample_rate".to_string(), rule.config.keep_one_in.into());
+        Action::Keep
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "rules": self.rules.iter().map(SampleRule::stats).collect::<Vec<_>>()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampler(keep_one_in: u64, key: Option<&str>) -> SampleMessages {
+        SampleMessages::new(&[SampleRuleConfig {
+            name: "debug".to_string(),
+            filter: "level:7".to_string(),
+            keep_one_in,
+            key: key.map(str::to_string),
+        }])
+        .unwrap()
+    }
+
+    fn kept(sampler: &SampleMessages, message: serde_json::Value) -> bool {
+        let mut message: GelfMessage = serde_json::from_value(message).unwrap();
+        sampler.process(&mut message, &mut String::new()) == Action::Keep
+    }
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        assert_eq!(fnv1a(""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a("a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a("foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn keyed_decisions_are_deterministic() {
+        let first = sampler(4, Some("_request_id"));
+        let second = sampler(4, Some("_request_id"));
+        for id in 0..200 {
+            let message = serde_json::json!({ "level": 7, "_request_id": format!("req-{}", id) });
+            let decision = kept(&first, message.clone());
+            assert_eq!(kept(&first, message.clone()), decision, "req-{}", id);
+            assert_eq!(kept(&second, message), decision, "req-{}", id);
+            assert_eq!(decision, fnv1a(&format!("req-{}", id)).is_multiple_of(4));
+        }
+    }
+
+    #[test]
+    fn numeric_keys_hash_their_json_text() {
+        let sampler = sampler(3, Some("_user_id"));
+        for id in 0..50u64 {
+            let decision = kept(&sampler, serde_json::json!({ "level": 7, "_user_id": id }));
+            assert_eq!(decision, fnv1a(&id.to_string()).is_multiple_of(3));
+        }
+    }
+
+    #[test]
+    fn unkeyed_rules_keep_every_nth_match() {
+        let sampler = sampler(3, None);
+        let decisions: Vec<bool> = (0..7)
+            .map(|_| kept(&sampler, serde_json::json!({ "level": 7 })))
+            .collect();
+        assert_eq!(decisions, [true, false, false, true, false, false, true]);
+        assert!(kept(&sampler, serde_json::json!({ "level": 3 })));
+
+        let stats = &sampler.stats().unwrap()["rules"][0];
+        assert_eq!((stats["matched"].as_u64(), stats["kept"].as_u64()), (Some(7), Some(3)));
+    }
+}
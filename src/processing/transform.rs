@@ -0,0 +1,321 @@
+use super::{now, Action, Processor};
+use crate::gelf::GelfMessage;
+use crate::query::{field_value, Query};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::debug;
+
+/// Counts values that could not be written, e.g. text into `level`
+#[derive(Default)]
+struct ErrorCount(AtomicU64);
+
+impl ErrorCount {
+    fn record(&self, result: Result<(), String>) {
+        if let Err(e) = result {
+            debug!("Processor could not set field: {}", e);
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "errors": self.0.load(Ordering::Relaxed) }))
+    }
+}
+
+/// Renames fields; a value that does not fit the new field is left in place
+pub struct RenameFields {
+    fields: BTreeMap<String, String>,
+    errors: ErrorCount,
+}
+
+impl RenameFields {
+    pub fn new(fields: BTreeMap<String, String>) -> Self {
+        Self {
+            fields,
+            errors: ErrorCount::default(),
+        }
+    }
+}
+
+impl Processor for RenameFields {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        for (from, to) in &self.fields {
+            let Some(value) = message.remove_field(from) else {
+                continue;
+            };
+            if let Err(e) = message.set_field(to, value.clone()) {
+                self.errors.record(Err(e));
+                let _ = message.set_field(from, value);
+            }
+        }
+        Action::Keep
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        self.errors.stats()
+    }
+}
+
+/// Removes fields
+pub struct RemoveFields {
+    fields: Vec<String>,
+}
+
+impl RemoveFields {
+    pub fn new(fields: Vec<String>) -> Self {
+        Self { fields }
+    }
+}
+
+impl Processor for RemoveFields {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        for field in &self.fields {
+            message.remove_field(field);
+        }
+        Action::Keep
+    }
+}
+
+/// Adds fields with static values, keeping existing values unless told otherwise
+pub struct AddFields {
+    fields: serde_json::Map<String, serde_json::Value>,
+    overwrite: bool,
+    errors: ErrorCount,
+}
+
+impl AddFields {
+    pub fn new(fields: serde_json::Map<String, serde_json::Value>, overwrite: bool) -> Self {
+        Self {
+            fields,
+            overwrite,
+            errors: ErrorCount::default(),
+        }
+    }
+}
+
+impl Processor for AddFields {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        for (name, value) in &self.fields {
+            if self.overwrite || message.get_field(name).is_none() {
+                self.errors.record(message.set_field(name, value.clone()));
+            }
+        }
+        Action::Keep
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        self.errors.stats()
+    }
+}
+
+/// Fill in `{{field}}` placeholders from the message; missing fields render as nothing
+fn render(template: &str, message: &GelfMessage, received_at: f64) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = rest[start + 2..start + end].trim();
+        if let Some(value) = field_value(message, received_at, name) {
+            rendered.push_str(&value.as_text());
+        }
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Sets fields on messages matching a query; text values may hold `{{field}}` placeholders
+pub struct SetFields {
+    filter: Query,
+    fields: serde_json::Map<String, serde_json::Value>,
+    matched: AtomicU64,
+    errors: ErrorCount,
+}
+
+impl SetFields {
+    pub fn new(filter: Query, fields: serde_json::Map<String, serde_json::Value>) -> Self {
+        Self {
+            filter,
+            fields,
+            matched: AtomicU64::new(0),
+            errors: ErrorCount::default(),
+        }
+    }
+}
+
+impl Processor for SetFields {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        let now = now();
+        if self.filter.matches(message, now) {
+            self.matched.fetch_add(1, Ordering::Relaxed);
+            // Placeholders see the message as it was before any field was set
+            let values: Vec<serde_json::Value> = self
+                .fields
+                .values()
+                .map(|value| match value.as_str() {
+                    Some(template) if template.contains("{{") => render(template, message, now).into(),
+                    _ => value.clone(),
+                })
+                .collect();
+            for (name, value) in self.fields.keys().zip(values) {
+                self.errors.record(message.set_field(name, value));
+            }
+        }
+        Action::Keep
+    }
+
+    fn stats(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({
+            "matched": self.matched.load(Ordering::Relaxed),
+            "errors": self.errors.0.load(Ordering::Relaxed)
+        }))
+    }
+}
+
+/// Drops messages matching a query
+pub struct DropMessages {
+    filter: Query,
+}
+
+impl DropMessages {
+    pub fn new(filter: Query) -> Self {
+        Self { filter }
+    }
+}
+
+impl Processor for DropMessages {
+    fn process(&self, message: &mut GelfMessage, _raw_message: &mut String) -> Action {
+        if self.filter.matches(message, now()) {
+            Action::Drop
+        } else {
+            Action::Keep
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message() -> GelfMessage {
+        serde_json::from_value(json!({
+            "host": "web-1",
+            "short_message": "payment failed",
+            "level": 3,
+            "_service": "payments",
+            "_env": "staging"
+        }))
+        .unwrap()
+    }
+
+    fn fields(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    fn process(processor: &impl Processor, message: &mut GelfMessage) -> Action {
+        processor.process(message, &mut String::new())
+    }
+
+    #[test]
+    fn add_keeps_existing_values_unless_overwriting() {
+        let added = fields(json!({ "env": "prod", "_region": "eu-1" }));
+
+        let mut kept = message();
+        process(&AddFields::new(added.clone(), false), &mut kept);
+        assert_eq!(kept.additional_fields["_env"], "staging");
+        assert_eq!(kept.additional_fields["_region"], "eu-1");
+
+        let mut overwritten = message();
+        process(&AddFields::new(added, true), &mut overwritten);
+        assert_eq!(overwritten.additional_fields["_env"], "prod");
+        assert!(!overwritten.additional_fields.contains_key("env"));
+    }
+
+    #[test]
+    fn set_overwrites_fields_of_matching_messages() {
+        let set = SetFields::new(
+            Query::parse("level:<=3").unwrap(),
+            fields(json!({ "_env": "prod", "level": 2 })),
+        );
+        let mut matching = message();
+        process(&set, &mut matching);
+        assert_eq!((matching.level, matching.additional_fields["_env"].clone()), (Some(2), json!("prod")));
+
+        let mut other = message();
+        other.level = Some(6);
+        process(&set, &mut other);
+        assert_eq!(other.additional_fields["_env"], "staging");
+        assert_eq!(set.stats().unwrap(), json!({ "matched": 1, "errors": 0 }));
+    }
+
+    #[test]
+    fn set_values_fill_in_placeholders() {
+        let set = SetFields::new(
+            Query::parse("*").unwrap(),
+            fields(json!({
+                "_route": "{{host}}/{{ _service }}/{{level}}",
+                "_service": "billing",
+                "_owner": "{{_team}} on call",
+                "_note": "literal {{host",
+                "_count": 3
+            })),
+        );
+        let mut message = message();
+        process(&set, &mut message);
+
+        // Placeholders see values from before the processor ran
+        assert_eq!(message.additional_fields["_route"], "web-1/payments/3");
+        assert_eq!(message.additional_fields["_service"], "billing");
+        assert_eq!(message.additional_fields["_owner"], " on call");
+        assert_eq!(message.additional_fields["_note"], "literal {{host");
+        assert_eq!(message.additional_fields["_count"], 3);
+    }
+
+    #[test]
+    fn the_reserved_id_field_is_never_written() {
+        let add = AddFields::new(fields(json!({ "id": 1, "_id": 2, "_ok": true })), true);
+        let mut message = message();
+        process(&add, &mut message);
+        assert!(!message.additional_fields.contains_key("_id"));
+        assert_eq!(message.additional_fields["_ok"], true);
+        assert_eq!(add.stats().unwrap()["errors"], 2);
+
+        let rename = RenameFields::new(BTreeMap::from([("_service".to_string(), "id".to_string())]));
+        process(&rename, &mut message);
+        assert_eq!(message.additional_fields["_service"], "payments");
+        assert_eq!(rename.stats().unwrap()["errors"], 1);
+    }
+
+    #[test]
+    fn rename_keeps_values_that_do_not_fit_the_new_field() {
+        let rename = RenameFields::new(BTreeMap::from([
+            ("_service".to_string(), "facility".to_string()),
+            ("_env".to_string(), "level".to_string()),
+        ]));
+        let mut message = message();
+        process(&rename, &mut message);
+
+        assert_eq!(message.facility.as_deref(), Some("payments"));
+        assert!(!message.additional_fields.contains_key("_service"));
+        assert_eq!((message.level, message.additional_fields["_env"].clone()), (Some(3), json!("staging")));
+        assert_eq!(rename.stats().unwrap()["errors"], 1);
+    }
+
+    #[test]
+    fn remove_and_drop() {
+        let mut message = message();
+        process(&RemoveFields::new(vec!["env".to_string(), "host".to_string()]), &mut message);
+        assert!(message.host.is_none());
+        assert!(!message.additional_fields.contains_key("_env"));
+
+        let drop = DropMessages::new(Query::parse("_service:payments").unwrap());
+        assert_eq!(process(&drop, &mut message), Action::Drop);
+        message.additional_fields.insert("_service".to_string(), json!("search"));
+        assert_eq!(process(&drop, &mut message), Action::Keep);
+    }
+}
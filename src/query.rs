@@ -0,0 +1,838 @@
+use crate::gelf::GelfMessage;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Deepest nesting of parentheses and negations a query may have, which
+/// bounds the recursion when parsing and matching
+pub const MAX_DEPTH: usize = 64;
+
+/// Error produced when a query string cannot be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    /// Zero-based character offset of the problem in the query string
+    pub position: usize,
+    pub message: String,
+}
+
+impl QueryError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Comparison operator for `field:>value` style terms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// One end of a range term; `None` means unbounded (`*`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeBound {
+    pub value: Option<String>,
+    pub inclusive: bool,
+}
+
+/// How a term matches a field value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Matcher {
+    /// Plain word
+    Exact(String),
+    /// Word containing `*` or `?`
+    Wildcard(String),
+    /// Double-quoted phrase
+    Phrase(String),
+    /// `[a TO b]` (inclusive) or `{a TO b}` (exclusive)
+    Range { lower: RangeBound, upper: RangeBound },
+    Compare(Comparison, String),
+    /// `field:*`
+    Exists,
+}
+
+/// A parsed query expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    Term {
+        field: Option<String>,
+        matcher: Matcher,
+    },
+}
+
+impl Query {
+    /// Parse a Lucene-style query such as
+    /// `level:<=3 AND host:web-* AND NOT _env:staging "timeout"`
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        let tokens = Lexer::new(input).tokenize()?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            end: input.chars().count(),
+            depth: 0,
+        };
+
+        if parser.tokens.is_empty() {
+            return Err(QueryError::new(0, "empty query"));
+        }
+
+        let query = parser.parse_or()?;
+        if let Some((token, position)) = parser.tokens.get(parser.position) {
+            return Err(QueryError::new(*position, format!("unexpected {}", token.describe())));
+        }
+        Ok(query)
+    }
+
+    pub fn matches(&self, message: &GelfMessage, received_at: f64) -> bool {
+        match self {
+            Query::And(queries) => queries.iter().all(|q| q.matches(message, received_at)),
+            Query::Or(queries) => queries.iter().any(|q| q.matches(message, received_at)),
+            Query::Not(query) => !query.matches(message, received_at),
+            Query::Term { field: None, matcher } => [&message.short_message, &message.full_message]
+                .into_iter()
+                .flatten()
+                .any(|text| matches_text(matcher, text)),
+            Query::Term {
+                field: Some(field),
+                matcher,
+            } => match field_value(message, received_at, field) {
+                Some(FieldValue::Text(text)) if is_text_field(field) => matches_text(matcher, &text),
+                Some(value) => matches_value(matcher, &value),
+                None => false,
+            },
+        }
+    }
+}
+
+/// Value of a message field used for matching
+pub(crate) enum FieldValue<'a> {
+    Text(Cow<'a, str>),
+    Number(f64),
+}
+
+impl FieldValue<'_> {
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        match self {
+            FieldValue::Number(n) => Some(*n),
+            FieldValue::Text(text) => text.parse::<f64>().ok(),
+        }
+    }
+
+    pub(crate) fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            FieldValue::Text(text) => Cow::Borrowed(text.as_ref()),
+            FieldValue::Number(n) => Cow::Owned(n.to_string()),
+        }
+    }
+}
+
+fn is_text_field(field: &str) -> bool {
+    matches!(field, "short_message" | "full_message" | "message")
+}
+
+/// Resolve a GELF or additional field by name
+pub(crate) fn field_value<'a>(message: &'a GelfMessage, received_at: f64, field: &str) -> Option<FieldValue<'a>> {
+    let text = |value: &'a Option<String>| value.as_deref().map(|s| FieldValue::Text(Cow::Borrowed(s)));
+
+    match field {
+        "version" => text(&message.version),
+        "host" | "source" => text(&message.host),
+        "short_message" | "message" => text(&message.short_message),
+        "full_message" => text(&message.full_message),
+        "facility" => text(&message.facility),
+        "file" => text(&message.file),
+        "timestamp" => message.timestamp.map(FieldValue::Number),
+        "level" => message.level.map(|level| FieldValue::Number(level as f64)),
+        "line" => message.line.map(|line| FieldValue::Number(line as f64)),
+        "received_at" => Some(FieldValue::Number(received_at)),
+        _ => {
+            // Accept additional fields with or without their leading underscore
+            let value = message.additional_fields.get(field).or_else(|| {
+                message
+                    .additional_fields
+                    .get(&format!("_{}", field))
+            })?;
+            match value {
+                serde_json::Value::String(s) => Some(FieldValue::Text(Cow::Borrowed(s))),
+                serde_json::Value::Number(n) => n.as_f64().map(FieldValue::Number),
+                serde_json::Value::Null => None,
+                other => Some(FieldValue::Text(Cow::Owned(other.to_string()))),
+            }
+        }
+    }
+}
+
+/// Match free text: words and phrases are case-insensitive substrings,
+/// wildcards must match a whole word
+fn matches_text(matcher: &Matcher, text: &str) -> bool {
+    match matcher {
+        Matcher::Exact(word) | Matcher::Phrase(word) => text.to_lowercase().contains(&word.to_lowercase()),
+        Matcher::Wildcard(pattern) => crate::index::tokenize(text)
+            .any(|token| wildcard_match(&pattern.to_lowercase(), &token)),
+        Matcher::Exists => true,
+        other => matches_value(other, &FieldValue::Text(Cow::Borrowed(text))),
+    }
+}
+
+fn matches_value(matcher: &Matcher, value: &FieldValue) -> bool {
+    match matcher {
+        Matcher::Exists => true,
+        Matcher::Exact(expected) | Matcher::Phrase(expected) => match (value, expected.parse::<f64>()) {
+            (FieldValue::Number(n), Ok(expected)) => *n == expected,
+            _ => value.as_text().eq_ignore_ascii_case(expected),
+        },
+        Matcher::Wildcard(pattern) => {
+            wildcard_match(&pattern.to_lowercase(), &value.as_text().to_lowercase())
+        }
+        Matcher::Compare(op, bound) => match compare(value, bound) {
+            Some(ordering) => match op {
+                Comparison::Less => ordering.is_lt(),
+                Comparison::LessOrEqual => ordering.is_le(),
+                Comparison::Greater => ordering.is_gt(),
+                Comparison::GreaterOrEqual => ordering.is_ge(),
+            },
+            None => false,
+        },
+        Matcher::Range { lower, upper } => {
+            let above = match &lower.value {
+                None => true,
+                Some(bound) => compare(value, bound)
+                    .is_some_and(|o| if lower.inclusive { o.is_ge() } else { o.is_gt() }),
+            };
+            let below = match &upper.value {
+                None => true,
+                Some(bound) => compare(value, bound)
+                    .is_some_and(|o| if upper.inclusive { o.is_le() } else { o.is_lt() }),
+            };
+            above && below
+        }
+    }
+}
+
+/// Compare a field value to a bound, numerically when both sides are numbers
+fn compare(value: &FieldValue, bound: &str) -> Option<std::cmp::Ordering> {
+    match (value.as_number(), bound.parse::<f64>()) {
+        (Some(n), Ok(bound)) => n.partial_cmp(&bound),
+        _ => Some(value.as_text().as_ref().cmp(bound)),
+    }
+}
+
+/// Glob-style match supporting `*` (any run) and `?` (any single character)
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Term {
+        field: Option<String>,
+        matcher: Matcher,
+    },
+}
+
+impl Token {
+    fn describe(&self) -> &'static str {
+        match self {
+            Token::LParen => "'('",
+            Token::RParen => "')'",
+            Token::And => "AND",
+            Token::Or => "OR",
+            Token::Not => "NOT",
+            Token::Term { .. } => "term",
+        }
+    }
+}
+
+/// An open `field:( ... )` group, whose plain terms apply to `field`
+struct FieldGroup {
+    field: String,
+    /// Parentheses opened inside the group and not yet closed
+    depth: usize,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    position: usize,
+    group: Option<FieldGroup>,
+}
+
+impl Lexer {
+    fn new(input: &str) -> Self {
+        Self {
+            chars: input.chars().collect(),
+            position: 0,
+            group: None,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Token, usize)>, QueryError> {
+        let mut tokens = Vec::new();
+
+        while let Some(c) = self.peek() {
+            let start = self.position;
+            match c {
+                c if c.is_whitespace() => self.position += 1,
+                '(' => {
+                    self.position += 1;
+                    if let Some(group) = &mut self.group {
+                        group.depth += 1;
+                    }
+                    tokens.push((Token::LParen, start));
+                }
+                ')' => {
+                    self.position += 1;
+                    match &mut self.group {
+                        Some(group) if group.depth > 0 => group.depth -= 1,
+                        Some(_) => self.group = None,
+                        None => {}
+                    }
+                    tokens.push((Token::RParen, start));
+                }
+                '-' | '!' => {
+                    self.position += 1;
+                    tokens.push((Token::Not, start));
+                }
+                _ => {
+                    let token = self.read_term()?;
+                    tokens.push((token, start));
+                }
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    /// Read a bare word, honouring backslash escapes
+    fn read_word(&mut self, stop_at_colon: bool) -> Result<(String, bool), QueryError> {
+        let mut word = String::new();
+        let mut has_wildcard = false;
+
+        while let Some(c) = self.peek() {
+            match c {
+                c if c.is_whitespace() => break,
+                '(' | ')' | '"' => break,
+                ':' if stop_at_colon => break,
+                '\\' => {
+                    self.position += 1;
+                    match self.peek() {
+                        Some(escaped) => {
+                            word.push(escaped);
+                            self.position += 1;
+                        }
+                        None => return Err(QueryError::new(self.position, "dangling escape character")),
+                    }
+                }
+                '*' | '?' => {
+                    has_wildcard = true;
+                    word.push(c);
+                    self.position += 1;
+                }
+                _ => {
+                    word.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+
+        Ok((word, has_wildcard))
+    }
+
+    fn read_phrase(&mut self) -> Result<String, QueryError> {
+        let start = self.position;
+        self.position += 1;
+        let mut phrase = String::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(QueryError::new(start, "unterminated phrase")),
+                Some('"') => {
+                    self.position += 1;
+                    return Ok(phrase);
+                }
+                Some('\\') => {
+                    self.position += 1;
+                    if let Some(escaped) = self.peek() {
+                        phrase.push(escaped);
+                        self.position += 1;
+                    }
+                }
+                Some(c) => {
+                    phrase.push(c);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn read_term(&mut self) -> Result<Token, QueryError> {
+        if self.group.is_some() {
+            return self.read_group_term();
+        }
+        if self.peek() == Some('"') {
+            let phrase = self.read_phrase()?;
+            return Ok(Token::Term {
+                field: None,
+                matcher: Matcher::Phrase(phrase),
+            });
+        }
+
+        let start = self.position;
+        let (word, has_wildcard) = self.read_word(true)?;
+
+        if self.peek() != Some(':') {
+            if word.is_empty() {
+                return Err(QueryError::new(start, "expected a term"));
+            }
+            return Ok(match word.as_str() {
+                "AND" | "&&" => Token::And,
+                "OR" | "||" => Token::Or,
+                "NOT" => Token::Not,
+                _ => Token::Term {
+                    field: None,
+                    matcher: word_matcher(word, has_wildcard),
+                },
+            });
+        }
+
+        if word.is_empty() {
+            return Err(QueryError::new(start, "missing field name before ':'"));
+        }
+        self.position += 1;
+        if self.peek() == Some('(') {
+            // `field:(a OR b)` is read as `(field:a OR field:b)`
+            self.position += 1;
+            self.group = Some(FieldGroup { field: word, depth: 0 });
+            return Ok(Token::LParen);
+        }
+        let matcher = self.read_value()?;
+
+        Ok(Token::Term {
+            field: Some(word),
+            matcher,
+        })
+    }
+
+    /// Read an operator or a value of the open field group's field
+    fn read_group_term(&mut self) -> Result<Token, QueryError> {
+        let start = self.position;
+        let (word, _) = self.read_word(true)?;
+        if self.peek() == Some(':') {
+            return Err(QueryError::new(start, "field name inside a field group"));
+        }
+        match word.as_str() {
+            "AND" | "&&" => return Ok(Token::And),
+            "OR" | "||" => return Ok(Token::Or),
+            "NOT" => return Ok(Token::Not),
+            _ => {}
+        }
+
+        self.position = start;
+        let matcher = self.read_value()?;
+        let field = self.group.as_ref().map(|group| group.field.clone());
+        Ok(Token::Term { field, matcher })
+    }
+
+    fn read_value(&mut self) -> Result<Matcher, QueryError> {
+        let start = self.position;
+        match self.peek() {
+            None => Err(QueryError::new(start, "missing value after ':'")),
+            Some(c) if c.is_whitespace() => Err(QueryError::new(start, "missing value after ':'")),
+            Some('"') => Ok(Matcher::Phrase(self.read_phrase()?)),
+            Some('[') | Some('{') => self.read_range(),
+            Some('<') | Some('>') => {
+                let op = self.read_comparison();
+                let value_start = self.position;
+                let (value, _) = self.read_word(false)?;
+                if value.is_empty() {
+                    return Err(QueryError::new(value_start, "missing value after comparison operator"));
+                }
+                Ok(Matcher::Compare(op, value))
+            }
+            Some(_) => {
+                let (word, has_wildcard) = self.read_word(false)?;
+                if word.is_empty() {
+                    return Err(QueryError::new(start, "missing value after ':'"));
+                }
+                if word == "*" {
+                    return Ok(Matcher::Exists);
+                }
+                Ok(word_matcher(word, has_wildcard))
+            }
+        }
+    }
+
+    fn read_comparison(&mut self) -> Comparison {
+        let first = self.peek();
+        self.position += 1;
+        let or_equal = self.peek() == Some('=');
+        if or_equal {
+            self.position += 1;
+        }
+
+        match (first, or_equal) {
+            (Some('<'), false) => Comparison::Less,
+            (Some('<'), true) => Comparison::LessOrEqual,
+            (_, false) => Comparison::Greater,
+            (_, true) => Comparison::GreaterOrEqual,
+        }
+    }
+
+    fn read_range(&mut self) -> Result<Matcher, QueryError> {
+        let start = self.position;
+        let lower_inclusive = self.peek() == Some('[');
+        self.position += 1;
+
+        let mut body = String::new();
+        let upper_inclusive = loop {
+            match self.peek() {
+                None => return Err(QueryError::new(start, "unterminated range")),
+                Some(']') => break true,
+                Some('}') => break false,
+                Some(c) => {
+                    body.push(c);
+                    self.position += 1;
+                }
+            }
+        };
+        self.position += 1;
+
+        let parts: Vec<&str> = body.split_whitespace().collect();
+        if parts.len() != 3 || parts[1] != "TO" {
+            return Err(QueryError::new(start, "range must have the form [lower TO upper]"));
+        }
+
+        let bound = |value: &str, inclusive: bool| RangeBound {
+            value: (value != "*").then(|| value.to_string()),
+            inclusive,
+        };
+
+        Ok(Matcher::Range {
+            lower: bound(parts[0], lower_inclusive),
+            upper: bound(parts[2], upper_inclusive),
+        })
+    }
+}
+
+fn word_matcher(word: String, has_wildcard: bool) -> Matcher {
+    if has_wildcard {
+        Matcher::Wildcard(word)
+    } else {
+        Matcher::Exact(word)
+    }
+}
+
+/// Recursive descent parser; adjacent terms without an operator are ANDed
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn current_position(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map(|(_, position)| *position)
+            .unwrap_or(self.end)
+    }
+
+    /// Enter a nested parenthesis or negation, failing past `MAX_DEPTH`
+    fn descend(&mut self, position: usize) -> Result<(), QueryError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(QueryError::new(
+                position,
+                format!("query is nested more than {} levels deep", MAX_DEPTH),
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Query, QueryError> {
+        let mut alternatives = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            alternatives.push(self.parse_and()?);
+        }
+
+        Ok(if alternatives.len() == 1 {
+            alternatives.pop().unwrap()
+        } else {
+            Query::Or(alternatives)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<Query, QueryError> {
+        let mut clauses = vec![self.parse_not()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.position += 1;
+                    clauses.push(self.parse_not()?);
+                }
+                Some(Token::Or) | Some(Token::RParen) | None => break,
+                Some(_) => clauses.push(self.parse_not()?),
+            }
+        }
+
+        Ok(if clauses.len() == 1 {
+            clauses.pop().unwrap()
+        } else {
+            Query::And(clauses)
+        })
+    }
+
+    fn parse_not(&mut self) -> Result<Query, QueryError> {
+        if self.peek() == Some(&Token::Not) {
+            self.descend(self.current_position())?;
+            self.position += 1;
+            let query = Query::Not(Box::new(self.parse_not()?));
+            self.depth -= 1;
+            return Ok(query);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, QueryError> {
+        let position = self.current_position();
+        let Some((token, _)) = self.tokens.get(self.position).cloned() else {
+            return Err(QueryError::new(position, "unexpected end of query"));
+        };
+        self.position += 1;
+
+        match token {
+            Token::LParen => {
+                self.descend(position)?;
+                let query = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(QueryError::new(self.current_position(), "expected ')'"));
+                }
+                self.position += 1;
+                self.depth -= 1;
+                Ok(query)
+            }
+            Token::Term { field, matcher } => Ok(Query::Term { field, matcher }),
+            other => Err(QueryError::new(position, format!("unexpected {}", other.describe()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn message(value: serde_json::Value) -> GelfMessage {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn term(field: Option<&str>, matcher: Matcher) -> Query {
+        Query::Term {
+            field: field.map(str::to_string),
+            matcher,
+        }
+    }
+
+    fn exact(field: Option<&str>, word: &str) -> Query {
+        term(field, Matcher::Exact(word.to_string()))
+    }
+
+    fn matches(query: &str, value: serde_json::Value) -> bool {
+        Query::parse(query).unwrap().matches(&message(value), 0.0)
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Query::parse("a OR b AND c").unwrap(),
+            Query::Or(vec![
+                exact(None, "a"),
+                Query::And(vec![exact(None, "b"), exact(None, "c")]),
+            ])
+        );
+        assert_eq!(
+            Query::parse("(a OR b) c").unwrap(),
+            Query::And(vec![
+                Query::Or(vec![exact(None, "a"), exact(None, "b")]),
+                exact(None, "c"),
+            ])
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_clause_only() {
+        assert_eq!(
+            Query::parse("NOT a b").unwrap(),
+            Query::And(vec![Query::Not(Box::new(exact(None, "a"))), exact(None, "b")])
+        );
+        assert_eq!(Query::parse("-a").unwrap(), Query::parse("!a").unwrap());
+        assert!(matches("timeout -host:db", json!({"short_message": "timeout", "host": "web"})));
+        assert!(!matches("timeout -host:db", json!({"short_message": "timeout", "host": "db"})));
+        assert!(matches("NOT NOT level:3", json!({"level": 3})));
+    }
+
+    #[test]
+    fn phrases_and_escapes_are_read_literally() {
+        assert_eq!(
+            Query::parse(r#"_path:"/a b/c" "x \" y""#).unwrap(),
+            Query::And(vec![
+                term(Some("_path"), Matcher::Phrase("/a b/c".to_string())),
+                term(None, Matcher::Phrase("x \" y".to_string())),
+            ])
+        );
+        assert_eq!(Query::parse(r"url:http\://x").unwrap(), exact(Some("url"), "http://x"));
+        assert_eq!(Query::parse(r"a\*b").unwrap(), exact(None, "a*b"));
+        assert!(matches(r#""Connection Refused""#, json!({"short_message": "error: connection refused"})));
+    }
+
+    #[test]
+    fn ranges_and_comparisons_compare_numbers() {
+        let query = Query::parse("_status:[500 TO 599]").unwrap();
+        assert!(query.matches(&message(json!({"_status": 500})), 0.0));
+        assert!(query.matches(&message(json!({"_status": "599"})), 0.0));
+        assert!(!query.matches(&message(json!({"_status": 600})), 0.0));
+
+        let exclusive = Query::parse("_ms:{100 TO *]").unwrap();
+        assert!(!exclusive.matches(&message(json!({"_ms": 100})), 0.0));
+        assert!(exclusive.matches(&message(json!({"_ms": 100000})), 0.0));
+
+        assert!(matches("level:<=3", json!({"level": 3})));
+        assert!(!matches("level:<3", json!({"level": 3})));
+        // Numeric, not lexical: "9" > "10" as text
+        assert!(matches("_n:>9", json!({"_n": 10})));
+        assert!(!matches("level:<=3", json!({"short_message": "no level"})));
+    }
+
+    #[test]
+    fn wildcards_match_field_values_and_whole_words() {
+        assert!(matches("host:web-*", json!({"host": "WEB-01"})));
+        assert!(matches("host:web-0?", json!({"host": "web-07"})));
+        assert!(!matches("host:web-0?", json!({"host": "web-100"})));
+        assert!(matches("time*", json!({"short_message": "request timed out"})));
+        assert!(!matches("imeo*", json!({"short_message": "timeout"})));
+        assert!(matches("_trace_id:*", json!({"_trace_id": "abc"})));
+        assert!(!matches("trace_id:*", json!({"short_message": "x"})));
+    }
+
+    #[test]
+    fn field_groups_apply_the_field_to_each_term() {
+        assert_eq!(
+            Query::parse("host:(web-1 OR web-2) timeout").unwrap(),
+            Query::And(vec![
+                Query::Or(vec![exact(Some("host"), "web-1"), exact(Some("host"), "web-2")]),
+                exact(None, "timeout"),
+            ])
+        );
+        assert_eq!(
+            Query::parse(r#"_status:(>=500 OR "not found" OR (4* AND NOT 404))"#).unwrap(),
+            Query::Or(vec![
+                term(Some("_status"), Matcher::Compare(Comparison::GreaterOrEqual, "500".to_string())),
+                term(Some("_status"), Matcher::Phrase("not found".to_string())),
+                Query::And(vec![
+                    term(Some("_status"), Matcher::Wildcard("4*".to_string())),
+                    Query::Not(Box::new(exact(Some("_status"), "404"))),
+                ]),
+            ])
+        );
+
+        assert!(matches("host:(db OR web-*)", json!({"host": "web-3"})));
+        assert!(!matches("host:(db OR web-*)", json!({"host": "cache", "short_message": "db"})));
+        assert!(matches("level:([0 TO 3] OR 7)", json!({"level": 7})));
+        assert!(Query::parse("host:(a OR b").is_err());
+        assert_eq!(
+            Query::parse("host:(a OR level:3)").unwrap_err().message,
+            "field name inside a field group"
+        );
+    }
+
+    #[test]
+    fn wildcard_match_backtracks() {
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("a*b", "aXc"));
+        assert!(!wildcard_match("?", ""));
+    }
+
+    #[test]
+    fn malformed_queries_report_their_position() {
+        let error = |query: &str| Query::parse(query).unwrap_err();
+
+        assert_eq!(error("").message, "empty query");
+        assert_eq!(error("(a OR b").position, 7);
+        assert_eq!(error("a)").position, 1);
+        assert_eq!(error(r#"x "open"#).message, "unterminated phrase");
+        assert_eq!(error("_s:[1 TO").message, "unterminated range");
+        assert_eq!(error("_s:[1 2]").message, "range must have the form [lower TO upper]");
+        assert_eq!(error("host: web").message, "missing value after ':'");
+        assert_eq!(error(":web").message, "missing field name before ':'");
+        assert_eq!(error("level:>").message, "missing value after comparison operator");
+        assert_eq!(error(r"a\").message, "dangling escape character");
+        assert_eq!(error("a AND").message, "unexpected end of query");
+        assert_eq!(error("OR a").message, "unexpected OR");
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Query::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Query::parse(&format!("{}a", "!".repeat(MAX_DEPTH))).is_ok());
+
+        let error = Query::parse(&nested(MAX_DEPTH + 1)).unwrap_err();
+        assert_eq!(error.position, MAX_DEPTH);
+        assert!(error.message.contains("nested"));
+        // Deep enough to overflow the stack without the limit
+        assert!(Query::parse(&nested(100_000)).is_err());
+        assert!(Query::parse(&"!".repeat(100_000)).is_err());
+        assert!(Query::parse(&"NOT (".repeat(50_000)).is_err());
+    }
+}
@@ -1,27 +1,158 @@
-use crate::gelf::{GelfMessage, MessageResponse, StoredMessage};
+use crate::aggregate::{AggregationRequest, AggregationResult, Aggregator};
+use crate::dedup::{self, DedupConfig, Deduplicator};
+use crate::fields::{FieldCatalogue, FieldInfo};
+use crate::filter::MessageFilter;
+use crate::hosts::{HostInfo, HostTracker, HostsConfig};
+use crate::gelf::{GelfMessage, MessageResponse, ReceiveMetadata, StoredMessage};
+use crate::index::{InvertedIndex, SearchQuery};
+use crate::patterns::{PatternInfo, PatternStripes};
+use crate::query::field_value;
 use std::collections::VecDeque;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::{broadcast, RwLock};
 use tracing::debug;
 
 /// Trait for message storage
 pub trait MessageStore: Clone + Send + Sync + 'static {
-    fn add_message(&self, gelf_message: GelfMessage, raw_message: String) -> impl std::future::Future<Output = ()> + Send;
-    fn get_messages(&self, limit: Option<usize>) -> impl std::future::Future<Output = Vec<MessageResponse>> + Send;
+    fn add_message(&self, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) -> impl std::future::Future<Output = ()> + Send;
+    fn get_messages(&self, filter: MessageFilter, limit: Option<usize>) -> impl std::future::Future<Output = Vec<MessageResponse>> + Send;
     fn get_stats(&self) -> impl std::future::Future<Output = serde_json::Value> + Send;
-    fn subscribe(&self) -> broadcast::Receiver<MessageResponse>;
+    fn subscribe(&self) -> broadcast::Receiver<StoreEvent>;
+    /// Remove all messages matching the filter, returning how many were removed
+    fn delete_messages(&self, filter: MessageFilter) -> impl std::future::Future<Output = usize> + Send;
+    /// Change the maximum number of stored messages, returning how many were evicted
+    fn set_capacity(&self, max_size: usize) -> impl std::future::Future<Output = usize> + Send;
+    fn capacity(&self) -> usize;
+    /// Full-text search over stored messages, newest first
+    fn search(&self, query: SearchQuery, limit: Option<usize>) -> impl std::future::Future<Output = Vec<MessageResponse>> + Send;
+    /// Compute aggregations over the messages matching the filter
+    fn aggregate(&self, filter: MessageFilter, request: AggregationRequest) -> impl std::future::Future<Output = AggregationResult> + Send;
+    /// Look up a single message by id
+    fn get_message(&self, id: u64) -> impl std::future::Future<Output = Option<StoredMessage>> + Send;
+    /// Messages around `id` that share the values of the `same` fields with it,
+    /// oldest first; `None` if the message is no longer buffered
+    fn get_context(&self, id: u64, before: usize, after: usize, same: Vec<String>) -> impl std::future::Future<Output = Option<MessageContext>> + Send;
+    /// Messages matching the filter with an id greater than `after_id`, oldest first
+    fn get_page(&self, filter: MessageFilter, after_id: u64, limit: usize) -> impl std::future::Future<Output = Vec<StoredMessage>> + Send;
+    /// List additional fields present in the buffer, optionally by name prefix
+    fn get_fields(&self, prefix: Option<String>) -> impl std::future::Future<Output = Vec<FieldInfo>> + Send;
+    /// Message patterns with the most buffered messages first
+    fn get_patterns(&self, limit: Option<usize>) -> impl std::future::Future<Output = Vec<PatternInfo>> + Send;
+    /// Hosts that sent messages since startup, with their activity and clock skew
+    fn get_hosts(&self) -> impl std::future::Future<Output = Vec<HostInfo>> + Send;
+}
+
+/// Implement every `MessageStore` method except `add_message` by delegating to a field
+///
+/// For stores that decide where a message goes and read from one of their stores.
+macro_rules! delegate_message_store {
+    ($field:ident) => {
+        fn get_messages(&self, filter: $crate::filter::MessageFilter, limit: Option<usize>) -> impl std::future::Future<Output = Vec<$crate::gelf::MessageResponse>> + Send {
+            self.$field.get_messages(filter, limit)
+        }
+
+        fn get_stats(&self) -> impl std::future::Future<Output = serde_json::Value> + Send {
+            self.$field.get_stats()
+        }
+
+        fn subscribe(&self) -> tokio::sync::broadcast::Receiver<$crate::storage::StoreEvent> {
+            self.$field.subscribe()
+        }
+
+        fn delete_messages(&self, filter: $crate::filter::MessageFilter) -> impl std::future::Future<Output = usize> + Send {
+            self.$field.delete_messages(filter)
+        }
+
+        fn set_capacity(&self, max_size: usize) -> impl std::future::Future<Output = usize> + Send {
+            self.$field.set_capacity(max_size)
+        }
+
+        fn capacity(&self) -> usize {
+            self.$field.capacity()
+        }
+
+        fn search(&self, query: $crate::index::SearchQuery, limit: Option<usize>) -> impl std::future::Future<Output = Vec<$crate::gelf::MessageResponse>> + Send {
+            self.$field.search(query, limit)
+        }
+
+        fn aggregate(&self, filter: $crate::filter::MessageFilter, request: $crate::aggregate::AggregationRequest) -> impl std::future::Future<Output = $crate::aggregate::AggregationResult> + Send {
+            self.$field.aggregate(filter, request)
+        }
+
+        fn get_message(&self, id: u64) -> impl std::future::Future<Output = Option<$crate::gelf::StoredMessage>> + Send {
+            self.$field.get_message(id)
+        }
+
+        fn get_context(&self, id: u64, before: usize, after: usize, same: Vec<String>) -> impl std::future::Future<Output = Option<$crate::storage::MessageContext>> + Send {
+            self.$field.get_context(id, before, after, same)
+        }
+
+        fn get_page(&self, filter: $crate::filter::MessageFilter, after_id: u64, limit: usize) -> impl std::future::Future<Output = Vec<$crate::gelf::StoredMessage>> + Send {
+            self.$field.get_page(filter, after_id, limit)
+        }
+
+        fn get_fields(&self, prefix: Option<String>) -> impl std::future::Future<Output = Vec<$crate::fields::FieldInfo>> + Send {
+            self.$field.get_fields(prefix)
+        }
+
+        fn get_patterns(&self, limit: Option<usize>) -> impl std::future::Future<Output = Vec<$crate::patterns::PatternInfo>> + Send {
+            self.$field.get_patterns(limit)
+        }
+
+        fn get_hosts(&self) -> impl std::future::Future<Output = Vec<$crate::hosts::HostInfo>> + Send {
+            self.$field.get_hosts()
+        }
+    };
+}
+
+pub(crate) use delegate_message_store;
+
+/// Neighbours of a message from the same source
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MessageContext {
+    pub message: MessageResponse,
+    pub before: Vec<MessageResponse>,
+    pub after: Vec<MessageResponse>,
+}
+
+/// Source of additional statistics reported alongside the store's own
+pub trait StatsProvider: Send + Sync {
+    fn stats(&self) -> serde_json::Value;
+}
+
+/// Named statistics providers registered on a store
+type StatsProviders = Vec<(String, Arc<dyn StatsProvider>)>;
+
+/// Change to the stored messages announced to subscribers
+#[derive(Debug, Clone)]
+pub enum StoreEvent {
+    /// A new message was stored
+    Added(MessageResponse),
+    /// A stored message changed in place, e.g. a duplicate was collapsed into it
+    Updated(MessageResponse),
+}
+
+impl StoreEvent {
+    pub fn message(&self) -> &MessageResponse {
+        match self {
+            StoreEvent::Added(message) | StoreEvent::Updated(message) => message,
+        }
+    }
 }
 
 /// Trait for broadcasting messages
 pub trait MessageBroadcaster: Send + Sync {
-    fn broadcast(&self, message: MessageResponse) -> Result<(), broadcast::error::SendError<MessageResponse>>;
-    fn subscribe(&self) -> broadcast::Receiver<MessageResponse>;
+    #[allow(clippy::result_large_err)]
+    fn broadcast(&self, event: StoreEvent) -> Result<(), broadcast::error::SendError<StoreEvent>>;
+    fn subscribe(&self) -> broadcast::Receiver<StoreEvent>;
 }
 
 /// Default broadcaster implementation
 #[derive(Clone)]
 pub struct DefaultBroadcaster {
-    tx: broadcast::Sender<MessageResponse>,
+    tx: broadcast::Sender<StoreEvent>,
 }
 
 impl DefaultBroadcaster {
@@ -32,21 +163,263 @@ impl DefaultBroadcaster {
 }
 
 impl MessageBroadcaster for DefaultBroadcaster {
-    fn broadcast(&self, message: MessageResponse) -> Result<(), broadcast::error::SendError<MessageResponse>> {
-        self.tx.send(message).map(|_| ())
+    fn broadcast(&self, event: StoreEvent) -> Result<(), broadcast::error::SendError<StoreEvent>> {
+        self.tx.send(event).map(|_| ())
     }
 
-    fn subscribe(&self) -> broadcast::Receiver<MessageResponse> {
+    fn subscribe(&self) -> broadcast::Receiver<StoreEvent> {
         self.tx.subscribe()
     }
 }
 
+/// Settings every shard's state is built from
+#[derive(Clone, Default)]
+struct StoreSettings {
+    dedup: Option<DedupConfig>,
+    hosts: HostsConfig,
+}
+
+/// Parts of the store shared by all shards
+///
+/// The locks in here are never held while waiting for a shard's lock.
+struct SharedState {
+    /// Next message id; taken under a shard's lock so ids increase within every shard
+    next_id: AtomicU64,
+    /// Patterns span the shards so that pattern ids are unique in the store
+    patterns: PatternStripes,
+    /// A host's messages go to several shards, so hosts are tracked once for the store
+    hosts: Mutex<HostTracker>,
+    /// Shard for the next message when duplicates need not be kept together
+    next_shard: AtomicUsize,
+    /// Messages in all shards
+    len: AtomicUsize,
+    /// Held, under a shard's lock, while messages are removed, so that every decision
+    /// to evict sees the removals made before it and the buffer never drops below its limit
+    evicting: Mutex<()>,
+}
+
+impl SharedState {
+    fn new(settings: &StoreSettings, shard_count: usize) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            patterns: PatternStripes::new(shard_count),
+            hosts: Mutex::new(HostTracker::new(&settings.hosts)),
+            next_shard: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            evicting: Mutex::new(()),
+        }
+    }
+}
+
+/// Buffered messages of one shard together with the structures derived from them
+///
+/// Everything in here is guarded by the shard's lock so that derived
+/// structures never disagree with the shard's contents.
+struct StoreState {
+    messages: VecDeque<StoredMessage>,
+    index: InvertedIndex,
+    fields: FieldCatalogue,
+    dedup: Option<Deduplicator>,
+}
+
+impl StoreState {
+    fn new(settings: &StoreSettings) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            index: InvertedIndex::new(),
+            fields: FieldCatalogue::new(),
+            dedup: settings.dedup.as_ref().map(Deduplicator::new),
+        }
+    }
+
+    /// Store a message that went through host tracking, or collapse it into a recent identical entry
+    fn add(&mut self, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata, arrival: Arrival, shared: &SharedState) -> StoreEvent {
+        let received_at = arrival.received_at;
+        let target = self
+            .dedup
+            .as_mut()
+            .and_then(|dedup| dedup.find(&gelf_message, received_at));
+        if let Some(repeat) = target
+            && let Some(position) = self.position(repeat.id)
+        {
+            let stored = &mut self.messages[position];
+            // The first collapse may replace text the sender put in the repeat
+            // fields; after that only numbers change and the index stays valid
+            let first = repeat.copies == 2;
+            if first {
+                self.index.remove(stored.id, &stored.gelf_message);
+            }
+            self.fields.remove(&stored.gelf_message);
+            dedup::collapse(&mut stored.gelf_message, repeat.copies, stored.received_at, received_at);
+            self.fields.insert(&stored.gelf_message, received_at);
+            if first {
+                self.index.insert(stored.id, &stored.gelf_message);
+            }
+            return StoreEvent::Updated(stored.to_response());
+        }
+
+        let stored = self.push(gelf_message, raw_message, metadata, arrival, shared);
+        let response = stored.to_response();
+        if let Some(dedup) = self.dedup.as_mut() {
+            dedup.record(response.id, &response.gelf_message, response.received_at);
+        }
+        StoreEvent::Added(response)
+    }
+
+    fn push(&mut self, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata, arrival: Arrival, shared: &SharedState) -> &StoredMessage {
+        let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut stored_message = StoredMessage::new(id, gelf_message, raw_message, metadata);
+        stored_message.received_at = arrival.received_at;
+        stored_message.corrected_timestamp = arrival.corrected_timestamp;
+        stored_message.pattern_id = shared
+            .patterns
+            .insert(&stored_message.gelf_message, stored_message.received_at);
+        self.index.insert(id, &stored_message.gelf_message);
+        self.fields.insert(&stored_message.gelf_message, stored_message.received_at);
+        self.messages.push_back(stored_message);
+        shared.len.fetch_add(1, Ordering::SeqCst);
+        self.messages.back().unwrap()
+    }
+
+    /// Evict the oldest message, returning whether there was one
+    fn pop_oldest(&mut self, shared: &SharedState) -> bool {
+        match self.messages.pop_front() {
+            Some(stored) => {
+                self.forget(&stored, shared);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evict messages received before `cutoff`, returning how many were evicted
+    fn expire_before(&mut self, cutoff: f64, shared: &SharedState) -> usize {
+        let mut evicted = 0;
+        while self.messages.front().is_some_and(|stored| stored.received_at < cutoff) {
+            self.pop_oldest(shared);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Remove the messages matching the filter, returning how many were removed
+    fn delete(&mut self, filter: &MessageFilter, shared: &SharedState) -> usize {
+        let (removed, kept): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition(|stored| filter.matches(stored));
+        self.messages = kept;
+        if self.messages.is_empty() {
+            self.index.clear();
+            self.fields.clear();
+            if let Some(dedup) = self.dedup.as_mut() {
+                dedup.clear();
+            }
+            for pattern_id in removed.iter().filter_map(|stored| stored.pattern_id) {
+                shared.patterns.remove(pattern_id);
+            }
+            shared.len.fetch_sub(removed.len(), Ordering::SeqCst);
+        } else {
+            for stored in &removed {
+                self.forget(stored, shared);
+            }
+        }
+        removed.len()
+    }
+
+    /// Remove a message that left the buffer from the derived structures
+    fn forget(&mut self, stored: &StoredMessage, shared: &SharedState) {
+        self.index.remove(stored.id, &stored.gelf_message);
+        self.fields.remove(&stored.gelf_message);
+        if let Some(pattern_id) = stored.pattern_id {
+            shared.patterns.remove(pattern_id);
+        }
+        if let Some(dedup) = self.dedup.as_mut() {
+            dedup.remove(stored.id, &stored.gelf_message);
+        }
+        shared.len.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Position of a message in the buffer; ids increase monotonically through it
+    fn position(&self, id: u64) -> Option<usize> {
+        self.messages.binary_search_by_key(&id, |stored| stored.id).ok()
+    }
+
+    fn get(&self, id: u64) -> Option<&StoredMessage> {
+        self.messages.get(self.position(id)?)
+    }
+}
+
+/// When a message arrived, as recorded by host tracking before it is stored
+#[derive(Clone, Copy)]
+struct Arrival {
+    received_at: f64,
+    corrected_timestamp: f64,
+}
+
+/// One independently locked part of the buffer
+struct Shard {
+    state: RwLock<StoreState>,
+    /// Id of the shard's oldest message, `u64::MAX` while empty; read without the lock
+    oldest: AtomicU64,
+}
+
+impl Shard {
+    fn new(settings: &StoreSettings) -> Self {
+        Self {
+            state: RwLock::new(StoreState::new(settings)),
+            oldest: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Publish the id of the oldest message after the state changed
+    fn note_oldest(&self, state: &StoreState) {
+        let oldest = state.messages.front().map_or(u64::MAX, |stored| stored.id);
+        self.oldest.store(oldest, Ordering::SeqCst);
+    }
+}
+
+/// Id below which a shard's messages cannot make it into the newest `limit` collected so far
+///
+/// Keeps only the newest `limit` of `messages`, so later shards clone no more than they must.
+fn cutoff_id(messages: &mut Vec<MessageResponse>, limit: Option<usize>) -> u64 {
+    match limit {
+        Some(limit) if limit > 0 && messages.len() >= limit => {
+            messages.select_nth_unstable_by_key(limit - 1, |message| std::cmp::Reverse(message.id));
+            messages.truncate(limit);
+            messages.iter().map(|message| message.id).min().unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+/// Merge the per-shard results of a read, newest first
+fn newest_first(mut messages: Vec<MessageResponse>, limit: Option<usize>) -> Vec<MessageResponse> {
+    messages.sort_unstable_by_key(|message| std::cmp::Reverse(message.id));
+    messages.truncate(limit.unwrap_or(messages.len()));
+    messages
+}
+
 /// In-memory message storage implementation
+///
+/// The buffer can be split into shards, each behind its own lock, so that
+/// messages are indexed and stored concurrently and readers hold only one
+/// shard at a time. Messages go to the shards in turn, or by their
+/// deduplication fields when duplicates are collapsed, so a single busy
+/// host is spread as well. Message ids are unique across shards and reads
+/// merge the shards by id; capacity and eviction apply to the store as a
+/// whole. Host tracking and pattern mining are shared by the shards and
+/// locked only briefly, never while waiting for a shard.
 #[derive(Clone)]
 pub struct InMemoryMessageStore {
-    messages: Arc<RwLock<VecDeque<StoredMessage>>>,
-    max_size: usize,
+    shards: Arc<Vec<Shard>>,
+    shared: Arc<SharedState>,
+    settings: StoreSettings,
+    max_size: Arc<AtomicUsize>,
+    /// Messages older than this many seconds are evicted
+    max_age: Option<f64>,
     broadcaster: Arc<dyn MessageBroadcaster + Send + Sync>,
+    stats_providers: Arc<std::sync::RwLock<StatsProviders>>,
 }
 
 impl InMemoryMessageStore {
@@ -58,68 +431,628 @@ impl InMemoryMessageStore {
         max_size: usize,
         broadcaster: Arc<dyn MessageBroadcaster + Send + Sync>,
     ) -> Self {
+        let settings = StoreSettings::default();
         Self {
-            messages: Arc::new(RwLock::new(VecDeque::new())),
-            max_size,
+            shards: Arc::new(vec![Shard::new(&settings)]),
+            shared: Arc::new(SharedState::new(&settings, 1)),
+            settings,
+            max_size: Arc::new(AtomicUsize::new(max_size)),
+            max_age: None,
             broadcaster,
+            stats_providers: Arc::new(std::sync::RwLock::new(Vec::new())),
         }
     }
+
+    /// Rebuild the shards from the current settings, for the `with_*` builders
+    fn rebuild(mut self, shard_count: usize) -> Self {
+        let shard_count = shard_count.max(1);
+        let shards = (0..shard_count).map(|_| Shard::new(&self.settings)).collect();
+        *Arc::get_mut(&mut self.shards).expect("store must be configured before it is cloned") = shards;
+        *Arc::get_mut(&mut self.shared).expect("store must be configured before it is cloned") =
+            SharedState::new(&self.settings, shard_count);
+        self
+    }
+
+    /// Split the buffer into this many shards; call before cloning the store
+    pub fn with_shards(self, shard_count: usize) -> Self {
+        self.rebuild(shard_count)
+    }
+
+    /// Collapse repeated messages as configured; call before cloning the store
+    pub fn with_dedup(mut self, config: &DedupConfig) -> Self {
+        self.settings.dedup = config.enabled.then(|| config.clone());
+        let shard_count = self.shard_count();
+        self.rebuild(shard_count)
+    }
+
+    /// Track per-host clock skew as configured; call before cloning the store
+    pub fn with_hosts(mut self, config: &HostsConfig) -> Self {
+        self.settings.hosts = config.clone();
+        let shard_count = self.shard_count();
+        self.rebuild(shard_count)
+    }
+
+    /// Evict messages older than `max_age_secs`, on arrival of new messages and on `expire`
+    pub fn with_max_age(mut self, max_age_secs: Option<f64>) -> Self {
+        self.max_age = max_age_secs;
+        self
+    }
+
+    pub fn max_age(&self) -> Option<f64> {
+        self.max_age
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Shard to store a message in; duplicates must meet in one shard to be collapsed
+    fn shard_for(&self, message: &GelfMessage) -> &Shard {
+        let count = self.shards.len();
+        let shard = if count == 1 {
+            0
+        } else if self.settings.dedup.is_some() {
+            (dedup::key_hash(message) % count as u64) as usize
+        } else {
+            self.shared.next_shard.fetch_add(1, Ordering::Relaxed) % count
+        };
+        &self.shards[shard]
+    }
+
+    /// Whether a shard holds the store's oldest message; checked under the shard's lock
+    fn holds_oldest(&self, shard: &Shard) -> bool {
+        self.oldest_shard().is_some_and(|oldest| std::ptr::eq(oldest, shard))
+    }
+
+    /// Shard holding the store's oldest message, if any
+    fn oldest_shard(&self) -> Option<&Shard> {
+        self.shards
+            .iter()
+            .map(|shard| (shard, shard.oldest.load(Ordering::SeqCst)))
+            .filter(|(_, oldest)| *oldest != u64::MAX)
+            .min_by_key(|(_, oldest)| *oldest)
+            .map(|(shard, _)| shard)
+    }
+
+    fn over_capacity(&self, max_size: usize) -> bool {
+        self.shared.len.load(Ordering::SeqCst) > max_size
+    }
+
+    /// Evict the store's oldest messages until at most `max_size` remain
+    async fn trim_to(&self, max_size: usize) -> usize {
+        let mut evicted = 0;
+        while self.over_capacity(max_size) {
+            let Some(shard) = self.oldest_shard() else {
+                break;
+            };
+            let mut state = shard.state.write().await;
+            let _evicting = self.shared.evicting.lock().unwrap();
+            // Other writers may have evicted, or stored an older message elsewhere, while
+            // the lock was awaited; only the shard that still holds the oldest message evicts
+            if self.over_capacity(max_size) && self.holds_oldest(shard) && state.pop_oldest(&self.shared) {
+                evicted += 1;
+            }
+            shard.note_oldest(&state);
+        }
+        evicted
+    }
+
+    /// Evict messages older than the maximum age, returning how many were evicted
+    pub async fn expire(&self) -> usize {
+        let Some(max_age) = self.max_age else {
+            return 0;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        let mut evicted = 0;
+        for shard in self.shards.iter() {
+            let mut state = shard.state.write().await;
+            let _evicting = self.shared.evicting.lock().unwrap();
+            evicted += state.expire_before(now - max_age, &self.shared);
+            shard.note_oldest(&state);
+        }
+        evicted
+    }
+
+    /// Number of buffered messages
+    pub fn message_count(&self) -> usize {
+        self.shared.len.load(Ordering::SeqCst)
+    }
+
+    /// Include a component's statistics in `get_stats` under the given key
+    pub fn register_stats(&self, name: impl Into<String>, provider: Arc<dyn StatsProvider>) {
+        self.stats_providers
+            .write()
+            .unwrap()
+            .push((name.into(), provider));
+    }
 }
 
 impl MessageStore for InMemoryMessageStore {
-    fn add_message(&self, gelf_message: GelfMessage, raw_message: String) -> impl std::future::Future<Output = ()> + Send {
-        let stored_message = StoredMessage::new(gelf_message, raw_message);
-        let response = stored_message.to_response();
-        let messages = self.messages.clone();
-        let max_size = self.max_size;
-        let broadcaster = self.broadcaster.clone();
+    fn add_message(&self, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) -> impl std::future::Future<Output = ()> + Send {
+        let store = self.clone();
 
         async move {
-            {
-                let mut messages_guard = messages.write().await;
-                messages_guard.push_back(stored_message);
+            let max_size = store.max_size.load(Ordering::Relaxed);
+            let mut gelf_message = gelf_message;
+            let received_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64();
+            let arrival = Arrival {
+                received_at,
+                corrected_timestamp: store
+                    .shared
+                    .hosts
+                    .lock()
+                    .unwrap()
+                    .observe(&mut gelf_message, &metadata, received_at),
+            };
+
+            let shard = store.shard_for(&gelf_message);
+            let event = {
+                let mut state_guard = shard.state.write().await;
+                let event = state_guard.add(gelf_message, raw_message, metadata, arrival, &store.shared);
+                shard.note_oldest(&state_guard);
 
-                // Clean up if we exceed max size
-                while messages_guard.len() > max_size {
-                    messages_guard.pop_front();
+                // Clean up if we exceed max size or age, here while this shard holds the oldest message
+                let _evicting = store.shared.evicting.lock().unwrap();
+                while store.over_capacity(max_size)
+                    && store.holds_oldest(shard)
+                    && state_guard.pop_oldest(&store.shared)
+                {
+                    shard.note_oldest(&state_guard);
                 }
-            }
+                if let Some(max_age) = store.max_age {
+                    state_guard.expire_before(event.message().received_at - max_age, &store.shared);
+                }
+                shard.note_oldest(&state_guard);
+                event
+            };
+            store.trim_to(max_size).await;
 
-            // Broadcast the new message to subscribers (ignore if no subscribers)
-            let _ = broadcaster.broadcast(response);
+            // Broadcast the change to subscribers (ignore if no subscribers)
+            let _ = store.broadcaster.broadcast(event);
             debug!("Message added to store and broadcasted");
         }
     }
 
-    fn get_messages(&self, limit: Option<usize>) -> impl std::future::Future<Output = Vec<MessageResponse>> + Send {
-        let messages = self.messages.clone();
+    fn get_messages(&self, filter: MessageFilter, limit: Option<usize>) -> impl std::future::Future<Output = Vec<MessageResponse>> + Send {
+        let shards = self.shards.clone();
         async move {
-            let messages_guard = messages.read().await;
-            let limit = limit.unwrap_or(messages_guard.len());
-            
-            messages_guard
-                .iter()
-                .rev()
-                .take(limit)
-                .map(|stored| stored.to_response())
-                .collect()
+            let mut messages = Vec::new();
+            for shard in shards.iter() {
+                let cutoff = cutoff_id(&mut messages, limit);
+                let state_guard = shard.state.read().await;
+
+                messages.extend(
+                    state_guard
+                        .messages
+                        .iter()
+                        .rev()
+                        .take_while(|stored| stored.id > cutoff)
+                        .filter(|stored| filter.matches(*stored))
+                        .take(limit.unwrap_or(usize::MAX))
+                        .map(|stored| stored.to_response()),
+                );
+            }
+            newest_first(messages, limit)
         }
     }
 
     fn get_stats(&self) -> impl std::future::Future<Output = serde_json::Value> + Send {
-        let messages = self.messages.clone();
-        let max_size = self.max_size;
+        let store = self.clone();
+        let max_size = self.max_size.load(Ordering::Relaxed);
         async move {
-            let messages_guard = messages.read().await;
-            serde_json::json!({
-                "total_messages": messages_guard.len(),
+            // One shard is read at a time, so writers are never held up by more than one
+            // read and the figures of different shards may be a few messages apart
+            let mut total = 0;
+            let mut field_lists = Vec::with_capacity(store.shards.len());
+            let (mut terms, mut postings, mut memory_bytes) = (0, 0, 0);
+            let mut dedups = Vec::new();
+            for shard in store.shards.iter() {
+                let state = shard.state.read().await;
+                total += state.messages.len();
+                field_lists.push(state.fields.list(None));
+                terms += state.index.term_count();
+                postings += state.index.posting_count();
+                memory_bytes += state.index.memory_bytes();
+                dedups.extend(state.dedup.as_ref().map(Deduplicator::counts));
+            }
+            let fields = crate::fields::merge_lists(field_lists);
+            let mut stats = serde_json::json!({
+                "total_messages": total,
                 "max_capacity": max_size,
-                "capacity_used_percent": (messages_guard.len() as f64 / max_size as f64) * 100.0
-            })
+                "capacity_used_percent": (total as f64 / max_size as f64) * 100.0,
+                "fields": fields.len(),
+                "patterns": store.shared.patterns.len(),
+                "hosts": store.shared.hosts.lock().unwrap().len(),
+                "shards": store.shards.len(),
+                "index": {
+                    "terms": terms,
+                    "postings": postings,
+                    "memory_bytes": memory_bytes
+                }
+            });
+            if let Some(dedup) = Deduplicator::combined_stats(dedups) {
+                stats["dedup"] = dedup;
+            }
+
+            for (name, provider) in store.stats_providers.read().unwrap().iter() {
+                stats[name] = provider.stats();
+            }
+            stats
         }
     }
 
-    fn subscribe(&self) -> broadcast::Receiver<MessageResponse> {
+    fn subscribe(&self) -> broadcast::Receiver<StoreEvent> {
         self.broadcaster.subscribe()
     }
-}
\ No newline at end of file
+
+    fn delete_messages(&self, filter: MessageFilter) -> impl std::future::Future<Output = usize> + Send {
+        let store = self.clone();
+        async move {
+            let mut deleted = 0;
+            for shard in store.shards.iter() {
+                let mut state_guard = shard.state.write().await;
+                let _evicting = store.shared.evicting.lock().unwrap();
+                deleted += state_guard.delete(&filter, &store.shared);
+                shard.note_oldest(&state_guard);
+            }
+
+            debug!("Deleted {} messages from store", deleted);
+            deleted
+        }
+    }
+
+    fn set_capacity(&self, max_size: usize) -> impl std::future::Future<Output = usize> + Send {
+        let store = self.clone();
+        async move {
+            store.max_size.store(max_size, Ordering::Relaxed);
+
+            let evicted = store.trim_to(max_size).await;
+            debug!("Store capacity changed to {}, evicted {} messages", max_size, evicted);
+            evicted
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.max_size.load(Ordering::Relaxed)
+    }
+
+    fn search(&self, query: SearchQuery, limit: Option<usize>) -> impl std::future::Future<Output = Vec<MessageResponse>> + Send {
+        let shards = self.shards.clone();
+        async move {
+            let mut messages = Vec::new();
+            for shard in shards.iter() {
+                let state_guard = shard.state.read().await;
+                let ids = state_guard
+                    .index
+                    .search(&query, |id| state_guard.get(id).map(|stored| &stored.gelf_message));
+
+                messages.extend(
+                    ids.into_iter()
+                        .take(limit.unwrap_or(usize::MAX))
+                        .filter_map(|id| state_guard.get(id))
+                        .map(|stored| stored.to_response()),
+                );
+            }
+            debug!("Search matched {} messages", messages.len());
+            newest_first(messages, limit)
+        }
+    }
+
+    fn aggregate(&self, filter: MessageFilter, request: AggregationRequest) -> impl std::future::Future<Output = AggregationResult> + Send {
+        let shards = self.shards.clone();
+        async move {
+            let mut aggregator = Aggregator::new(&request);
+
+            for shard in shards.iter() {
+                let state_guard = shard.state.read().await;
+                for stored in state_guard
+                    .messages
+                    .iter()
+                    .filter(|stored| filter.matches(*stored))
+                {
+                    aggregator.add(&stored.gelf_message, stored.received_at);
+                }
+            }
+
+            aggregator.finish()
+        }
+    }
+
+    fn get_fields(&self, prefix: Option<String>) -> impl std::future::Future<Output = Vec<FieldInfo>> + Send {
+        let shards = self.shards.clone();
+        async move {
+            let mut lists = Vec::with_capacity(shards.len());
+            for shard in shards.iter() {
+                lists.push(shard.state.read().await.fields.list(prefix.as_deref()));
+            }
+            crate::fields::merge_lists(lists)
+        }
+    }
+
+    fn get_patterns(&self, limit: Option<usize>) -> impl std::future::Future<Output = Vec<PatternInfo>> + Send {
+        let shared = self.shared.clone();
+        async move { shared.patterns.list(limit) }
+    }
+
+    fn get_hosts(&self) -> impl std::future::Future<Output = Vec<HostInfo>> + Send {
+        let shared = self.shared.clone();
+        async move {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64();
+            shared.hosts.lock().unwrap().list(now)
+        }
+    }
+
+    fn get_message(&self, id: u64) -> impl std::future::Future<Output = Option<StoredMessage>> + Send {
+        let shards = self.shards.clone();
+        async move {
+            for shard in shards.iter() {
+                if let Some(stored) = shard.state.read().await.get(id) {
+                    return Some(stored.clone());
+                }
+            }
+            None
+        }
+    }
+
+    fn get_page(&self, filter: MessageFilter, after_id: u64, limit: usize) -> impl std::future::Future<Output = Vec<StoredMessage>> + Send {
+        let shards = self.shards.clone();
+        async move {
+            let mut messages = Vec::new();
+            for shard in shards.iter() {
+                let state_guard = shard.state.read().await;
+                let start = state_guard.messages.partition_point(|stored| stored.id <= after_id);
+
+                messages.extend(
+                    state_guard
+                        .messages
+                        .range(start..)
+                        .filter(|stored| filter.matches(*stored))
+                        .take(limit)
+                        .cloned(),
+                );
+            }
+            messages.sort_unstable_by_key(|stored| stored.id);
+            messages.truncate(limit);
+            messages
+        }
+    }
+
+    fn get_context(&self, id: u64, before: usize, after: usize, same: Vec<String>) -> impl std::future::Future<Output = Option<MessageContext>> + Send {
+        let store = self.clone();
+        async move {
+            let anchor = store.get_message(id).await?;
+
+            // Values the neighbours must share; a field missing on the anchor
+            // must also be missing on the neighbour
+            let expected: Vec<Option<String>> = same
+                .iter()
+                .map(|field| {
+                    field_value(&anchor.gelf_message, anchor.received_at, field)
+                        .map(|value| value.as_text().into_owned())
+                })
+                .collect();
+            let same_source = |stored: &&StoredMessage| {
+                same.iter().zip(&expected).all(|(field, expected)| {
+                    let value = field_value(&stored.gelf_message, stored.received_at, field);
+                    match (value, expected) {
+                        (Some(value), Some(expected)) => value.as_text() == expected.as_str(),
+                        (None, None) => true,
+                        _ => false,
+                    }
+                })
+            };
+
+            let mut before_messages = Vec::new();
+            let mut after_messages = Vec::new();
+            for shard in store.shards.iter() {
+                let state_guard = shard.state.read().await;
+                let position = state_guard.messages.partition_point(|stored| stored.id < id);
+                before_messages.extend(
+                    state_guard
+                        .messages
+                        .range(..position)
+                        .rev()
+                        .filter(same_source)
+                        .take(before)
+                        .map(|stored| stored.to_response()),
+                );
+
+                let start = state_guard.messages.partition_point(|stored| stored.id <= id);
+                after_messages.extend(
+                    state_guard
+                        .messages
+                        .range(start..)
+                        .filter(same_source)
+                        .take(after)
+                        .map(|stored| stored.to_response()),
+                );
+            }
+            let mut before_messages = newest_first(before_messages, Some(before));
+            before_messages.reverse();
+            after_messages.sort_unstable_by_key(|message| message.id);
+            after_messages.truncate(after);
+
+            Some(MessageContext {
+                message: anchor.to_response(),
+                before: before_messages,
+                after: after_messages,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(host: &str, text: &str) -> GelfMessage {
+        serde_json::from_value(serde_json::json!({ "host": host, "short_message": text })).unwrap()
+    }
+
+    async fn add(store: &InMemoryMessageStore, host: &str, text: &str) {
+        let metadata = ReceiveMetadata {
+            source: Some("10.0.0.1:5000".to_string()),
+            ..ReceiveMetadata::default()
+        };
+        store.add_message(message(host, text), String::new(), metadata).await;
+    }
+
+    async fn ids(store: &InMemoryMessageStore) -> Vec<u64> {
+        store
+            .get_page(MessageFilter::default(), 0, usize::MAX)
+            .await
+            .iter()
+            .map(|stored| stored.id)
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_writers_leave_the_newest_messages() {
+        let store = InMemoryMessageStore::new(500).with_shards(4);
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for sequence in 0..1000 {
+                        add(&store, "busy", &format!("request {} of writer {}", sequence, writer)).await;
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+
+        assert_eq!(store.message_count(), 500);
+        assert_eq!(ids(&store).await, (7501..=8000).collect::<Vec<_>>());
+        let hosts = store.get_hosts().await;
+        assert_eq!((hosts.len(), hosts[0].messages), (1, 8000));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn concurrent_writers_never_evict_below_the_limit() {
+        for round in 0..20 {
+            let store = InMemoryMessageStore::new(64).with_shards(8);
+            let writers: Vec<_> = (0..16)
+                .map(|writer| {
+                    let store = store.clone();
+                    tokio::spawn(async move {
+                        for sequence in 0..200 {
+                            add(&store, &format!("host-{}", writer), &format!("event {}", sequence)).await;
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.await.unwrap();
+            }
+
+            let mut buffered = 0;
+            for shard in store.shards.iter() {
+                buffered += shard.state.read().await.messages.len();
+            }
+            assert_eq!((store.message_count(), buffered), (64, 64), "round {}", round);
+            assert_eq!(ids(&store).await, (3137..=3200).collect::<Vec<_>>(), "round {}", round);
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_merge_the_shards_newest_first() {
+        let store = InMemoryMessageStore::new(100).with_shards(3);
+        for i in 0..30 {
+            add(&store, "a", &format!("message {}", i)).await;
+        }
+
+        let newest: Vec<u64> = store
+            .get_messages(MessageFilter::default(), Some(5))
+            .await
+            .iter()
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(newest, [30, 29, 28, 27, 26]);
+        assert_eq!(store.get_messages(MessageFilter::default(), None).await.len(), 30);
+        assert_eq!(store.get_stats().await["total_messages"], 30);
+    }
+
+    #[tokio::test]
+    async fn a_busy_host_is_spread_over_the_shards() {
+        let store = InMemoryMessageStore::new(100).with_shards(4);
+        for i in 0..8 {
+            add(&store, "busy", &format!("message {}", i)).await;
+        }
+
+        for shard in store.shards.iter() {
+            assert_eq!(shard.state.read().await.messages.len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn duplicates_meet_in_one_shard() {
+        let dedup = DedupConfig {
+            enabled: true,
+            window_secs: 60.0,
+        };
+        let store = InMemoryMessageStore::new(100).with_dedup(&dedup).with_shards(4);
+        for _ in 0..5 {
+            add(&store, "a", "disk full").await;
+            add(&store, "b", "disk full").await;
+        }
+
+        assert_eq!(store.message_count(), 2);
+        let messages = store.get_messages(MessageFilter::default(), None).await;
+        assert!(messages.iter().all(|message| message.gelf_message.additional_fields["_repeat_count"] == 5));
+    }
+
+    #[tokio::test]
+    async fn repeat_fields_sent_by_the_client_leave_the_index() {
+        let store = InMemoryMessageStore::new(100).with_dedup(&DedupConfig {
+            enabled: true,
+            window_secs: 60.0,
+        });
+        for _ in 0..3 {
+            let message: GelfMessage = serde_json::from_value(serde_json::json!({
+                "host": "a",
+                "short_message": "disk full",
+                "_first_seen": "yesterday"
+            }))
+            .unwrap();
+            store.add_message(message, String::new(), ReceiveMetadata::default()).await;
+        }
+
+        let search = |text: &str| SearchQuery::parse(text).unwrap();
+        assert!(store.search(search("yesterday"), None).await.is_empty());
+        let found = store.search(search("disk"), None).await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].gelf_message.additional_fields["_repeat_count"], 3);
+    }
+
+    #[tokio::test]
+    async fn patterns_span_the_shards() {
+        let store = InMemoryMessageStore::new(100).with_shards(4);
+        for i in 0..12 {
+            add(&store, "a", &format!("user {} logged in", i)).await;
+        }
+        add(&store, "a", "cache warmed").await;
+
+        let patterns = store.get_patterns(None).await;
+        assert_eq!(patterns.len(), 2);
+        assert_eq!((patterns[0].template.as_str(), patterns[0].count), ("user <NUM> logged in", 12));
+        let filter = MessageFilter {
+            pattern: Some(patterns[0].id),
+            ..MessageFilter::default()
+        };
+        assert_eq!(store.get_messages(filter, None).await.len(), 12);
+
+        store.set_capacity(1).await;
+        assert_eq!(store.get_patterns(None).await.len(), 1);
+    }
+}
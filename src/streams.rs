@@ -0,0 +1,284 @@
+use crate::config::ConfigError;
+use crate::dedup::DedupConfig;
+use crate::gelf::{GelfMessage, ReceiveMetadata};
+use crate::hosts::HostsConfig;
+use crate::query::Query;
+use crate::storage::{delegate_message_store, InMemoryMessageStore, MessageStore};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Name of the stream that receives every message
+pub const DEFAULT_STREAM: &str = "default";
+
+/// How often streams with a maximum age are checked for expired messages
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+fn default_max_messages() -> usize {
+    10000
+}
+
+/// A named buffer receiving the messages that match its filter
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamConfig {
+    pub name: String,
+    /// Query selecting the messages of this stream
+    pub filter: String,
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
+    /// Messages older than this many seconds are evicted
+    #[serde(default)]
+    pub max_age_secs: Option<f64>,
+}
+
+/// Summary of a stream for `GET /streams`
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamInfo {
+    pub name: String,
+    pub filter: Option<String>,
+    pub max_messages: usize,
+    pub max_age_secs: Option<f64>,
+    pub total_messages: usize,
+}
+
+struct Stream {
+    name: String,
+    filter: Option<(String, Query)>,
+    store: InMemoryMessageStore,
+}
+
+/// Stores messages into named streams, each with its own buffer
+///
+/// The default stream receives every message; the other streams receive
+/// the messages matching their filter, so a message can be in several.
+/// As a `MessageStore` the router stores into all matching streams and
+/// reads from the default stream.
+#[derive(Clone)]
+pub struct StreamRouter {
+    default: InMemoryMessageStore,
+    streams: Arc<Vec<Stream>>,
+}
+
+impl StreamRouter {
+    /// A router with only the default stream
+    pub fn new(default: InMemoryMessageStore) -> Self {
+        Self {
+            streams: Arc::new(vec![Stream {
+                name: DEFAULT_STREAM.to_string(),
+                filter: None,
+                store: default.clone(),
+            }]),
+            default,
+        }
+    }
+
+    /// Add the configured streams, which are sharded, collapse duplicates and track hosts like the default one
+    pub fn from_config(
+        default: InMemoryMessageStore,
+        configs: &[StreamConfig],
+        dedup: &DedupConfig,
+        hosts: &HostsConfig,
+    ) -> Result<Self, ConfigError> {
+        let shard_count = default.shard_count();
+        let mut router = Self::new(default);
+        let streams = Arc::get_mut(&mut router.streams).unwrap();
+
+        for config in configs {
+            if config.name == DEFAULT_STREAM {
+                return Err(ConfigError::Invalid(format!("stream name '{}' is reserved", DEFAULT_STREAM)));
+            }
+            if streams.iter().any(|stream| stream.name == config.name) {
+                return Err(ConfigError::Invalid(format!("stream '{}' is defined twice", config.name)));
+            }
+            if config.max_messages == 0 {
+                return Err(ConfigError::Invalid(format!("stream '{}' needs max_messages above 0", config.name)));
+            }
+            let query = Query::parse(&config.filter)
+                .map_err(|e| ConfigError::Invalid(format!("stream '{}' filter: {}", config.name, e)))?;
+
+            let store = InMemoryMessageStore::new(config.max_messages)
+                .with_shards(shard_count)
+                .with_dedup(dedup)
+                .with_hosts(hosts)
+                .with_max_age(config.max_age_secs);
+            streams.push(Stream {
+                name: config.name.clone(),
+                filter: Some((config.filter.clone(), query)),
+                store,
+            });
+        }
+        Ok(router)
+    }
+
+    /// The stream with the given name; the default stream if no name is given
+    pub fn get(&self, name: Option<&str>) -> Option<InMemoryMessageStore> {
+        let name = name.unwrap_or(DEFAULT_STREAM);
+        self.streams
+            .iter()
+            .find(|stream| stream.name == name)
+            .map(|stream| stream.store.clone())
+    }
+
+    pub fn default_stream(&self) -> &InMemoryMessageStore {
+        &self.default
+    }
+
+    /// All streams, the default stream first
+    pub async fn list(&self) -> Vec<StreamInfo> {
+        let mut streams = Vec::with_capacity(self.streams.len());
+        for stream in self.streams.iter() {
+            streams.push(StreamInfo {
+                name: stream.name.clone(),
+                filter: stream.filter.as_ref().map(|(filter, _)| filter.clone()),
+                max_messages: stream.store.capacity(),
+                max_age_secs: stream.store.max_age(),
+                total_messages: stream.store.message_count(),
+            });
+        }
+        streams
+    }
+
+    /// Evict expired messages from streams with a maximum age, even when no messages arrive
+    pub fn spawn_expiry(&self) {
+        let stores: Vec<InMemoryMessageStore> = self
+            .streams
+            .iter()
+            .filter(|stream| stream.store.max_age().is_some())
+            .map(|stream| stream.store.clone())
+            .collect();
+        if stores.is_empty() {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(EXPIRY_INTERVAL);
+            loop {
+                ticker.tick().await;
+                for store in &stores {
+                    let expired = store.expire().await;
+                    if expired > 0 {
+                        debug!("Expired {} messages", expired);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl MessageStore for StreamRouter {
+    fn add_message(&self, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) -> impl std::future::Future<Output = ()> + Send {
+        let router = self.clone();
+        async move {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64();
+            for stream in router.streams.iter() {
+                if let Some((_, query)) = &stream.filter
+                    && query.matches(&gelf_message, now)
+                {
+                    debug!("Message routed to stream {}", stream.name);
+                    stream
+                        .store
+                        .add_message(gelf_message.clone(), raw_message.clone(), metadata.clone())
+                        .await;
+                }
+            }
+            router.default.add_message(gelf_message, raw_message, metadata).await;
+        }
+    }
+
+    delegate_message_store!(default);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::MessageFilter;
+    use crate::web::handlers::{handle_rejection, select_stream, UnknownStream};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use warp::Reply;
+
+    fn router() -> StreamRouter {
+        let configs: Vec<StreamConfig> = serde_json::from_value(json!([
+            { "name": "errors", "filter": "level:<=3", "max_messages": 10 },
+            { "name": "payments", "filter": "_service:payments" }
+        ]))
+        .unwrap();
+        StreamRouter::from_config(
+            InMemoryMessageStore::new(100),
+            &configs,
+            &DedupConfig::default(),
+            &HostsConfig::default(),
+        )
+        .unwrap()
+    }
+
+    async fn add(router: &StreamRouter, message: serde_json::Value) {
+        let message: GelfMessage = serde_json::from_value(message).unwrap();
+        router
+            .add_message(message, String::new(), ReceiveMetadata::default())
+            .await;
+    }
+
+    fn counts(streams: &[StreamInfo]) -> Vec<(&str, usize)> {
+        streams
+            .iter()
+            .map(|stream| (stream.name.as_str(), stream.total_messages))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn messages_go_to_every_matching_stream_and_the_default_one() {
+        let router = router();
+        add(&router, json!({ "short_message": "failed", "level": 3, "_service": "payments" })).await;
+        add(&router, json!({ "short_message": "paid", "level": 6, "_service": "payments" })).await;
+        add(&router, json!({ "short_message": "started", "level": 6 })).await;
+
+        assert_eq!(
+            counts(&router.list().await),
+            [("default", 3), ("errors", 1), ("payments", 2)]
+        );
+        let errors = router.get(Some("errors")).unwrap();
+        let messages = errors.get_messages(MessageFilter::default(), None).await;
+        assert_eq!(messages[0].gelf_message.short_message.as_deref(), Some("failed"));
+        assert_eq!(router.get_messages(MessageFilter::default(), None).await.len(), 3);
+    }
+
+    #[test]
+    fn invalid_streams_are_rejected() {
+        let configs = |value| serde_json::from_value::<Vec<StreamConfig>>(value).unwrap();
+        let build = |configs: &[StreamConfig]| {
+            StreamRouter::from_config(
+                InMemoryMessageStore::new(100),
+                configs,
+                &DedupConfig::default(),
+                &HostsConfig::default(),
+            )
+        };
+        assert!(build(&configs(json!([{ "name": "default", "filter": "level:3" }]))).is_err());
+        assert!(build(&configs(json!([{ "name": "a", "filter": "level:3" }, { "name": "a", "filter": "level:4" }]))).is_err());
+        assert!(build(&configs(json!([{ "name": "a", "filter": "level:3", "max_messages": 0 }]))).is_err());
+        assert!(build(&configs(json!([{ "name": "a", "filter": "(level:3" }]))).is_err());
+    }
+
+    #[tokio::test]
+    async fn the_stream_parameter_selects_a_stream() {
+        let router = router();
+        let params = |stream: &str| HashMap::from([("stream".to_string(), stream.to_string())]);
+
+        let errors = select_stream(params("errors"), router.clone()).await.unwrap();
+        assert_eq!(errors.capacity(), 10);
+        for default in [HashMap::new(), params("")] {
+            assert_eq!(select_stream(default, router.clone()).await.unwrap().capacity(), 100);
+        }
+
+        let rejection = select_stream(params("audit"), router.clone()).await.err().unwrap();
+        assert!(matches!(rejection.find(), Some(UnknownStream(name)) if name == "audit"));
+        let response = handle_rejection(rejection).await.unwrap().into_response();
+        assert_eq!(response.status(), 404);
+    }
+}
@@ -0,0 +1,468 @@
+use crate::config::ConfigError;
+use crate::dedup::DedupConfig;
+use crate::gelf::{GelfMessage, ReceiveMetadata};
+use crate::hosts::HostsConfig;
+use crate::storage::{delegate_message_store, InMemoryMessageStore, MessageStore};
+use crate::streams::{StreamConfig, StreamRouter};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::debug;
+
+/// Name of the tenant receiving messages that belong to no configured tenant
+pub const DEFAULT_TENANT: &str = "default";
+
+fn default_max_messages() -> usize {
+    10000
+}
+
+/// Tokens are never shown, e.g. in `GET /admin/config`
+fn mask_tokens<S: Serializer>(tokens: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(tokens.iter().map(|_| "***"))
+}
+
+/// Separation of messages and API access by team
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenancyConfig {
+    /// Additional field naming the tenant of messages that did not arrive on a tenant's port
+    pub field: Option<String>,
+    /// Tokens with access to every tenant and to the admin endpoints
+    #[serde(serialize_with = "mask_tokens")]
+    pub admin_tokens: Vec<String>,
+    pub tenants: Vec<TenantConfig>,
+}
+
+/// A team with its own buffer and API tokens
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TenantConfig {
+    pub name: String,
+    /// Messages buffered for this tenant, its copies of the streams included
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
+    /// Tokens granting access to this tenant's messages only
+    #[serde(default, serialize_with = "mask_tokens")]
+    pub api_tokens: Vec<String>,
+    /// UDP ports whose messages belong to this tenant, whatever their fields say
+    #[serde(default)]
+    pub udp_ports: Vec<u16>,
+}
+
+/// What a request's token grants access to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Access {
+    /// Every tenant and the admin endpoints
+    Admin,
+    /// The named tenant only
+    Tenant(String),
+}
+
+/// Usage of a tenant for `GET /tenants`, over its default buffer and its streams
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantInfo {
+    pub name: String,
+    pub max_messages: usize,
+    pub total_messages: usize,
+    pub capacity_used_percent: f64,
+    /// Messages received since startup, including evicted ones
+    pub received_messages: u64,
+    /// Payload bytes received since startup, before decompression
+    pub received_bytes: u64,
+    pub udp_ports: Vec<u16>,
+    pub streams: usize,
+}
+
+struct Tenant {
+    name: String,
+    api_tokens: Vec<String>,
+    udp_ports: Vec<u16>,
+    router: StreamRouter,
+    received_messages: AtomicU64,
+    received_bytes: AtomicU64,
+}
+
+impl Tenant {
+    fn new(name: &str, router: StreamRouter) -> Self {
+        Self {
+            name: name.to_string(),
+            api_tokens: Vec::new(),
+            udp_ports: Vec::new(),
+            router,
+            received_messages: AtomicU64::new(0),
+            received_bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Compare tokens in time independent of where they differ
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Stores messages into per-tenant buffers, each with its own streams
+///
+/// A message belongs to the tenant owning the UDP port it arrived on, or
+/// else to the tenant named by the configured field; anything else goes to
+/// the default tenant, which only admins can read. As a `MessageStore` the
+/// router stores into the message's tenant and reads from the default tenant.
+#[derive(Clone)]
+pub struct TenantRouter {
+    default: StreamRouter,
+    tenants: Arc<Vec<Tenant>>,
+    field: Option<String>,
+    admin_tokens: Arc<Vec<String>>,
+}
+
+impl TenantRouter {
+    /// A router without tenants, where every request has admin access
+    pub fn new(default: StreamRouter) -> Self {
+        Self {
+            tenants: Arc::new(vec![Tenant::new(DEFAULT_TENANT, default.clone())]),
+            default,
+            field: None,
+            admin_tokens: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Add the configured tenants, each buffering its messages and streams like the default one, with as many shards
+    ///
+    /// A tenant's `max_messages` is shared between its default buffer and its
+    /// copies of the streams in proportion to their global sizes, so a tenant
+    /// never holds more messages than its quota.
+    pub fn from_config(
+        default: StreamRouter,
+        config: &TenancyConfig,
+        udp_port: u16,
+        streams: &[StreamConfig],
+        dedup: &DedupConfig,
+        hosts: &HostsConfig,
+    ) -> Result<Self, ConfigError> {
+        let shard_count = default.default_stream().shard_count();
+        let global_size = default.default_stream().capacity() + streams.iter().map(|stream| stream.max_messages).sum::<usize>();
+        let mut router = Self::new(default);
+        if config.tenants.is_empty() {
+            return Ok(router);
+        }
+        if config.admin_tokens.is_empty() {
+            return Err(ConfigError::Invalid("tenancy needs at least one admin token".to_string()));
+        }
+        if config.field.as_deref().is_some_and(str::is_empty) {
+            return Err(ConfigError::Invalid("tenancy.field must not be empty".to_string()));
+        }
+
+        let mut tokens: HashSet<&str> = HashSet::new();
+        let mut ports: HashSet<u16> = HashSet::from([udp_port]);
+        for token in &config.admin_tokens {
+            if token.is_empty() || !tokens.insert(token) {
+                return Err(ConfigError::Invalid("admin tokens must be non-empty and unique".to_string()));
+            }
+        }
+
+        let tenants = Arc::get_mut(&mut router.tenants).unwrap();
+        for tenant_config in &config.tenants {
+            let name = &tenant_config.name;
+            if name.is_empty() || name == DEFAULT_TENANT {
+                return Err(ConfigError::Invalid(format!("tenant name '{}' is reserved", name)));
+            }
+            if tenants.iter().any(|tenant| &tenant.name == name) {
+                return Err(ConfigError::Invalid(format!("tenant '{}' is defined twice", name)));
+            }
+            if tenant_config.max_messages == 0 {
+                return Err(ConfigError::Invalid(format!("tenant '{}' needs max_messages above 0", name)));
+            }
+            for token in &tenant_config.api_tokens {
+                if token.is_empty() || !tokens.insert(token) {
+                    return Err(ConfigError::Invalid(format!(
+                        "tenant '{}' has an empty token or one used elsewhere",
+                        name
+                    )));
+                }
+            }
+            for port in &tenant_config.udp_ports {
+                if !ports.insert(*port) {
+                    return Err(ConfigError::Invalid(format!("tenant '{}': UDP port {} is already in use", name, port)));
+                }
+            }
+
+            let quota = tenant_config.max_messages;
+            let share = |size: usize| (size as u128 * quota as u128 / global_size as u128) as usize;
+            let tenant_streams: Vec<StreamConfig> = streams
+                .iter()
+                .map(|stream| StreamConfig {
+                    max_messages: share(stream.max_messages),
+                    ..stream.clone()
+                })
+                .collect();
+            let default_size = quota - tenant_streams.iter().map(|stream| stream.max_messages).sum::<usize>();
+            if default_size == 0 || tenant_streams.iter().any(|stream| stream.max_messages == 0) {
+                return Err(ConfigError::Invalid(format!(
+                    "tenant '{}' needs a larger max_messages to give each of its streams a share",
+                    name
+                )));
+            }
+
+            let store = InMemoryMessageStore::new(default_size)
+                .with_shards(shard_count)
+                .with_dedup(dedup)
+                .with_hosts(hosts);
+            let streams = StreamRouter::from_config(store, &tenant_streams, dedup, hosts)?;
+            tenants.push(Tenant {
+                api_tokens: tenant_config.api_tokens.clone(),
+                udp_ports: tenant_config.udp_ports.clone(),
+                ..Tenant::new(name, streams)
+            });
+        }
+
+        router.field = config.field.clone();
+        router.admin_tokens = Arc::new(config.admin_tokens.clone());
+        Ok(router)
+    }
+
+    /// Whether tenants are configured, so that requests need a token
+    pub fn is_enabled(&self) -> bool {
+        self.tenants.len() > 1
+    }
+
+    /// UDP ports the tenants receive messages on, besides the main one
+    pub fn udp_ports(&self) -> Vec<u16> {
+        self.tenants
+            .iter()
+            .flat_map(|tenant| tenant.udp_ports.iter().copied())
+            .collect()
+    }
+
+    /// Default stores of the configured tenants, without the default tenant's
+    pub fn tenant_stores(&self) -> Vec<InMemoryMessageStore> {
+        self.tenants
+            .iter()
+            .skip(1)
+            .map(|tenant| tenant.router.default_stream().clone())
+            .collect()
+    }
+
+    /// What a token grants access to; `None` if tenants are configured and the token is unknown
+    pub fn authorize(&self, token: Option<&str>) -> Option<Access> {
+        if !self.is_enabled() {
+            return Some(Access::Admin);
+        }
+        let token = token?;
+        if self.admin_tokens.iter().any(|admin| token_matches(admin, token)) {
+            return Some(Access::Admin);
+        }
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.api_tokens.iter().any(|api| token_matches(api, token)))
+            .map(|tenant| Access::Tenant(tenant.name.clone()))
+    }
+
+    /// Streams of the tenant with the given name; the default tenant's if no name is given
+    pub fn get(&self, name: Option<&str>) -> Option<StreamRouter> {
+        let name = name.unwrap_or(DEFAULT_TENANT);
+        self.tenants
+            .iter()
+            .find(|tenant| tenant.name == name)
+            .map(|tenant| tenant.router.clone())
+    }
+
+    /// Usage of all tenants, the default tenant first
+    pub async fn list(&self) -> Vec<TenantInfo> {
+        let mut tenants = Vec::with_capacity(self.tenants.len());
+        for tenant in self.tenants.iter() {
+            let streams = tenant.router.list().await;
+            let max_messages = streams.iter().map(|stream| stream.max_messages).sum::<usize>();
+            let total_messages = streams.iter().map(|stream| stream.total_messages).sum::<usize>();
+            tenants.push(TenantInfo {
+                name: tenant.name.clone(),
+                max_messages,
+                total_messages,
+                capacity_used_percent: (total_messages as f64 / max_messages as f64) * 100.0,
+                received_messages: tenant.received_messages.load(Ordering::Relaxed),
+                received_bytes: tenant.received_bytes.load(Ordering::Relaxed),
+                udp_ports: tenant.udp_ports.clone(),
+                streams: streams.len() - 1,
+            });
+        }
+        tenants
+    }
+
+    /// Evict expired messages from the streams of every tenant
+    pub fn spawn_expiry(&self) {
+        for tenant in self.tenants.iter() {
+            tenant.router.spawn_expiry();
+        }
+    }
+
+    /// Tenant a message belongs to, by the port it arrived on or else by its field
+    fn assign(&self, message: &GelfMessage, metadata: &ReceiveMetadata) -> &Tenant {
+        if let Some(port) = metadata.port
+            && let Some(tenant) = self.tenants.iter().find(|tenant| tenant.udp_ports.contains(&port))
+        {
+            return tenant;
+        }
+        let named = self
+            .field
+            .as_deref()
+            .and_then(|field| message.get_field(field))
+            .and_then(|value| value.as_str().map(str::to_string));
+        named
+            .and_then(|name| self.tenants.iter().skip(1).find(|tenant| tenant.name == name))
+            .unwrap_or(&self.tenants[0])
+    }
+}
+
+impl MessageStore for TenantRouter {
+    fn add_message(&self, gelf_message: GelfMessage, raw_message: String, metadata: ReceiveMetadata) -> impl std::future::Future<Output = ()> + Send {
+        let router = self.clone();
+        async move {
+            let tenant = router.assign(&gelf_message, &metadata);
+            debug!("Message assigned to tenant {}", tenant.name);
+            tenant.received_messages.fetch_add(1, Ordering::Relaxed);
+            tenant
+                .received_bytes
+                .fetch_add(metadata.size_bytes as u64, Ordering::Relaxed);
+            tenant.router.add_message(gelf_message, raw_message, metadata).await;
+        }
+    }
+
+    delegate_message_store!(default);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerting::{AlertEngine, AlertsConfig};
+    use crate::config::Config;
+    use crate::web::routes::create_routes;
+    use clap::Parser;
+    use serde_json::json;
+
+    fn tenancy(tenants: serde_json::Value) -> TenancyConfig {
+        serde_json::from_value(json!({ "field": "_tenant", "admin_tokens": ["admin"], "tenants": tenants })).unwrap()
+    }
+
+    fn router(config: &TenancyConfig, streams: &[StreamConfig]) -> Result<TenantRouter, ConfigError> {
+        let dedup = DedupConfig::default();
+        let hosts = HostsConfig::default();
+        let default = StreamRouter::from_config(InMemoryMessageStore::new(100), streams, &dedup, &hosts)?;
+        TenantRouter::from_config(default, config, 12201, streams, &dedup, &hosts)
+    }
+
+    fn errors_stream() -> Vec<StreamConfig> {
+        serde_json::from_value(json!([{ "name": "errors", "filter": "level:<=3", "max_messages": 300 }])).unwrap()
+    }
+
+    fn two_tenants() -> TenantRouter {
+        let config = tenancy(json!([
+            { "name": "a", "api_tokens": ["token-a"] },
+            { "name": "b", "api_tokens": ["token-b"] }
+        ]));
+        router(&config, &[]).unwrap()
+    }
+
+    async fn add(router: &TenantRouter, tenant: &str, text: &str) {
+        let message: GelfMessage =
+            serde_json::from_value(json!({ "host": "web-1", "short_message": text, "_tenant": tenant })).unwrap();
+        router
+            .add_message(message, String::new(), ReceiveMetadata::default())
+            .await;
+    }
+
+    async fn get(router: &TenantRouter, path: &str, token: Option<&str>) -> (u16, serde_json::Value) {
+        let routes = create_routes(
+            router.clone(),
+            Config::parse_from(["light-gelf-collector"]),
+            Arc::new(AlertEngine::new(&AlertsConfig::default()).unwrap()),
+        );
+        let mut request = warp::test::request().method("GET").path(path);
+        if let Some(token) = token {
+            request = request.header("authorization", format!("Bearer {}", token));
+        }
+        let response = request.reply(&routes).await;
+        let body = serde_json::from_slice(response.body()).unwrap_or_default();
+        (response.status().as_u16(), body)
+    }
+
+    fn texts(messages: &serde_json::Value) -> Vec<&str> {
+        messages
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|message| message["short_message"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn tokens_resolve_to_their_tenant() {
+        let tenants = two_tenants();
+        assert_eq!(tenants.authorize(Some("admin")), Some(Access::Admin));
+        assert_eq!(tenants.authorize(Some("token-b")), Some(Access::Tenant("b".to_string())));
+        assert_eq!(tenants.authorize(Some("token-c")), None);
+        assert_eq!(tenants.authorize(Some("token-")), None);
+        assert_eq!(tenants.authorize(None), None);
+
+        let open = router(&TenancyConfig::default(), &[]).unwrap();
+        assert!(!open.is_enabled());
+        assert_eq!(open.authorize(None), Some(Access::Admin));
+    }
+
+    #[test]
+    fn tokens_must_be_unique() {
+        let config = tenancy(json!([
+            { "name": "a", "api_tokens": ["shared"] },
+            { "name": "b", "api_tokens": ["shared"] }
+        ]));
+        assert!(router(&config, &[]).is_err());
+        assert!(router(&tenancy(json!([{ "name": "a", "api_tokens": ["admin"] }])), &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn streams_share_the_tenant_quota() {
+        let config = tenancy(json!([{ "name": "a", "max_messages": 40 }]));
+        let tenants = router(&config, &errors_stream()).unwrap();
+
+        let streams = tenants.get(Some("a")).unwrap();
+        assert_eq!(streams.get(None).unwrap().capacity(), 10);
+        assert_eq!(streams.get(Some("errors")).unwrap().capacity(), 30);
+        assert_eq!(tenants.list().await[1].max_messages, 40);
+
+        let too_small = tenancy(json!([{ "name": "a", "max_messages": 1 }]));
+        assert!(router(&too_small, &errors_stream()).is_err());
+    }
+
+    #[tokio::test]
+    async fn requests_without_a_valid_token_are_unauthorized() {
+        let tenants = two_tenants();
+        assert_eq!(get(&tenants, "/logs", None).await.0, 401);
+        assert_eq!(get(&tenants, "/logs", Some("guess")).await.0, 401);
+        assert_eq!(get(&tenants, "/logs?token=token-a", None).await.0, 200);
+        assert_eq!(get(&tenants, "/health", None).await.0, 200);
+    }
+
+    #[tokio::test]
+    async fn tenants_only_read_their_own_messages() {
+        let tenants = two_tenants();
+        add(&tenants, "a", "from a").await;
+        add(&tenants, "b", "from b").await;
+        add(&tenants, "nobody", "unassigned").await;
+
+        let (status, messages) = get(&tenants, "/logs", Some("token-a")).await;
+        assert_eq!(status, 200);
+        assert_eq!(texts(&messages), ["from a"]);
+        assert_eq!(get(&tenants, "/logs?tenant=a", Some("token-a")).await.0, 200);
+
+        assert_eq!(get(&tenants, "/logs?tenant=b", Some("token-a")).await.0, 403);
+        assert_eq!(get(&tenants, "/search?q=from&tenant=b", Some("token-a")).await.0, 403);
+        assert_eq!(get(&tenants, "/tenants", Some("token-a")).await.0, 403);
+
+        assert_eq!(texts(&get(&tenants, "/logs?tenant=b", Some("admin")).await.1), ["from b"]);
+        assert_eq!(texts(&get(&tenants, "/logs", Some("admin")).await.1), ["unassigned"]);
+        assert_eq!(get(&tenants, "/logs?tenant=c", Some("admin")).await.0, 404);
+    }
+}
@@ -1,5 +1,8 @@
+use crate::alerting::AlertEngine;
 use crate::compression::CompressionManager;
-use crate::gelf::{GelfParser, JsonGelfParser};
+use crate::gelf::{GelfParser, JsonGelfParser, ReceiveMetadata};
+use crate::output::Outputs;
+use crate::processing::{Action, Pipeline};
 use crate::storage::MessageStore;
 use std::sync::Arc;
 use tokio::net::UdpSocket;
@@ -25,6 +28,9 @@ pub struct UdpMessageHandler<S: MessageStore, P: GelfParser> {
     compression_manager: CompressionManager,
     parser: P,
     config: UdpHandlerConfig,
+    pipeline: Option<Arc<Pipeline>>,
+    alerts: Option<Arc<AlertEngine>>,
+    outputs: Option<Outputs>,
 }
 
 impl<S: MessageStore> UdpMessageHandler<S, JsonGelfParser> {
@@ -39,6 +45,9 @@ impl<S: MessageStore> UdpMessageHandler<S, JsonGelfParser> {
             compression_manager: CompressionManager::new(),
             parser: JsonGelfParser,
             config,
+            pipeline: None,
+            alerts: None,
+            outputs: None,
         }
     }
 }
@@ -51,11 +60,38 @@ impl<S: MessageStore, P: GelfParser> UdpMessageHandler<S, P> {
             compression_manager: CompressionManager::new(),
             parser,
             config: UdpHandlerConfig::default(),
+            pipeline: None,
+            alerts: None,
+            outputs: None,
         }
     }
 
+    /// Run every parsed message through the processing pipeline before storing it
+    pub fn with_pipeline(mut self, pipeline: Arc<Pipeline>) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    /// Evaluate alert rules on every parsed message
+    pub fn with_alerts(mut self, alerts: Arc<AlertEngine>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// Hand every accepted message to the outputs before storing it
+    pub fn with_outputs(mut self, outputs: Outputs) -> Self {
+        self.outputs = Some(outputs);
+        self
+    }
+
+    /// Name of the parser, as recorded in message metadata
+    pub fn parser_name(&self) -> &'static str {
+        self.parser.name()
+    }
+
     pub async fn run(&self) {
         let mut buf = vec![0; self.config.buffer_size];
+        let port = self.socket.local_addr().ok().map(|addr| addr.port());
         debug!("Starting UDP message handler with buffer size: {}", buf.len());
 
         loop {
@@ -65,14 +101,8 @@ impl<S: MessageStore, P: GelfParser> UdpMessageHandler<S, P> {
                     debug!("Received {} bytes from {}", len, addr);
                     let raw_data = &buf[..len];
 
-                    // Log raw data information
-                    debug!(
-                        "Raw data first 10 bytes: {:?}",
-                        &raw_data[..std::cmp::min(10, raw_data.len())]
-                    );
-
                     // Try to decompress the data
-                    let message_str = match self.compression_manager.decompress(raw_data) {
+                    let mut message_str = match self.compression_manager.decompress(raw_data) {
                         Ok(decompressed) => {
                             if decompressed.len() != raw_data.len() {
                                 debug!(
@@ -92,16 +122,14 @@ impl<S: MessageStore, P: GelfParser> UdpMessageHandler<S, P> {
                         }
                     };
 
+                    // Message content is only logged once the pipeline has had a chance
+                    // to redact it
                     debug!("Message string length: {} characters", message_str.len());
-                    
-                    // Safe string truncation for logging
-                    let preview = self.get_safe_preview(&message_str, 200);
-                    debug!("Message preview (first ~200 chars): {}", preview);
 
                     // Parse GELF message
                     debug!("Attempting to parse GELF message...");
                     match self.parser.parse(&message_str) {
-                        Ok(gelf_msg) => {
+                        Ok(mut gelf_msg) => {
                             debug!("Successfully parsed GELF message structure");
                             debug!("GELF version: {:?}", gelf_msg.version);
                             debug!("GELF host: {:?}", gelf_msg.host);
@@ -109,20 +137,44 @@ impl<S: MessageStore, P: GelfParser> UdpMessageHandler<S, P> {
                             debug!("GELF level: {:?}", gelf_msg.level);
                             debug!("GELF facility: {:?}", gelf_msg.facility);
 
+                            let metadata = ReceiveMetadata {
+                                source: Some(addr.to_string()),
+                                compression: self.compression_manager.detect(raw_data).map(String::from),
+                                size_bytes: len,
+                                parser: self.parser.name().to_string(),
+                                port,
+                            };
+
+                            let dropped = self
+                                .pipeline
+                                .as_ref()
+                                .is_some_and(|pipeline| pipeline.run(&mut gelf_msg, &mut message_str) == Action::Drop);
+                            if dropped {
+                                debug!("Message from {} dropped by processing pipeline", addr);
+                                continue;
+                            }
+
                             info!(
                                 "Received GELF message from {}: {}",
                                 addr,
                                 gelf_msg.short_message.as_deref().unwrap_or("(no message)")
                             );
 
+                            if let Some(alerts) = &self.alerts {
+                                alerts.evaluate(&gelf_msg);
+                            }
+                            if let Some(outputs) = &self.outputs {
+                                outputs.send(&gelf_msg);
+                            }
+
                             debug!("Adding message to store...");
-                            self.store.add_message(gelf_msg, message_str).await;
+                            self.store.add_message(gelf_msg, message_str, metadata).await;
                             debug!("Message successfully added to store");
                         }
                         Err(e) => {
                             warn!("Failed to parse GELF message from {}: {}", addr, e);
                             debug!("JSON parsing error details: {:?}", e);
-                            debug!("Failed message content: {}", message_str);
+                            debug!("Failed message was {} characters long", message_str.len());
                         }
                     }
                 }
@@ -133,19 +185,6 @@ impl<S: MessageStore, P: GelfParser> UdpMessageHandler<S, P> {
             }
         }
     }
-
-    fn get_safe_preview<'a>(&self, text: &'a str, max_len: usize) -> &'a str {
-        if text.len() <= max_len {
-            text
-        } else {
-            // Find a safe character boundary at or before max_len bytes
-            let mut end = max_len;
-            while end > 0 && !text.is_char_boundary(end) {
-                end -= 1;
-            }
-            &text[..end]
-        }
-    }
 }
 
 /// Convenience function to handle UDP messages
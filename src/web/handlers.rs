@@ -1,10 +1,160 @@
-use crate::storage::MessageStore;
+use crate::aggregate::AggregationRequest;
+use crate::alerting::AlertEngine;
+use crate::config::Config;
+use crate::export::ExportFormat;
+use crate::filter::{FilterError, MessageFilter};
+use crate::gelf::MessageResponse;
+use crate::index::SearchQuery;
+use crate::storage::{InMemoryMessageStore, MessageStore, StoreEvent};
+use crate::streams::StreamRouter;
+use crate::tenants::{Access, TenantRouter};
+use crate::web::websocket::run_session;
 use futures_util::StreamExt;
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio_stream::wrappers::BroadcastStream;
-use tracing::debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info};
+use warp::http::StatusCode;
 use warp::Reply;
 
+/// Request body for changing the store capacity
+#[derive(Debug, Deserialize)]
+pub struct CapacityRequest {
+    pub max_size: usize,
+}
+
+/// Rejection for a `stream` parameter naming no configured stream
+#[derive(Debug)]
+pub struct UnknownStream(pub String);
+
+impl warp::reject::Reject for UnknownStream {}
+
+/// Rejection for a request without a valid token while tenants are configured
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Rejection for a token that does not grant access to the requested data
+#[derive(Debug)]
+pub struct Forbidden;
+
+impl warp::reject::Reject for Forbidden {}
+
+/// Rejection for a browser request to an admin endpoint from another site
+#[derive(Debug)]
+pub struct CrossOrigin;
+
+impl warp::reject::Reject for CrossOrigin {}
+
+/// Rejection for a `tenant` parameter naming no configured tenant
+#[derive(Debug)]
+pub struct UnknownTenant(pub String);
+
+impl warp::reject::Reject for UnknownTenant {}
+
+/// Resolve the request's token, from the `Authorization: Bearer` header or the `token` parameter
+pub async fn authorize(
+    authorization: Option<String>,
+    params: HashMap<String, String>,
+    tenants: TenantRouter,
+) -> Result<Access, warp::Rejection> {
+    let token = authorization
+        .as_deref()
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .or(params.get("token").map(String::as_str))
+        .map(str::trim);
+    tenants
+        .authorize(token)
+        .ok_or_else(|| warp::reject::custom(Unauthorized))
+}
+
+/// Only let admin tokens through
+pub async fn require_admin(access: Access) -> Result<(), warp::Rejection> {
+    match access {
+        Access::Admin => Ok(()),
+        Access::Tenant(_) => Err(warp::reject::custom(Forbidden)),
+    }
+}
+
+/// Refuse requests that a browser sends to an admin endpoint on behalf of another site
+///
+/// The API allows any origin, and without tenants every request has admin
+/// access, so an `Origin` header must name the host the request was sent to.
+/// Clients other than browsers send no `Origin` and are let through.
+pub async fn require_same_origin(origin: Option<String>, host: Option<String>) -> Result<(), warp::Rejection> {
+    let Some(origin) = origin else {
+        return Ok(());
+    };
+    let origin_host = origin.split_once("://").map_or(origin.as_str(), |(_, rest)| rest);
+    if host.is_some_and(|host| host.eq_ignore_ascii_case(origin_host)) {
+        Ok(())
+    } else {
+        Err(warp::reject::custom(CrossOrigin))
+    }
+}
+
+/// Resolve the `tenant` parameter to that tenant's streams
+///
+/// Tenant tokens always get their own tenant; admins choose with the parameter
+/// and get the default tenant without it.
+pub async fn select_tenant(
+    params: HashMap<String, String>,
+    access: Access,
+    tenants: TenantRouter,
+) -> Result<StreamRouter, warp::Rejection> {
+    let requested = params.get("tenant").map(String::as_str).filter(|name| !name.is_empty());
+    let name = match &access {
+        Access::Admin => requested,
+        Access::Tenant(own) if requested.is_none_or(|name| name == own) => Some(own.as_str()),
+        Access::Tenant(_) => return Err(warp::reject::custom(Forbidden)),
+    };
+    tenants
+        .get(name)
+        .ok_or_else(|| warp::reject::custom(UnknownTenant(name.unwrap_or_default().to_string())))
+}
+
+/// Resolve the `stream` parameter to that stream's store
+pub async fn select_stream(
+    params: HashMap<String, String>,
+    router: StreamRouter,
+) -> Result<InMemoryMessageStore, warp::Rejection> {
+    let name = params.get("stream").map(String::as_str).filter(|name| !name.is_empty());
+    router
+        .get(name)
+        .ok_or_else(|| warp::reject::custom(UnknownStream(name.unwrap_or_default().to_string())))
+}
+
+/// Turn rejections raised by the handlers into JSON error responses
+pub async fn handle_rejection(rejection: warp::Rejection) -> Result<impl Reply, warp::Rejection> {
+    let (error, status) = if let Some(UnknownStream(name)) = rejection.find() {
+        (format!("unknown stream '{}'", name), StatusCode::NOT_FOUND)
+    } else if let Some(UnknownTenant(name)) = rejection.find() {
+        (format!("unknown tenant '{}'", name), StatusCode::NOT_FOUND)
+    } else if rejection.find::<Unauthorized>().is_some() {
+        ("missing or invalid token".to_string(), StatusCode::UNAUTHORIZED)
+    } else if rejection.find::<Forbidden>().is_some() {
+        ("token does not grant access".to_string(), StatusCode::FORBIDDEN)
+    } else if rejection.find::<CrossOrigin>().is_some() {
+        ("admin endpoints do not accept cross-origin requests".to_string(), StatusCode::FORBIDDEN)
+    } else {
+        return Err(rejection);
+    };
+    debug!("Rejected request: {}", error);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "error": error })),
+        status,
+    ))
+}
+
+/// Build a 400 response describing invalid filter parameters
+fn filter_error_reply(error: FilterError) -> warp::reply::WithStatus<warp::reply::Json> {
+    debug!("Invalid filter: {}", error);
+    warp::reply::with_status(warp::reply::json(&error.to_json()), StatusCode::BAD_REQUEST)
+}
+
 /// Handler for retrieving log messages
 pub async fn logs_handler<S: MessageStore>(
     params: HashMap<String, String>,
@@ -15,10 +165,381 @@ pub async fn logs_handler<S: MessageStore>(
     let limit = params.get("limit").and_then(|s| s.parse::<usize>().ok());
     debug!("Parsed limit parameter: {:?}", limit);
 
-    let messages = store.get_messages(limit).await;
+    let filter = match MessageFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(filter_error_reply(e)),
+    };
+    debug!("Parsed filter: {:?}", filter);
+
+    // Messages are buffered in arrival order, so other orders need every match
+    let sort_key: fn(&MessageResponse) -> f64 = match params.get("sort").map(String::as_str) {
+        None | Some("received") => {
+            let messages = store.get_messages(filter, limit).await;
+            debug!("Retrieved {} messages from store", messages.len());
+            return Ok(warp::reply::with_status(warp::reply::json(&messages), StatusCode::OK));
+        }
+        Some("timestamp") => |message| message.gelf_message.timestamp.unwrap_or(message.received_at),
+        Some("corrected") => |message| message.corrected_timestamp,
+        Some(other) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({
+                    "error": format!("unknown sort '{}', expected received, timestamp or corrected", other)
+                })),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    let mut messages = store.get_messages(filter, None).await;
+    messages.sort_by(|a, b| sort_key(b).total_cmp(&sort_key(a)));
+    messages.truncate(limit.unwrap_or(messages.len()));
     debug!("Retrieved {} messages from store", messages.len());
     
-    Ok(warp::reply::json(&messages))
+    Ok(warp::reply::with_status(warp::reply::json(&messages), StatusCode::OK))
+}
+
+/// Handler for full-text search over stored messages
+pub async fn search_handler<S: MessageStore>(
+    params: HashMap<String, String>,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /search endpoint with params: {:?}", params);
+
+    let query = match SearchQuery::parse(params.get("q").map(String::as_str).unwrap_or("")) {
+        Ok(query) => query,
+        Err(e) => {
+            debug!("Invalid search query: {}", e);
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+    let limit = params.get("limit").and_then(|s| s.parse::<usize>().ok());
+
+    let messages = store.search(query, limit).await;
+    debug!("Search returned {} messages", messages.len());
+
+    Ok(warp::reply::with_status(warp::reply::json(&messages), StatusCode::OK))
+}
+
+/// Handler for computing aggregations over stored messages
+pub async fn aggregate_handler<S: MessageStore>(
+    params: HashMap<String, String>,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /aggregate endpoint with params: {:?}", params);
+
+    let filter = match MessageFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(filter_error_reply(e)),
+    };
+    let request = match AggregationRequest::from_params(&params) {
+        Ok(request) => request,
+        Err(e) => {
+            debug!("Invalid aggregation request: {}", e);
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                StatusCode::BAD_REQUEST,
+            ));
+        }
+    };
+
+    let result = store.aggregate(filter, request).await;
+    debug!("Aggregated {} messages", result.total);
+
+    Ok(warp::reply::with_status(warp::reply::json(&result), StatusCode::OK))
+}
+
+/// Handler for listing additional fields seen in the buffer
+pub async fn fields_handler<S: MessageStore>(
+    params: HashMap<String, String>,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /fields endpoint with params: {:?}", params);
+
+    let fields = store.get_fields(params.get("prefix").cloned()).await;
+    debug!("Retrieved {} fields from catalogue", fields.len());
+
+    Ok(warp::reply::json(&fields))
+}
+
+/// Handler for listing alert rules and their recent firings
+pub async fn alerts_handler(
+    params: HashMap<String, String>,
+    alerts: Arc<AlertEngine>,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /alerts endpoint with params: {:?}", params);
+
+    let limit = params.get("limit").and_then(|s| s.parse::<usize>().ok());
+    let history = alerts.history(params.get("rule").map(String::as_str), limit);
+    debug!("Returning {} alert firings", history.len());
+
+    Ok(warp::reply::json(&serde_json::json!({
+        "rules": alerts.rules(),
+        "alerts": history
+    })))
+}
+
+/// Handler for listing message patterns
+pub async fn patterns_handler<S: MessageStore>(
+    params: HashMap<String, String>,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /patterns endpoint with params: {:?}", params);
+
+    let limit = params.get("limit").and_then(|s| s.parse::<usize>().ok());
+    let patterns = store.get_patterns(limit).await;
+    debug!("Returning {} patterns", patterns.len());
+
+    Ok(warp::reply::json(&patterns))
+}
+
+/// Handler for listing the hosts that sent messages
+pub async fn hosts_handler<S: MessageStore>(store: S) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /hosts endpoint");
+
+    let hosts = store.get_hosts().await;
+    debug!("Returning {} hosts", hosts.len());
+
+    Ok(warp::reply::json(&hosts))
+}
+
+/// Handler for listing the streams and their sizes
+pub async fn streams_handler(router: StreamRouter) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /streams endpoint");
+    Ok(warp::reply::json(&router.list().await))
+}
+
+/// Handler for per-tenant usage
+pub async fn tenants_handler(tenants: TenantRouter) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /tenants endpoint");
+    Ok(warp::reply::json(&tenants.list().await))
+}
+
+/// Number of messages fetched from the store per export chunk
+const EXPORT_PAGE_SIZE: usize = 500;
+
+/// Handler for exporting the filtered buffer as a chunked download
+pub async fn export_handler<S: MessageStore>(
+    params: HashMap<String, String>,
+    store: S,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    debug!("Received request for /export endpoint with params: {:?}", params);
+
+    let mut filter = match MessageFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(filter_error_reply(e).into_response()),
+    };
+    let format = match ExportFormat::parse(
+        params.get("format").map(String::as_str).unwrap_or("ndjson"),
+        params.get("columns").map(String::as_str),
+    ) {
+        Ok(format) => format,
+        Err(e) => {
+            debug!("Invalid export request: {}", e);
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": e })),
+                StatusCode::BAD_REQUEST,
+            )
+            .into_response());
+        }
+    };
+
+    // Only export what was in the buffer when the request arrived, so a busy
+    // collector cannot keep the download open forever
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    filter.until = Some(filter.until.map_or(now, |until| until.min(now)));
+
+    let filename = format!("gelf-export-{}.{}", now as u64, format.file_extension());
+    let content_type = format.content_type();
+    let header = Some(format.header()).filter(|header| !header.is_empty());
+
+    let chunks = futures_util::stream::unfold(
+        (store, filter, format, 0u64, header),
+        |(store, filter, format, after_id, header)| async move {
+            // The header (if any) goes out on its own before the first page
+            if let Some(header) = header {
+                return Some((Ok::<_, std::convert::Infallible>(header), (store, filter, format, after_id, None)));
+            }
+
+            let page = store.get_page(filter.clone(), after_id, EXPORT_PAGE_SIZE).await;
+            let last_id = page.last()?.id;
+
+            let mut chunk = String::new();
+            for stored in &page {
+                format.write_message(&mut chunk, stored);
+            }
+            debug!("Exported chunk of {} messages", page.len());
+
+            Some((Ok(chunk), (store, filter, format, last_id, None)))
+        },
+    );
+
+    let mut response = warp::reply::Response::new(warp::hyper::Body::wrap_stream(chunks));
+    let headers = response.headers_mut();
+    headers.insert(
+        warp::http::header::CONTENT_TYPE,
+        warp::http::HeaderValue::from_static(content_type),
+    );
+    if let Ok(disposition) = warp::http::HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename)) {
+        headers.insert(warp::http::header::CONTENT_DISPOSITION, disposition);
+    }
+
+    Ok(response)
+}
+
+/// Parameters of `DELETE /logs` besides the filter
+const DELETE_PARAMS: &[&str] = &["all", "token", "tenant", "stream"];
+
+/// Handler for deleting log messages, optionally restricted by filter
+///
+/// A typo must not clear the buffer, so unknown parameters are rejected and
+/// deleting everything takes an explicit `all=true`.
+pub async fn delete_logs_handler<S: MessageStore>(
+    params: HashMap<String, String>,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received DELETE request for /logs endpoint with params: {:?}", params);
+
+    let filter = match MessageFilter::check_params(&params, DELETE_PARAMS)
+        .and_then(|()| MessageFilter::from_params(&params))
+    {
+        Ok(filter) => filter,
+        Err(e) => return Ok(filter_error_reply(e)),
+    };
+    let all = match params.get("all").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(other) => {
+            return Ok(filter_error_reply(FilterError::InvalidParameter {
+                name: "all".to_string(),
+                value: other.to_string(),
+            }));
+        }
+    };
+    if filter.is_empty() && !all {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "error": "no filter given; pass all=true to delete every message"
+            })),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let deleted = store.delete_messages(filter).await;
+    info!("Deleted {} messages via admin API", deleted);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({ "deleted": deleted })),
+        StatusCode::OK,
+    ))
+}
+
+/// Handler for changing the store capacity at runtime
+pub async fn set_capacity_handler<S: MessageStore>(
+    request: CapacityRequest,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request to change capacity to {}", request.max_size);
+
+    if request.max_size == 0 {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({"error": "max_size must be greater than 0"})),
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let evicted = store.set_capacity(request.max_size).await;
+    info!("Store capacity changed to {} ({} messages evicted)", request.max_size, evicted);
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "max_capacity": request.max_size,
+            "evicted": evicted
+        })),
+        StatusCode::OK,
+    ))
+}
+
+/// Handler for showing the effective configuration
+pub async fn config_handler<S: MessageStore>(
+    config: Config,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /admin/config endpoint");
+
+    // Capacity may have been changed at runtime, report the live value
+    let mut effective = config;
+    effective.max_messages = store.capacity();
+
+    Ok(warp::reply::json(&effective))
+}
+
+/// Handler for retrieving a single message with its raw payload
+pub async fn log_by_id_handler<S: MessageStore>(id: u64, store: S) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /logs/{} endpoint", id);
+
+    match store.get_message(id).await {
+        Some(stored) => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({
+                "id": stored.id,
+                "message": stored.to_response(),
+                "raw_message": stored.raw_message,
+                "received_at": stored.received_at,
+                "metadata": stored.metadata
+            })),
+            StatusCode::OK,
+        )),
+        None => {
+            debug!("Message {} not found (never existed or already evicted)", id);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "error": format!("message {} not found", id) })),
+                StatusCode::NOT_FOUND,
+            ))
+        }
+    }
+}
+
+/// Default number of neighbours returned on each side by the context endpoint
+const DEFAULT_CONTEXT_SIZE: usize = 20;
+
+/// Handler for retrieving the messages surrounding a given message
+pub async fn log_context_handler<S: MessageStore>(
+    id: u64,
+    params: HashMap<String, String>,
+    store: S,
+) -> Result<impl Reply, warp::Rejection> {
+    debug!("Received request for /logs/{}/context endpoint with params: {:?}", id, params);
+
+    let count = |name: &str| {
+        params
+            .get(name)
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_CONTEXT_SIZE)
+    };
+    let same: Vec<String> = params
+        .get("same")
+        .map(|fields| {
+            fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_else(|| vec!["host".to_string()]);
+
+    match store.get_context(id, count("before"), count("after"), same).await {
+        Some(context) => Ok(warp::reply::with_status(warp::reply::json(&context), StatusCode::OK)),
+        None => Ok(warp::reply::with_status(
+            warp::reply::json(&serde_json::json!({ "error": format!("message {} not found", id) })),
+            StatusCode::NOT_FOUND,
+        )),
+    }
 }
 
 /// Handler for retrieving storage statistics
@@ -44,24 +565,54 @@ pub async fn web_interface_handler() -> Result<impl Reply, warp::Rejection> {
 }
 
 /// Handler for Server-Sent Events streaming
-pub fn stream_handler<S: MessageStore>(store: S) -> impl Reply {
-    debug!("New SSE client connected");
+pub fn stream_handler<S: MessageStore>(params: HashMap<String, String>, store: S) -> warp::reply::Response {
+    debug!("New SSE client connected with params: {:?}", params);
+
+    let filter = match MessageFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(e) => return filter_error_reply(e).into_response(),
+    };
     
     let rx = store.subscribe();
     let stream = BroadcastStream::new(rx)
-        .filter_map(|result| async move {
-            match result {
-                Ok(message) => {
-                    let json_str = serde_json::to_string(&message).ok()?;
-                    Some(Ok::<_, warp::Error>(
-                        warp::sse::Event::default()
-                            .event("message")
-                            .data(json_str)
-                    ))
+        .filter_map(move |result| {
+            let event = match result {
+                Ok(event) if filter.matches(event.message()) => {
+                    // Collapsed duplicates are sent as updates of the entry with the same id
+                    let name = match event {
+                        StoreEvent::Added(_) => "message",
+                        StoreEvent::Updated(_) => "update",
+                    };
+                    serde_json::to_string(event.message()).ok().map(|json_str| {
+                        Ok::<_, warp::Error>(
+                            warp::sse::Event::default()
+                                .event(name)
+                                .data(json_str)
+                        )
+                    })
                 }
+                Ok(_) => None, // Filtered out
                 Err(_) => None, // Client lagged behind, skip
-            }
+            };
+            async move { event }
         });
 
-    warp::sse::reply(warp::sse::keep_alive().stream(stream))
-}
\ No newline at end of file
+    warp::sse::reply(warp::sse::keep_alive().stream(stream)).into_response()
+}
+
+/// Handler for upgrading to a bidirectional WebSocket stream
+pub fn ws_handler<S: MessageStore>(
+    ws: warp::ws::Ws,
+    params: HashMap<String, String>,
+    store: S,
+) -> warp::reply::Response {
+    debug!("New WebSocket client with params: {:?}", params);
+
+    let filter = match MessageFilter::from_params(&params) {
+        Ok(filter) => filter,
+        Err(e) => return filter_error_reply(e).into_response(),
+    };
+
+    ws.on_upgrade(move |socket| run_session(socket, filter, store))
+        .into_response()
+}
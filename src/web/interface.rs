@@ -78,6 +78,36 @@ pub fn get_web_interface() -> String {
             background: #c53030;
         }
         
+        .query-input {
+            flex: 1;
+            min-width: 250px;
+            background: #1a1a1a;
+            color: #e0e0e0;
+            border: 1px solid #4a5568;
+            border-radius: 6px;
+            padding: 0.5rem 0.75rem;
+            font-size: 0.9rem;
+            font-family: 'Courier New', monospace;
+        }
+        
+        .query-input:focus {
+            outline: none;
+            border-color: #63b3ed;
+        }
+        
+        .query-input.invalid {
+            border-color: #e53e3e;
+        }
+        
+        .select {
+            background: #1a1a1a;
+            color: #e0e0e0;
+            border: 1px solid #4a5568;
+            border-radius: 6px;
+            padding: 0.5rem;
+            font-size: 0.9rem;
+        }
+        
         .status {
             padding: 0.5rem 1rem;
             border-radius: 6px;
@@ -170,6 +200,81 @@ pub fn get_web_interface() -> String {
             font-size: 0.9rem;
         }
         
+        .repeat-count {
+            background: #744210;
+            color: #fefcbf;
+            border-radius: 10px;
+            padding: 0.1rem 0.5rem;
+            font-size: 0.75rem;
+            margin-left: 0.5rem;
+        }
+        
+        .permalink {
+            color: #718096;
+            font-size: 0.75rem;
+            font-family: 'Courier New', monospace;
+            text-decoration: none;
+            margin-left: 0.5rem;
+        }
+        
+        .permalink:hover {
+            color: #63b3ed;
+        }
+        
+        .context-link {
+            color: #718096;
+            font-size: 0.75rem;
+            margin-left: 0.5rem;
+            cursor: pointer;
+            background: none;
+            border: none;
+        }
+        
+        .context-link:hover {
+            color: #63b3ed;
+        }
+        
+        .context-overlay {
+            display: none;
+            position: fixed;
+            inset: 0;
+            background: rgba(0,0,0,0.7);
+            z-index: 10;
+        }
+        
+        .context-overlay.open {
+            display: block;
+        }
+        
+        .context-panel {
+            position: absolute;
+            inset: 2rem;
+            background: #1a1a1a;
+            border: 1px solid #4a5568;
+            border-radius: 8px;
+            display: flex;
+            flex-direction: column;
+        }
+        
+        .context-header {
+            display: flex;
+            justify-content: space-between;
+            align-items: center;
+            padding: 1rem;
+            border-bottom: 1px solid #4a5568;
+            color: #a0aec0;
+        }
+        
+        .context-body {
+            overflow-y: auto;
+            padding: 1rem;
+        }
+        
+        .log-entry.anchor {
+            border-color: #ed8936;
+            box-shadow: 0 0 0 1px #ed8936;
+        }
+        
         .message {
             margin-top: 0.5rem;
         }
@@ -265,11 +370,24 @@ pub fn get_web_interface() -> String {
     </div>
     
     <div class="controls">
+        <select class="select" id="streamSelect" title="Stream" onchange="switchStream()" hidden></select>
         <button class="btn" onclick="toggleStream()">
             <span id="streamBtn">Pause Stream</span>
         </button>
         <button class="btn danger" onclick="clearLogs()">Clear Display</button>
         <button class="btn" onclick="loadHistoryLogs()">Load History</button>
+        <input type="text" class="query-input" id="queryInput" list="fieldList"
+               placeholder="Filter, e.g. level:<=3 AND host:web-*"
+               oninput="updateFieldSuggestions()"
+               onkeydown="if (event.key === 'Enter') applyQuery()">
+        <datalist id="fieldList"></datalist>
+        <button class="btn" onclick="applyQuery()">Apply Filter</button>
+        <select class="select" id="exportFormat" title="Export format">
+            <option value="ndjson">NDJSON</option>
+            <option value="csv">CSV</option>
+            <option value="raw">Raw GELF</option>
+        </select>
+        <button class="btn" onclick="downloadExport()">Download</button>
         <div class="status" id="status">
             <span id="statusText">Connecting...</span>
         </div>
@@ -284,10 +402,55 @@ pub fn get_web_interface() -> String {
         </div>
     </div>
 
+    <div class="context-overlay" id="contextOverlay" onclick="if (event.target === this) closeContext()">
+        <div class="context-panel">
+            <div class="context-header">
+                <span>
+                    Context grouped by
+                    <input type="text" class="query-input" id="contextSame" value="host"
+                           title="Comma-separated fields neighbours must share"
+                           onkeydown="if (event.key === 'Enter') showContext(contextId)">
+                </span>
+                <button class="btn" onclick="closeContext()">Close</button>
+            </div>
+            <div class="context-body" id="contextBody"></div>
+        </div>
+    </div>
+
     <script>
         let eventSource = null;
         let isStreaming = false;
         let logs = [];
+        let currentQuery = '';
+        let currentStream = '';
+        // Token and tenant are taken from the page address, e.g. /?token=...
+        const pageParams = new URLSearchParams(window.location.search);
+        const apiToken = pageParams.get('token') || '';
+        const currentTenant = pageParams.get('tenant') || '';
+        let knownFields = ['host', 'level', 'facility', 'short_message', 'full_message', 'file', 'line', 'timestamp'];
+        
+        // Select the chosen stream; the server uses the default stream without it
+        function withStream(params) {
+            if (apiToken) {
+                params.set('token', apiToken);
+            }
+            if (currentTenant) {
+                params.set('tenant', currentTenant);
+            }
+            if (currentStream) {
+                params.set('stream', currentStream);
+            }
+            return params;
+        }
+        
+        function queryString(params) {
+            withStream(params);
+            if (currentQuery) {
+                params.set('q', currentQuery);
+            }
+            const query = params.toString();
+            return query ? `?${query}` : '';
+        }
         
         function formatTimestamp(timestamp) {
             return new Date(timestamp * 1000).toLocaleString();
@@ -308,6 +471,7 @@ pub fn get_web_interface() -> String {
         function createLogEntry(log) {
             const entry = document.createElement('div');
             entry.className = 'log-entry';
+            entry.dataset.id = log.id;
             
             const additionalFields = Object.entries(log)
                 .filter(([key, value]) => key.startsWith('_') && value !== null && value !== undefined)
@@ -319,8 +483,13 @@ pub fn get_web_interface() -> String {
                     <div>
                         <span class="log-level ${getLevelClass(log.level)}">${getLevelText(log.level)}</span>
                         <span class="host">${log.host || 'unknown'}</span>
+                        ${log._repeat_count ? `<span class="repeat-count" title="Repeated until ${formatTimestamp(log._last_seen)}">×${log._repeat_count}</span>` : ''}
                     </div>
-                    <span class="timestamp">${formatTimestamp(log.received_at)}</span>
+                    <span>
+                        <span class="timestamp">${formatTimestamp(log.received_at)}</span>
+                        <a class="permalink" href="/logs/${log.id}" target="_blank" title="Raw message and metadata">#${log.id}</a>
+                        <button class="context-link" onclick="showContext(${log.id})" title="Surrounding messages from the same host">show context</button>
+                    </span>
                 </div>
                 <div class="message">
                     <div class="short-message">${log.short_message || 'No message'}</div>
@@ -349,27 +518,138 @@ pub fn get_web_interface() -> String {
             }
         }
         
+        // Replace an entry in place when more duplicates are collapsed into it
+        function updateLogEntry(log) {
+            const existing = document.querySelector(`#logContainer .log-entry[data-id="${log.id}"]`);
+            if (existing) {
+                existing.replaceWith(createLogEntry(log));
+            } else {
+                addLogEntry(log);
+            }
+        }
+        
+        function renderStats(data) {
+            document.getElementById('messageCount').textContent = data.total_messages;
+            document.getElementById('capacity').textContent = data.capacity_used_percent.toFixed(1);
+        }
+        
         function updateStats() {
-            fetch('/stats')
+            fetch('/stats?' + withStream(new URLSearchParams()))
                 .then(response => response.json())
-                .then(data => {
-                    document.getElementById('messageCount').textContent = data.total_messages;
-                    document.getElementById('capacity').textContent = data.capacity_used_percent.toFixed(1);
-                })
+                .then(renderStats)
                 .catch(console.error);
         }
         
-        function startStream() {
+        function setStatus(connected, text) {
+            document.getElementById('status').className = connected ? 'status connected' : 'status disconnected';
+            document.getElementById('statusText').textContent = text;
+        }
+        
+        function markQuery(error) {
+            const input = document.getElementById('queryInput');
+            if (error) {
+                input.classList.add('invalid');
+                input.title = `${error.error} (position ${error.position})`;
+            } else {
+                input.classList.remove('invalid');
+                input.title = '';
+            }
+        }
+        
+        // Prefer the WebSocket, which can change filters and pause without reconnecting
+        let socket = null;
+        let useWebSocket = 'WebSocket' in window;
+        
+        function socketOpen() {
+            return socket !== null && socket.readyState === WebSocket.OPEN;
+        }
+        
+        function sendCommand(command) {
+            socket.send(JSON.stringify(command));
+        }
+        
+        function connectWebSocket() {
+            const protocol = window.location.protocol === 'https:' ? 'wss' : 'ws';
+            const ws = new WebSocket(`${protocol}://${window.location.host}/ws` + queryString(new URLSearchParams()));
+            let opened = false;
+            socket = ws;
+            
+            ws.onopen = function() {
+                opened = true;
+                setStatus(true, 'Connected (WebSocket)');
+                isStreaming = true;
+                document.getElementById('streamBtn').textContent = 'Pause Stream';
+            };
+            
+            ws.onmessage = function(event) {
+                const data = JSON.parse(event.data);
+                switch (data.type) {
+                    case 'message':
+                        addLogEntry(data.message);
+                        break;
+                    case 'update':
+                        updateLogEntry(data.message);
+                        break;
+                    case 'backfill':
+                        data.messages.forEach(log => addLogEntry(log));
+                        break;
+                    case 'stats':
+                        renderStats(data.stats);
+                        break;
+                    case 'ack':
+                        if (data.command === 'filter') {
+                            markQuery(null);
+                            clearLogs();
+                            sendCommand({ type: 'backfill', limit: 50 });
+                        } else if (data.command === 'resume' && data.dropped > 0) {
+                            console.log(`${data.dropped} messages dropped while paused`);
+                        }
+                        break;
+                    case 'error':
+                        if (data.command === 'filter') {
+                            markQuery(data);
+                        } else {
+                            console.error(data.error);
+                        }
+                        break;
+                    case 'lagged':
+                        console.log(`Stream lagged, skipped ${data.skipped} messages`);
+                        break;
+                }
+            };
+            
+            ws.onclose = function() {
+                if (socket !== ws) {
+                    return;
+                }
+                socket = null;
+                if (!opened) {
+                    // WebSocket unavailable (e.g. blocked by a proxy), fall back to SSE
+                    console.log('WebSocket unavailable, using SSE');
+                    useWebSocket = false;
+                    startEventSource();
+                    return;
+                }
+                setStatus(false, 'Disconnected');
+                setTimeout(() => {
+                    if (isStreaming && socket === null) {
+                        console.log('Attempting to reconnect...');
+                        connectWebSocket();
+                    }
+                }, 5000);
+            };
+        }
+        
+        function startEventSource() {
             if (eventSource) {
                 eventSource.close();
             }
             
-            eventSource = new EventSource('/stream');
+            eventSource = new EventSource('/stream' + queryString(new URLSearchParams()));
             
             eventSource.onopen = function() {
                 console.log('SSE connection opened');
-                document.getElementById('status').className = 'status connected';
-                document.getElementById('statusText').textContent = 'Connected';
+                setStatus(true, 'Connected');
                 isStreaming = true;
                 document.getElementById('streamBtn').textContent = 'Pause Stream';
             };
@@ -379,29 +659,47 @@ pub fn get_web_interface() -> String {
                 addLogEntry(log);
             };
             
+            eventSource.addEventListener('update', function(event) {
+                updateLogEntry(JSON.parse(event.data));
+            });
+            
             eventSource.onerror = function() {
                 console.log('SSE connection error');
-                document.getElementById('status').className = 'status disconnected';
-                document.getElementById('statusText').textContent = 'Disconnected';
+                setStatus(false, 'Disconnected');
                 
                 // Attempt to reconnect after 5 seconds
                 setTimeout(() => {
                     if (isStreaming) {
                         console.log('Attempting to reconnect...');
-                        startStream();
+                        startEventSource();
                     }
                 }, 5000);
             };
         }
         
+        function startStream() {
+            if (socketOpen()) {
+                // Held messages are delivered on resume, nothing is lost
+                sendCommand({ type: 'resume' });
+                isStreaming = true;
+                setStatus(true, 'Connected (WebSocket)');
+                document.getElementById('streamBtn').textContent = 'Pause Stream';
+            } else if (useWebSocket) {
+                connectWebSocket();
+            } else {
+                startEventSource();
+            }
+        }
+        
         function stopStream() {
-            if (eventSource) {
+            if (socketOpen()) {
+                sendCommand({ type: 'pause' });
+            } else if (eventSource) {
                 eventSource.close();
                 eventSource = null;
             }
             isStreaming = false;
-            document.getElementById('status').className = 'status disconnected';
-            document.getElementById('statusText').textContent = 'Paused';
+            setStatus(false, 'Paused');
             document.getElementById('streamBtn').textContent = 'Resume Stream';
         }
         
@@ -419,20 +717,156 @@ pub fn get_web_interface() -> String {
         }
         
         function loadHistoryLogs() {
-            fetch('/logs?limit=50')
+            fetch('/logs' + queryString(new URLSearchParams({ limit: 50 })))
                 .then(response => response.json())
                 .then(data => {
+                    if (!Array.isArray(data)) {
+                        markQuery(data);
+                        return;
+                    }
+                    markQuery(null);
                     clearLogs();
                     data.reverse().forEach(log => addLogEntry(log));
                 })
                 .catch(console.error);
         }
         
+        function applyQuery() {
+            currentQuery = document.getElementById('queryInput').value.trim();
+            if (socketOpen()) {
+                // The history is backfilled once the server accepts the filter
+                const params = currentQuery ? { q: currentQuery } : {};
+                sendCommand({ type: 'filter', params });
+                return;
+            }
+            loadHistoryLogs();
+            if (isStreaming) {
+                startEventSource();
+            }
+        }
+        
+        let contextId = null;
+        
+        // Show the messages before and after one entry from the same source
+        function showContext(id) {
+            contextId = id;
+            const same = document.getElementById('contextSame').value.trim() || 'host';
+            const params = withStream(new URLSearchParams({ before: 20, after: 20, same }));
+            fetch(`/logs/${id}/context?${params}`)
+                .then(response => response.json())
+                .then(data => {
+                    const body = document.getElementById('contextBody');
+                    body.innerHTML = '';
+                    if (data.error) {
+                        body.innerHTML = `<div class="empty-state"><h3>${data.error}</h3><p>The message may have been evicted from the buffer</p></div>`;
+                    } else {
+                        data.before.forEach(log => body.appendChild(createLogEntry(log)));
+                        const anchor = createLogEntry(data.message);
+                        anchor.classList.add('anchor');
+                        body.appendChild(anchor);
+                        data.after.forEach(log => body.appendChild(createLogEntry(log)));
+                        anchor.scrollIntoView({ block: 'center' });
+                    }
+                    document.getElementById('contextOverlay').classList.add('open');
+                })
+                .catch(console.error);
+        }
+        
+        function closeContext() {
+            document.getElementById('contextOverlay').classList.remove('open');
+        }
+        
+        // Download the filtered buffer in the selected format
+        function downloadExport() {
+            const format = document.getElementById('exportFormat').value;
+            window.location.href = '/export' + queryString(new URLSearchParams({ format }));
+        }
+        
+        function loadFields() {
+            fetch('/fields?' + withStream(new URLSearchParams()))
+                .then(response => response.json())
+                .then(data => {
+                    data.forEach(field => {
+                        if (!knownFields.includes(field.name)) {
+                            knownFields.push(field.name);
+                        }
+                    });
+                })
+                .catch(console.error);
+        }
+        
+        function loadStreams() {
+            fetch('/streams?' + withStream(new URLSearchParams()))
+                .then(response => response.json())
+                .then(data => {
+                    const select = document.getElementById('streamSelect');
+                    select.innerHTML = '';
+                    data.forEach(stream => {
+                        const option = document.createElement('option');
+                        option.value = stream.name;
+                        option.textContent = stream.name;
+                        option.title = stream.filter || 'All messages';
+                        select.appendChild(option);
+                    });
+                    select.value = currentStream || 'default';
+                    select.hidden = data.length < 2;
+                })
+                .catch(console.error);
+        }
+        
+        // Reconnect the live view and reload history from the selected stream
+        function switchStream() {
+            const name = document.getElementById('streamSelect').value;
+            currentStream = name === 'default' ? '' : name;
+            
+            if (socket) {
+                const old = socket;
+                socket = null;
+                old.close();
+            }
+            if (eventSource) {
+                eventSource.close();
+                eventSource = null;
+            }
+            if (isStreaming) {
+                startStream();
+            }
+            loadHistoryLogs();
+            updateStats();
+            loadFields();
+        }
+        
+        // Suggest field names for the word currently being typed
+        function updateFieldSuggestions() {
+            const value = document.getElementById('queryInput').value;
+            const wordStart = Math.max(value.lastIndexOf(' '), value.lastIndexOf('(')) + 1;
+            const word = value.slice(wordStart).replace(/^-/, '');
+            const list = document.getElementById('fieldList');
+            list.innerHTML = '';
+            
+            if (!word || word.includes(':')) {
+                return;
+            }
+            
+            knownFields
+                .filter(name => name.startsWith(word))
+                .slice(0, 20)
+                .forEach(name => {
+                    const option = document.createElement('option');
+                    option.value = `${value.slice(0, value.length - word.length)}${name}:`;
+                    list.appendChild(option);
+                });
+        }
+        
         // Initialize
         document.addEventListener('DOMContentLoaded', function() {
             startStream();
             updateStats();
-            setInterval(updateStats, 10000); // Update stats every 10 seconds
+            // Update stats every 10 seconds unless the WebSocket pushes them
+            setInterval(() => { if (!socketOpen()) updateStats(); }, 10000);
+            loadFields();
+            setInterval(loadFields, 60000); // Refresh field suggestions every minute
+            loadStreams();
             
             // Load initial history
             loadHistoryLogs();
@@ -443,6 +877,9 @@ pub fn get_web_interface() -> String {
             if (eventSource) {
                 eventSource.close();
             }
+            if (socket) {
+                socket.close();
+            }
         });
     </script>
 </body>
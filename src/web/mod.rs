@@ -1,6 +1,7 @@
 pub mod handlers;
 pub mod interface;
 pub mod routes;
+pub mod websocket;
 
 pub use handlers::*;
 pub use interface::get_web_interface;
@@ -1,22 +1,155 @@
-use crate::storage::MessageStore;
+use crate::alerting::AlertEngine;
+use crate::config::Config;
+use crate::tenants::TenantRouter;
 use crate::web::handlers::{
-    health_handler, logs_handler, stats_handler, stream_handler, web_interface_handler,
+    aggregate_handler, alerts_handler, authorize, config_handler, delete_logs_handler,
+    export_handler, fields_handler, handle_rejection, health_handler, hosts_handler,
+    log_by_id_handler, log_context_handler, logs_handler, patterns_handler, require_admin,
+    search_handler, select_stream, select_tenant, set_capacity_handler, stats_handler,
+    require_same_origin, stream_handler, streams_handler, tenants_handler, web_interface_handler,
+    ws_handler,
 };
+use std::sync::Arc;
 use warp::Filter;
 
 /// Create all HTTP routes for the application
-pub fn create_routes<S: MessageStore>(
-    store: S,
+pub fn create_routes(
+    tenants: TenantRouter,
+    config: Config,
+    alerts: Arc<AlertEngine>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    let store_filter = warp::any().map(move || store.clone());
+    let tenants_filter = warp::any().map(move || tenants.clone());
+    // What the request's token grants access to; everything while no tenants are configured
+    let access_filter = warp::header::optional::<String>("authorization")
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(tenants_filter.clone())
+        .and_then(authorize);
+    // Admin tokens only, and never from another site's scripts
+    let admin_filter = warp::header::optional::<String>("origin")
+        .and(warp::header::optional::<String>("host"))
+        .and_then(require_same_origin)
+        .untuple_one()
+        .and(access_filter.clone().and_then(require_admin).untuple_one());
+    // Streams of the caller's tenant, or of the tenant an admin names with `tenant`
+    let router_filter = warp::query::<std::collections::HashMap<String, String>>()
+        .and(access_filter)
+        .and(tenants_filter.clone())
+        .and_then(select_tenant);
+    // Store of the stream named by the `stream` parameter, the default stream without one
+    let store_filter = warp::query::<std::collections::HashMap<String, String>>()
+        .and(router_filter.clone())
+        .and_then(select_stream);
+    let config_filter = warp::any().map(move || config.clone());
+    let alerts_filter = warp::any().map(move || alerts.clone());
 
     // GET /logs - retrieve log messages
     let logs_route = warp::path("logs")
+        .and(warp::path::end())
         .and(warp::get())
         .and(warp::query::<std::collections::HashMap<String, String>>())
         .and(store_filter.clone())
         .and_then(logs_handler);
 
+    // GET /logs/{id} - retrieve a single message with its raw payload
+    let log_by_id_route = warp::path!("logs" / u64)
+        .and(warp::get())
+        .and(store_filter.clone())
+        .and_then(log_by_id_handler);
+
+    // GET /logs/{id}/context - neighbouring messages from the same source
+    let log_context_route = warp::path!("logs" / u64 / "context")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and_then(log_context_handler);
+
+    // GET /search - full-text search over stored messages
+    let search_route = warp::path("search")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and_then(search_handler);
+
+    // GET /aggregate - histograms, top values and numeric stats
+    let aggregate_route = warp::path("aggregate")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and_then(aggregate_handler);
+
+    // GET /fields - catalogue of additional fields in the buffer
+    let fields_route = warp::path("fields")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and_then(fields_handler);
+
+    // GET /patterns - message templates mined from short_message
+    let patterns_route = warp::path("patterns")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and_then(patterns_handler);
+
+    // GET /hosts - inventory of hosts that sent messages
+    let hosts_route = warp::path("hosts")
+        .and(warp::get())
+        .and(store_filter.clone())
+        .and_then(hosts_handler);
+
+    // GET /streams - named streams and their sizes
+    let streams_route = warp::path("streams")
+        .and(warp::get())
+        .and(router_filter)
+        .and_then(streams_handler);
+
+    // GET /tenants - per-tenant usage, for admins
+    let tenants_route = warp::path("tenants")
+        .and(warp::get())
+        .and(admin_filter.clone())
+        .and(tenants_filter)
+        .and_then(tenants_handler);
+
+    // GET /export - download the filtered buffer as NDJSON, CSV or raw GELF
+    let export_route = warp::path("export")
+        .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and_then(export_handler);
+
+    // GET /alerts - alert rules and firing history
+    let alerts_route = warp::path("alerts")
+        .and(warp::get())
+        .and(admin_filter.clone())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(alerts_filter)
+        .and_then(alerts_handler);
+
+    // DELETE /logs - remove log messages matching a filter, or all of them
+    let delete_logs_route = warp::path("logs")
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(admin_filter.clone())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .and_then(delete_logs_handler);
+
+    // PUT /admin/capacity - change the maximum number of stored messages
+    let capacity_route = warp::path!("admin" / "capacity")
+        .and(warp::put())
+        .and(admin_filter.clone())
+        .and(warp::body::json())
+        .and(store_filter.clone())
+        .and_then(set_capacity_handler);
+
+    // GET /admin/config - show effective configuration
+    let config_route = warp::path!("admin" / "config")
+        .and(warp::get())
+        .and(admin_filter)
+        .and(config_filter)
+        .and(store_filter.clone())
+        .and_then(config_handler);
+
     // GET /stats - get storage statistics  
     let stats_route = warp::path("stats")
         .and(warp::get())
@@ -36,19 +169,43 @@ pub fn create_routes<S: MessageStore>(
     // GET /stream - Server-Sent Events for real-time log streaming
     let stream_route = warp::path("stream")
         .and(warp::get())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
         .and(store_filter.clone())
         .map(stream_handler);
 
+    // GET /ws - WebSocket streaming with client commands
+    let ws_route = warp::path("ws")
+        .and(warp::ws())
+        .and(warp::query::<std::collections::HashMap<String, String>>())
+        .and(store_filter.clone())
+        .map(ws_handler);
+
     // Combine all routes with CORS
     web_route
         .or(logs_route)
+        .or(log_by_id_route)
+        .or(log_context_route)
+        .or(search_route)
+        .or(aggregate_route)
+        .or(fields_route)
+        .or(patterns_route)
+        .or(hosts_route)
+        .or(streams_route)
+        .or(tenants_route)
+        .or(export_route)
+        .or(alerts_route)
+        .or(delete_logs_route)
+        .or(capacity_route)
+        .or(config_route)
         .or(stats_route)
         .or(health_route)
         .or(stream_route)
+        .or(ws_route)
+        .recover(handle_rejection)
         .with(
             warp::cors()
                 .allow_any_origin()
-                .allow_headers(vec!["content-type"])
-                .allow_methods(vec!["GET"]),
+                .allow_headers(vec!["content-type", "authorization"])
+                .allow_methods(vec!["GET", "PUT", "DELETE"]),
         )
 }
\ No newline at end of file
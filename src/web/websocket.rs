@@ -0,0 +1,163 @@
+use crate::filter::MessageFilter;
+use crate::storage::{MessageStore, StoreEvent};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+use warp::ws::{Message, WebSocket};
+
+/// How often stats are pushed to connected clients
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of messages held back while a client is paused
+const MAX_HELD_MESSAGES: usize = 1000;
+
+/// Commands sent by WebSocket clients
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientCommand {
+    /// Replace the filter, using the same parameters as `GET /logs`
+    Filter {
+        #[serde(default)]
+        params: HashMap<String, String>,
+    },
+    /// Hold back new messages until `resume`
+    Pause,
+    /// Deliver held messages and continue streaming
+    Resume,
+    /// Send the last `limit` stored messages matching the current filter
+    Backfill { limit: usize },
+}
+
+/// State of one WebSocket client
+struct Session<S: MessageStore> {
+    store: S,
+    filter: MessageFilter,
+    paused: bool,
+    held: VecDeque<StoreEvent>,
+    dropped_while_paused: usize,
+}
+
+impl<S: MessageStore> Session<S> {
+    async fn handle_command(&mut self, text: &str) -> Vec<serde_json::Value> {
+        let command = match serde_json::from_str::<ClientCommand>(text) {
+            Ok(command) => command,
+            Err(e) => {
+                return vec![serde_json::json!({ "type": "error", "error": format!("invalid command: {}", e) })];
+            }
+        };
+        debug!("WebSocket command: {:?}", command);
+
+        match command {
+            ClientCommand::Filter { params } => match MessageFilter::from_params(&params) {
+                Ok(filter) => {
+                    self.filter = filter;
+                    vec![serde_json::json!({ "type": "ack", "command": "filter" })]
+                }
+                Err(e) => {
+                    let mut reply = e.to_json();
+                    reply["type"] = "error".into();
+                    reply["command"] = "filter".into();
+                    vec![reply]
+                }
+            },
+            ClientCommand::Pause => {
+                self.paused = true;
+                vec![serde_json::json!({ "type": "ack", "command": "pause" })]
+            }
+            ClientCommand::Resume => {
+                self.paused = false;
+                let mut replies: Vec<_> = self.held.drain(..).map(store_event).collect();
+                replies.push(serde_json::json!({
+                    "type": "ack",
+                    "command": "resume",
+                    "dropped": self.dropped_while_paused
+                }));
+                self.dropped_while_paused = 0;
+                replies
+            }
+            ClientCommand::Backfill { limit } => {
+                let mut messages = self.store.get_messages(self.filter.clone(), Some(limit)).await;
+                messages.reverse();
+                vec![serde_json::json!({ "type": "backfill", "messages": messages })]
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: StoreEvent) -> Vec<serde_json::Value> {
+        if !self.filter.matches(event.message()) {
+            return Vec::new();
+        }
+
+        if !self.paused {
+            return vec![store_event(event)];
+        }
+
+        self.held.push_back(event);
+        if self.held.len() > MAX_HELD_MESSAGES {
+            self.held.pop_front();
+            self.dropped_while_paused += 1;
+        }
+        Vec::new()
+    }
+}
+
+fn store_event(event: StoreEvent) -> serde_json::Value {
+    match event {
+        StoreEvent::Added(message) => serde_json::json!({ "type": "message", "message": message }),
+        // A collapsed duplicate, replacing the entry with the same id
+        StoreEvent::Updated(message) => serde_json::json!({ "type": "update", "message": message }),
+    }
+}
+
+/// Serve one WebSocket client until it disconnects
+pub async fn run_session<S: MessageStore>(socket: WebSocket, filter: MessageFilter, store: S) {
+    debug!("WebSocket client connected");
+
+    let (mut sink, mut incoming) = socket.split();
+    let mut messages = store.subscribe();
+    let mut stats_tick = tokio::time::interval(STATS_INTERVAL);
+    let mut session = Session {
+        store,
+        filter,
+        paused: false,
+        held: VecDeque::new(),
+        dropped_while_paused: 0,
+    };
+
+    loop {
+        let outgoing = tokio::select! {
+            received = incoming.next() => match received {
+                Some(Ok(frame)) if frame.is_close() => break,
+                Some(Ok(frame)) => match frame.to_str() {
+                    Ok(text) => session.handle_command(text).await,
+                    Err(()) => continue, // Ping, pong and binary frames
+                },
+                Some(Err(e)) => {
+                    debug!("WebSocket receive error: {}", e);
+                    break;
+                }
+                None => break,
+            },
+            received = messages.recv() => match received {
+                Ok(event) => session.handle_event(event),
+                Err(RecvError::Lagged(skipped)) => vec![serde_json::json!({ "type": "lagged", "skipped": skipped })],
+                Err(RecvError::Closed) => break,
+            },
+            _ = stats_tick.tick() => {
+                vec![serde_json::json!({ "type": "stats", "stats": session.store.get_stats().await })]
+            }
+        };
+
+        for event in outgoing {
+            if sink.send(Message::text(event.to_string())).await.is_err() {
+                debug!("WebSocket client went away");
+                return;
+            }
+        }
+    }
+
+    debug!("WebSocket client disconnected");
+}